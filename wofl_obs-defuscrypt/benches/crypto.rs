@@ -0,0 +1,58 @@
+// crypto.rs - Where encryption time goes: PBKDF2, raw sealing, and the full
+// theatrics-free path. Run with `cargo bench --features web-api`.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+use wofl_obs_defuscrypt::web_theatre::{bench, DataTheater, EncryptionLevel};
+
+// The production PBKDF2 round count
+const PRODUCTION_ROUNDS: u32 = 600_000;
+// Rounds for benches that measure sealing rather than key derivation
+const CHEAP_ROUNDS: u32 = 1_000;
+const PAYLOAD_SIZES: [usize; 2] = [1024, 1024 * 1024];
+
+fn derive_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("derive_key");
+    // Each iteration takes a good fraction of a second
+    group.sample_size(10);
+    group.bench_function(BenchmarkId::new("pbkdf2_sha256", PRODUCTION_ROUNDS), |b| {
+        b.iter(|| bench::derive_key(PRODUCTION_ROUNDS, black_box("hunter2"), black_box(&[7u8; 32])).unwrap())
+    });
+    group.finish();
+}
+
+fn basic_encrypt(c: &mut Criterion) {
+    let mut theater = DataTheater::new().with_pbkdf2_rounds(CHEAP_ROUNDS).unwrap();
+    let mut group = c.benchmark_group("basic_encrypt");
+    for size in PAYLOAD_SIZES {
+        let data = vec![0xA5u8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            b.iter(|| bench::basic_encrypt(&mut theater, black_box(data), "hunter2").unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn encrypt_with_drama(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("encrypt_with_drama");
+    // Production rounds, so this shows what a real request pays
+    group.sample_size(10);
+    for size in PAYLOAD_SIZES {
+        let data = vec![0xA5u8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("basic", size), &data, |b, data| {
+            b.to_async(&runtime).iter_batched(
+                || DataTheater::new().without_theatrics(),
+                |mut theater| async move {
+                    theater.encrypt_with_drama(1, black_box(data), EncryptionLevel::Basic, None).await.unwrap()
+                },
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, derive_key, basic_encrypt, encrypt_with_drama);
+criterion_main!(benches);
@@ -0,0 +1,121 @@
+// armor.rs - PEM-style text wrapping for ciphertexts that travel by email or JSON
+use crate::web_theatre::{base64_text, TheaterError};
+
+const BEGIN_LINE: &str = "-----BEGIN GONGLE ENCRYPTED-----";
+const END_LINE: &str = "-----END GONGLE ENCRYPTED-----";
+// Base64 characters per body line, as in OpenPGP armor
+const LINE_LENGTH: usize = 64;
+// CRC-24 from RFC 4880, section 6.1
+const CRC24_INIT: u32 = 0xB7_04CE;
+const CRC24_POLY: u32 = 0x186_4CFB;
+
+/// Wrap a ciphertext in BEGIN/END lines around 64-column base64 and a CRC-24 line
+pub fn to_armor(blob: &[u8]) -> String {
+    let encoded = base64_text::encode(blob);
+    let mut armor = String::with_capacity(encoded.len() + encoded.len() / LINE_LENGTH + 80);
+    armor.push_str(BEGIN_LINE);
+    armor.push('\n');
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        // Base64 output is ASCII, so any chunk boundary is a char boundary
+        armor.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        armor.push('\n');
+    }
+    armor.push('=');
+    armor.push_str(&base64_text::encode(&crc24(blob).to_be_bytes()[1..]));
+    armor.push('\n');
+    armor.push_str(END_LINE);
+    armor.push('\n');
+    armor
+}
+
+/// Undo `to_armor`, rejecting text with missing markers or a bad checksum
+pub fn from_armor(armored: &str) -> Result<Vec<u8>, TheaterError> {
+    let mut lines = armored.lines().map(str::trim).skip_while(|line| line.is_empty());
+    if lines.next() != Some(BEGIN_LINE) {
+        return Err(TheaterError::BadArmor("missing BEGIN line"));
+    }
+
+    let mut body = String::new();
+    let mut checksum = None;
+    let mut ended = false;
+    for line in lines.by_ref() {
+        if line == END_LINE {
+            ended = true;
+            break;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        match line.strip_prefix('=') {
+            Some(crc) if checksum.is_none() => checksum = Some(crc.to_string()),
+            Some(_) => return Err(TheaterError::BadArmor("more than one checksum line")),
+            None if checksum.is_some() => return Err(TheaterError::BadArmor("data after the checksum line")),
+            None => body.push_str(line),
+        }
+    }
+    if !ended {
+        return Err(TheaterError::BadArmor("missing END line"));
+    }
+    if lines.any(|line| !line.is_empty()) {
+        return Err(TheaterError::BadArmor("data after the END line"));
+    }
+
+    let checksum = checksum.ok_or(TheaterError::BadArmor("missing checksum line"))?;
+    let blob = base64_text::decode(body).map_err(|_| TheaterError::BadArmor("body is not valid base64"))?;
+    let expected = base64_text::decode(checksum).map_err(|_| TheaterError::BadArmor("checksum is not valid base64"))?;
+    if expected[..] != crc24(&blob).to_be_bytes()[1..] {
+        return Err(TheaterError::BadArmor("checksum mismatch"));
+    }
+    Ok(blob)
+}
+
+/// Whether `text` looks like armor rather than bare base64
+pub fn is_armored(text: &str) -> bool {
+    text.trim_start().starts_with(BEGIN_LINE)
+}
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc = CRC24_INIT;
+    for &byte in data {
+        crc ^= u32::from(byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x100_0000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+    }
+    crc & 0xFF_FFFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn armor_round_trips() {
+        for blob in [&b""[..], b"GNGL", &[0xA5; 200]] {
+            let armored = to_armor(blob);
+            assert!(armored.starts_with("-----BEGIN GONGLE ENCRYPTED-----\n"));
+            assert!(armored.lines().all(|line| line.len() <= LINE_LENGTH || line.starts_with("-----")));
+            assert_eq!(from_armor(&armored).unwrap(), blob);
+        }
+
+        // The RFC 4880 check value for the empty input is the initial register
+        assert_eq!(crc24(b""), CRC24_INIT);
+        assert!(from_armor(&format!("\n  {}", to_armor(b"indented"))).is_ok());
+    }
+
+    #[test]
+    fn tampered_armor_is_rejected() {
+        let armored = to_armor(b"attack at dawn");
+        let flipped = armored.replacen("YXR0", "YXR1", 1);
+        assert_ne!(flipped, armored);
+        assert!(matches!(from_armor(&flipped), Err(TheaterError::BadArmor("checksum mismatch"))));
+
+        let headless = armored.replacen(BEGIN_LINE, "", 1);
+        assert!(matches!(from_armor(&headless), Err(TheaterError::BadArmor("missing BEGIN line"))));
+        let endless = armored.replacen(END_LINE, "", 1);
+        assert!(matches!(from_armor(&endless), Err(TheaterError::BadArmor("missing END line"))));
+    }
+}
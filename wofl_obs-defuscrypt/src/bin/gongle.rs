@@ -0,0 +1,138 @@
+// gongle - the data protection theater from the command line, no server needed
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use std::{io::BufRead, path::PathBuf};
+use wofl_obs_defuscrypt::{
+    theatre_api::funeral_type_from_name,
+    web_theatre::{DataTheater, EncryptionLevel},
+};
+use zeroize::Zeroizing;
+
+// Where the password comes from when --password-stdin isn't given
+const PASSWORD_ENV: &str = "GONGLE_PASSWORD";
+
+#[derive(Parser)]
+#[command(name = "gongle", version, about = "Gongle's data protection theater, offline")]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+
+    /// Skip the dramatic pauses
+    #[arg(long, global = true)]
+    no_drama: bool,
+
+    /// PBKDF2 rounds for new file keys; decryption reads them from the file
+    #[arg(long, global = true, value_name = "ROUNDS")]
+    pbkdf2_rounds: Option<u32>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Encrypt a file
+    Encrypt {
+        /// basic, premium, paranoid, tinfoil, quantum, alien or eldritch
+        #[arg(short, long, default_value = "basic")]
+        level: EncryptionLevel,
+
+        #[arg(long = "in", value_name = "FILE")]
+        input: PathBuf,
+
+        #[arg(long = "out", value_name = "FILE")]
+        output: PathBuf,
+
+        /// Read the password from the first line of stdin instead of $GONGLE_PASSWORD
+        #[arg(long)]
+        password_stdin: bool,
+    },
+
+    /// Decrypt a file written by `gongle encrypt`
+    Decrypt {
+        #[arg(long = "in", value_name = "FILE")]
+        input: PathBuf,
+
+        #[arg(long = "out", value_name = "FILE")]
+        output: PathBuf,
+
+        /// Read the password from the first line of stdin instead of $GONGLE_PASSWORD
+        #[arg(long)]
+        password_stdin: bool,
+    },
+
+    /// Plan a funeral for some data and print the schedule as JSON
+    Funeral {
+        #[arg(
+            short = 't',
+            long = "type",
+            default_value = "viking",
+            value_parser = ["viking", "space", "quantum", "eldritch"]
+        )]
+        funeral_type: String,
+
+        #[arg(short, long, default_value_t = 0)]
+        user_id: u64,
+
+        /// Ids of the data being laid to rest
+        #[arg(required = true)]
+        data_ids: Vec<String>,
+    },
+
+    /// Print what encrypting at a level would cost and how long it would take
+    Estimate {
+        level: EncryptionLevel,
+    },
+}
+
+/// The password from stdin or the environment; never from an argument, where
+/// other users could see it in the process list
+fn read_password(from_stdin: bool) -> Result<Zeroizing<String>> {
+    let password = if from_stdin {
+        let mut line = Zeroizing::new(String::new());
+        std::io::stdin().lock().read_line(&mut line).context("Failed to read password from stdin")?;
+        Zeroizing::new(line.trim_end_matches(['\r', '\n']).to_string())
+    } else {
+        Zeroizing::new(std::env::var(PASSWORD_ENV).with_context(|| {
+            format!("No password given: set {} or pass --password-stdin", PASSWORD_ENV)
+        })?)
+    };
+    if password.is_empty() {
+        bail!("The password is empty");
+    }
+    Ok(password)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let mut theater = DataTheater::new();
+    if let Some(rounds) = cli.pbkdf2_rounds {
+        theater = theater.with_pbkdf2_rounds(rounds)?;
+    }
+    if cli.no_drama {
+        theater = theater.without_theatrics();
+    }
+
+    match cli.command {
+        Commands::Encrypt { level, input, output, password_stdin } => {
+            let password = read_password(password_stdin)?;
+            theater.encrypt_file(&input, &output, level.clone(), &password).await?;
+            eprintln!("Encrypted {} -> {} at {} level", input.display(), output.display(), level);
+        },
+        Commands::Decrypt { input, output, password_stdin } => {
+            let password = read_password(password_stdin)?;
+            let level = theater.decrypt_file(&input, &output, &password).await?;
+            eprintln!("Decrypted {} -> {} ({} level)", input.display(), output.display(), level);
+        },
+        Commands::Funeral { funeral_type, user_id, data_ids } => {
+            let schedule = theater
+                .schedule_funeral(user_id, data_ids, funeral_type_from_name(&funeral_type), None)
+                .await?;
+            println!("{}", serde_json::to_string_pretty(&schedule)?);
+        },
+        Commands::Estimate { level } => {
+            println!("{}", serde_json::to_string_pretty(&theater.estimate(&level))?);
+        },
+    }
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+// theater_api - HTTP server exposing the data protection theater to Gongle
+use wofl_obs_defuscrypt::theatre_api;
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    // RUST_LOG picks what gets logged, e.g. RUST_LOG=wofl_obs_defuscrypt=debug
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    let config = theatre_api::ServerConfig::from_env()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{:#}", e)))?;
+
+    theatre_api::run(config).await
+}
@@ -0,0 +1,76 @@
+// cipher.rs - AEADs the theater can seal ciphertexts with
+use crate::web_theatre::{AuthFailed, TheaterError};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+
+// Cipher ids recorded in the ciphertext header
+pub const CHACHA20_POLY1305_ID: u8 = 0;
+pub const AES_256_GCM_ID: u8 = 1;
+
+/// An AEAD taking a 256-bit key and a 96-bit nonce
+pub trait TheaterCipher: Send + Sync {
+    /// Byte identifying this cipher in the ciphertext header
+    fn id(&self) -> u8;
+
+    fn encrypt(&self, key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, TheaterError>;
+
+    fn decrypt(&self, key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, TheaterError>;
+}
+
+/// ChaCha20-Poly1305, the default and the only cipher of format version 1
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChaCha20Cipher;
+
+impl TheaterCipher for ChaCha20Cipher {
+    fn id(&self) -> u8 {
+        CHACHA20_POLY1305_ID
+    }
+
+    fn encrypt(&self, key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, TheaterError> {
+        ChaCha20Poly1305::new(Key::from_slice(key))
+            .encrypt(Nonce::from_slice(nonce), Payload { msg: plaintext, aad })
+            .map_err(|_| TheaterError::Encrypt)
+    }
+
+    fn decrypt(&self, key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, TheaterError> {
+        ChaCha20Poly1305::new(Key::from_slice(key))
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| TheaterError::Decrypt(AuthFailed))
+    }
+}
+
+/// AES-256-GCM, for deployments that need FIPS algorithms or AES-NI speed
+#[cfg(feature = "aes")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Aes256GcmCipher;
+
+#[cfg(feature = "aes")]
+impl TheaterCipher for Aes256GcmCipher {
+    fn id(&self) -> u8 {
+        AES_256_GCM_ID
+    }
+
+    fn encrypt(&self, key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, TheaterError> {
+        aes_gcm::Aes256Gcm::new(aes_gcm::Key::<aes_gcm::Aes256Gcm>::from_slice(key))
+            .encrypt(aes_gcm::Nonce::from_slice(nonce), Payload { msg: plaintext, aad })
+            .map_err(|_| TheaterError::Encrypt)
+    }
+
+    fn decrypt(&self, key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, TheaterError> {
+        aes_gcm::Aes256Gcm::new(aes_gcm::Key::<aes_gcm::Aes256Gcm>::from_slice(key))
+            .decrypt(aes_gcm::Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| TheaterError::Decrypt(AuthFailed))
+    }
+}
+
+/// The cipher a ciphertext header names, if this build supports it
+pub fn cipher_for(id: u8) -> Result<Box<dyn TheaterCipher>, TheaterError> {
+    match id {
+        CHACHA20_POLY1305_ID => Ok(Box::new(ChaCha20Cipher)),
+        #[cfg(feature = "aes")]
+        AES_256_GCM_ID => Ok(Box::new(Aes256GcmCipher)),
+        _ => Err(TheaterError::UnsupportedCipher(id)),
+    }
+}
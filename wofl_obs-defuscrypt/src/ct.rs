@@ -0,0 +1,27 @@
+// ct.rs - Constant-time comparison for secrets
+//
+// `==` on byte slices stops at the first differing byte, so how long a
+// comparison takes says how much of a guess was right. Auth tokens and
+// signature bytes go through `ct_eq` instead.
+use subtle::ConstantTimeEq;
+
+/// Whether `a` and `b` hold the same bytes, taking the same time wherever they
+/// differ. Only the lengths are compared in variable time.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ct_eq_matches_plain_equality() {
+        assert!(ct_eq(b"hunter2", b"hunter2"));
+        assert!(ct_eq(b"", b""));
+        assert!(!ct_eq(b"hunter2", b"hunter3"));
+        assert!(!ct_eq(b"hunter2", b"Hunter2"));
+        assert!(!ct_eq(b"hunter2", b"hunter22"));
+        assert!(!ct_eq(b"hunter2", b""));
+    }
+}
@@ -0,0 +1,21 @@
+//! Library half of wofl_obs-defuscrypt: the Gongle data protection theater,
+//! the HTTP API that fronts it, and the file shredder behind its funerals.
+
+#[cfg(feature = "web-api")]
+pub mod armor;
+#[cfg(feature = "web-api")]
+pub mod cipher;
+pub mod ct;
+#[cfg(feature = "web-api")]
+pub mod metrics;
+#[cfg(feature = "web-api")]
+pub mod nonce;
+pub mod shred;
+#[cfg(feature = "web-api")]
+pub mod session;
+#[cfg(feature = "web-api")]
+pub mod theater_config;
+#[cfg(feature = "web-api")]
+pub mod theatre_api;
+#[cfg(feature = "web-api")]
+pub mod web_theatre;
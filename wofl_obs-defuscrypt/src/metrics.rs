@@ -0,0 +1,102 @@
+// metrics.rs - Prometheus counters for encryption volume and the points economy
+use crate::web_theatre::{EncryptionLevel, EncryptionResult, FuneralType};
+use prometheus::{
+    exponential_buckets, Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+
+/// Everything the theater API counts, in its own registry so each server
+/// (and each test) starts from zero
+pub struct TheaterMetrics {
+    registry: Registry,
+    encryptions: IntCounterVec,
+    points_spent: IntCounter,
+    points_awarded: IntCounter,
+    funerals_scheduled: IntCounterVec,
+    crypto_seconds: Histogram,
+}
+
+impl TheaterMetrics {
+    pub fn new() -> Self {
+        let encryptions = IntCounterVec::new(
+            Opts::new("gongle_encryptions_total", "Successful encryptions by level"),
+            &["level"],
+        )
+        .unwrap();
+        let points_spent = IntCounter::new(
+            "gongle_points_spent_total",
+            "Points charged for encryptions and loot boxes",
+        )
+        .unwrap();
+        let points_awarded = IntCounter::new(
+            "gongle_points_awarded_total",
+            "Points awarded for encryptions, loot boxes and races",
+        )
+        .unwrap();
+        let funerals_scheduled = IntCounterVec::new(
+            Opts::new("gongle_funerals_scheduled_total", "Funerals scheduled by type"),
+            &["type"],
+        )
+        .unwrap();
+        // 1ms up to about 8s, which covers every PBKDF2 setting in use
+        let crypto_seconds = Histogram::with_opts(
+            HistogramOpts::new("gongle_crypto_seconds", "Time spent on real cryptography")
+                .buckets(exponential_buckets(0.001, 2.0, 14).unwrap()),
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(encryptions.clone())).unwrap();
+        registry.register(Box::new(points_spent.clone())).unwrap();
+        registry.register(Box::new(points_awarded.clone())).unwrap();
+        registry.register(Box::new(funerals_scheduled.clone())).unwrap();
+        registry.register(Box::new(crypto_seconds.clone())).unwrap();
+
+        Self {
+            registry,
+            encryptions,
+            points_spent,
+            points_awarded,
+            funerals_scheduled,
+            crypto_seconds,
+        }
+    }
+
+    /// Count a successful encryption along with the points it moved, if it
+    /// was charged at all
+    pub fn record_encryption(&self, level: &EncryptionLevel, result: &EncryptionResult) {
+        self.encryptions.with_label_values(&[level.as_str()]).inc();
+        if result.charged {
+            self.points_spent.inc_by(u64::from(result.cost));
+            self.points_awarded.inc_by(u64::from(result.points_earned));
+        }
+        self.crypto_seconds.observe(result.real_crypto_time_ms as f64 / 1000.0);
+    }
+
+    /// Count points charged outside of encryption
+    pub fn record_points_spent(&self, points: u32) {
+        self.points_spent.inc_by(u64::from(points));
+    }
+
+    /// Count points handed out outside of encryption
+    pub fn record_points_awarded(&self, points: u32) {
+        self.points_awarded.inc_by(u64::from(points));
+    }
+
+    pub fn record_funeral(&self, funeral_type: &FuneralType) {
+        self.funerals_scheduled.with_label_values(&[funeral_type.kind()]).inc();
+    }
+
+    /// Everything in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .expect("metrics encode as text")
+    }
+}
+
+impl Default for TheaterMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
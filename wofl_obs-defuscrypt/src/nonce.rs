@@ -0,0 +1,80 @@
+// nonce.rs - Keeping AEAD nonces unique under each key
+//
+// Nonces are random by default. That needs no state, but 96 random bits hit
+// the birthday bound: after about 2^32 messages under one key a repeat becomes
+// likely, and a repeat under ChaCha20-Poly1305 or AES-GCM leaks the XOR of two
+// plaintexts and lets the authenticator be forged. Most theater ciphertexts get
+// a fresh salt and so a fresh key, which keeps this far away; batches share one
+// key, which brings it closer.
+//
+// Counter nonces can't repeat while the counter survives, but the counter is
+// state: it lives in memory, so two processes encrypting under the same key,
+// or one restarted mid-batch, would count from zero again. A `NonceStore`
+// catches a repeat either way, at the cost of remembering every nonce it has
+// seen.
+use crate::web_theatre::TheaterError;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashSet;
+
+// Length of the fingerprint a store files nonces under
+pub const KEY_ID_LENGTH: usize = 16;
+// Length of an AEAD nonce in bytes
+const NONCE_LENGTH: usize = 12;
+
+/// Fingerprint of an encryption key; safe to store, unlike the key
+pub type KeyId = [u8; KEY_ID_LENGTH];
+
+/// Fingerprint `key` for use as a store key, without revealing it
+pub fn key_id(key: &[u8; 32]) -> KeyId {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(b"gongle nonce key id");
+    let mut id = [0u8; KEY_ID_LENGTH];
+    id.copy_from_slice(&mac.finalize().into_bytes()[..KEY_ID_LENGTH]);
+    id
+}
+
+/// How the theater picks the nonce for a new ciphertext
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonceStrategy {
+    /// 96 random bits per message
+    #[default]
+    Random,
+    /// A per-key message counter, little-endian in the first 8 bytes
+    Counter,
+}
+
+/// The nonce for message number `counter` under a key
+pub fn counter_nonce(counter: u64) -> [u8; NONCE_LENGTH] {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    nonce[..8].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+/// Remembers which nonces have been used under which key
+pub trait NonceStore: Send + Sync {
+    /// Record `nonce` as used under `key_id`, failing if it already was
+    fn record(&mut self, key_id: &KeyId, nonce: &[u8; NONCE_LENGTH]) -> Result<(), TheaterError>;
+}
+
+/// A `NonceStore` that lives and dies with the process
+#[derive(Debug, Default)]
+pub struct MemoryNonceStore {
+    seen: HashSet<(KeyId, [u8; NONCE_LENGTH])>,
+}
+
+impl MemoryNonceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl NonceStore for MemoryNonceStore {
+    fn record(&mut self, key_id: &KeyId, nonce: &[u8; NONCE_LENGTH]) -> Result<(), TheaterError> {
+        if self.seen.insert((*key_id, *nonce)) {
+            Ok(())
+        } else {
+            Err(TheaterError::NonceReuse)
+        }
+    }
+}
@@ -0,0 +1,83 @@
+// session.rs - Signed bearer tokens naming the user a request acts for
+//
+// A token is `<user_id>.<expires_at>.<tag>`: the user, a unix expiry in seconds
+// and the hex HMAC-SHA256 of the first two fields under the session secret. The
+// Flask front end mints them with the same secret once its own login succeeds,
+// so the API no longer has to take the `user_id` in a request body on trust.
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+use crate::ct::ct_eq;
+use crate::web_theatre::TheaterError;
+
+/// Secret that signs and checks session tokens
+pub struct SessionKey(Zeroizing<Vec<u8>>);
+
+/// The user a verified session token names, left in the request extensions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionUser(pub u64);
+
+impl SessionKey {
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self(Zeroizing::new(secret.as_ref().to_vec()))
+    }
+
+    /// A token for `user_id` that stops verifying at `expires_at`
+    pub fn issue(&self, user_id: u64, expires_at: SystemTime) -> String {
+        let expires_at = expires_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let claims = format!("{}.{}", user_id, expires_at);
+        let tag = hex::encode(self.tag(&claims));
+        format!("{}.{}", claims, tag)
+    }
+
+    /// The user `token` was issued for, if it is signed with this key and
+    /// hasn't expired by `now`
+    pub fn verify(&self, token: &str, now: SystemTime) -> Result<u64, TheaterError> {
+        let (claims, tag) = token.rsplit_once('.').ok_or(TheaterError::InvalidSession("malformed token"))?;
+        let tag = hex::decode(tag).map_err(|_| TheaterError::InvalidSession("malformed token"))?;
+        if !ct_eq(&tag, &self.tag(claims)) {
+            return Err(TheaterError::InvalidSession("bad signature"));
+        }
+
+        let (user_id, expires_at) = claims
+            .split_once('.')
+            .and_then(|(user_id, expires_at)| Some((user_id.parse().ok()?, expires_at.parse().ok()?)))
+            .ok_or(TheaterError::InvalidSession("malformed token"))?;
+        if now >= UNIX_EPOCH + Duration::from_secs(expires_at) {
+            return Err(TheaterError::InvalidSession("expired"));
+        }
+        Ok(user_id)
+    }
+
+    fn tag(&self, claims: &str) -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.0).expect("HMAC accepts any key length");
+        mac.update(claims.as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_verify_until_they_expire() {
+        let key = SessionKey::new("sekrit");
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let token = key.issue(7, now + Duration::from_secs(60));
+
+        assert_eq!(key.verify(&token, now).unwrap(), 7);
+        let expired = key.verify(&token, now + Duration::from_secs(60));
+        assert!(matches!(expired, Err(TheaterError::InvalidSession("expired"))));
+
+        let forged = token.replacen("7.", "8.", 1);
+        assert!(matches!(key.verify(&forged, now), Err(TheaterError::InvalidSession("bad signature"))));
+        let other_key = SessionKey::new("not sekrit");
+        assert!(matches!(other_key.verify(&token, now), Err(TheaterError::InvalidSession("bad signature"))));
+        for junk in ["", "7", "7.123", "7.123.zz"] {
+            assert!(matches!(key.verify(junk, now), Err(TheaterError::InvalidSession(_))), "{:?}", junk);
+        }
+    }
+}
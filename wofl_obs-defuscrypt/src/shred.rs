@@ -0,0 +1,310 @@
+// shred.rs - Multi-pass file shredding that actually overwrites the bytes
+use anyhow::{Context, Result};
+use rand::{rngs::OsRng, RngCore};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+// Bytes written per chunk of a pass; a multiple of 3 so Gutmann's
+// three-byte patterns line up across chunks
+const BUFFER_SIZE: usize = 8190;
+
+// Gutmann passes 5 to 31; the first and last four passes are random
+const GUTMANN_PATTERNS: [[u8; 3]; 27] = [
+    [0x55, 0x55, 0x55],
+    [0xAA, 0xAA, 0xAA],
+    [0x92, 0x49, 0x24],
+    [0x49, 0x24, 0x92],
+    [0x24, 0x92, 0x49],
+    [0x00, 0x00, 0x00],
+    [0x11, 0x11, 0x11],
+    [0x22, 0x22, 0x22],
+    [0x33, 0x33, 0x33],
+    [0x44, 0x44, 0x44],
+    [0x55, 0x55, 0x55],
+    [0x66, 0x66, 0x66],
+    [0x77, 0x77, 0x77],
+    [0x88, 0x88, 0x88],
+    [0x99, 0x99, 0x99],
+    [0xAA, 0xAA, 0xAA],
+    [0xBB, 0xBB, 0xBB],
+    [0xCC, 0xCC, 0xCC],
+    [0xDD, 0xDD, 0xDD],
+    [0xEE, 0xEE, 0xEE],
+    [0xFF, 0xFF, 0xFF],
+    [0x92, 0x49, 0x24],
+    [0x49, 0x24, 0x92],
+    [0x24, 0x92, 0x49],
+    [0x6D, 0xB6, 0xDB],
+    [0xB6, 0xDB, 0x6D],
+    [0xDB, 0x6D, 0xB6],
+];
+// Passes in a full Gutmann shred
+const GUTMANN_PASSES: u32 = 35;
+
+/// Which bytes each shred pass writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShredPattern {
+    /// Cycle through zeros, ones and random data, one per pass
+    Alternating,
+    /// Zeros on every pass
+    Zeros,
+    /// Fresh random data on every pass
+    Random,
+    /// DoD 5220.22-M: 3 passes (0x00, 0xFF, random) or the 7-pass ECE variant,
+    /// which adds a random pass and then repeats the three
+    Dod522022M,
+    /// Peter Gutmann's 35 passes: 4 random, 27 fixed patterns, 4 random
+    Gutmann,
+}
+
+/// Pattern and pass count for a named shredding tier from the frontend
+pub fn shred_tier(name: &str) -> Option<(ShredPattern, u32)> {
+    match name {
+        "standard" => Some((ShredPattern::Dod522022M, 3)),
+        "military" => Some((ShredPattern::Dod522022M, 7)),
+        "nuclear" => Some((ShredPattern::Gutmann, GUTMANN_PASSES)),
+        "blackhole" => Some((ShredPattern::Random, 999)),
+        _ => None,
+    }
+}
+
+/// What a single pass fills the target with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fill {
+    /// A repeating three-byte pattern
+    Pattern([u8; 3]),
+    Random,
+}
+
+const ZEROS: Fill = Fill::Pattern([0x00; 3]);
+const ONES: Fill = Fill::Pattern([0xFF; 3]);
+
+impl ShredPattern {
+    /// Reject pass counts the pattern isn't defined for
+    fn check_passes(&self, passes: u32) -> io::Result<()> {
+        let valid = match self {
+            ShredPattern::Dod522022M => passes == 3 || passes == 7,
+            ShredPattern::Gutmann => passes == GUTMANN_PASSES,
+            _ => true,
+        };
+        if valid {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} shredding can't run {} passes", self, passes),
+        ))
+    }
+
+    /// Fill used for the zero-based `pass`
+    fn fill(&self, pass: u32) -> Fill {
+        match self {
+            ShredPattern::Alternating => match pass % 3 {
+                0 => ZEROS,
+                1 => ONES,
+                _ => Fill::Random,
+            },
+            ShredPattern::Zeros => ZEROS,
+            ShredPattern::Random => Fill::Random,
+            // Pass 3 of the ECE variant is the extra random one
+            ShredPattern::Dod522022M => match pass {
+                0 | 4 => ZEROS,
+                1 | 5 => ONES,
+                _ => Fill::Random,
+            },
+            ShredPattern::Gutmann => match pass {
+                4..=30 => Fill::Pattern(GUTMANN_PATTERNS[pass as usize - 4]),
+                _ => Fill::Random,
+            },
+        }
+    }
+}
+
+/// Outcome of a completed shred
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShredReport {
+    pub passes: u32,
+    pub bytes_overwritten: u64,
+}
+
+/// Something a shred can overwrite and flush to stable storage between passes
+pub trait ShredTarget: Write + Seek {
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+impl ShredTarget for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+/// Overwrite the first `len` bytes of `target` once per pass, syncing after each
+pub fn overwrite<T: ShredTarget, R: RngCore + ?Sized>(
+    target: &mut T,
+    len: u64,
+    passes: u32,
+    pattern: ShredPattern,
+    rng: &mut R,
+) -> io::Result<ShredReport> {
+    pattern.check_passes(passes)?;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+
+    for pass in 0..passes {
+        let fill = pattern.fill(pass);
+        if let Fill::Pattern(bytes) = fill {
+            for (slot, byte) in buffer.iter_mut().zip(bytes.iter().cycle()) {
+                *slot = *byte;
+            }
+        }
+
+        target.seek(SeekFrom::Start(0))?;
+        let mut written = 0;
+        while written < len {
+            let chunk = (len - written).min(BUFFER_SIZE as u64) as usize;
+            if fill == Fill::Random {
+                rng.fill_bytes(&mut buffer[..chunk]);
+            }
+            target.write_all(&buffer[..chunk])?;
+            written += chunk as u64;
+        }
+
+        target.flush()?;
+        target.sync()?;
+    }
+
+    Ok(ShredReport {
+        passes,
+        bytes_overwritten: len * u64::from(passes),
+    })
+}
+
+/// Overwrite a file `passes` times, then truncate and remove it
+pub fn shred_file(path: &Path, passes: u32, pattern: ShredPattern) -> Result<ShredReport> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {} for shredding", path.display()))?;
+    let len = file.metadata().context("Failed to read file size")?.len();
+
+    let report = overwrite(&mut file, len, passes, pattern, &mut OsRng)
+        .with_context(|| format!("Failed while overwriting {}", path.display()))?;
+
+    file.set_len(0).context("Failed to truncate shredded file")?;
+    file.sync_all().context("Failed to sync truncated file")?;
+    drop(file);
+    fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// In-memory target remembering what it held at every sync
+    struct RecordingTarget {
+        data: Cursor<Vec<u8>>,
+        synced: Vec<Vec<u8>>,
+    }
+
+    impl Write for RecordingTarget {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.data.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for RecordingTarget {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+
+    impl ShredTarget for RecordingTarget {
+        fn sync(&mut self) -> io::Result<()> {
+            self.synced.push(self.data.get_ref().clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn every_pass_is_written_and_synced() {
+        let original = vec![0x5A; 20_000];
+        let mut target = RecordingTarget {
+            data: Cursor::new(original.clone()),
+            synced: Vec::new(),
+        };
+
+        let report = overwrite(&mut target, 20_000, 3, ShredPattern::Alternating, &mut OsRng).unwrap();
+
+        assert_eq!(report, ShredReport { passes: 3, bytes_overwritten: 60_000 });
+        assert_eq!(target.synced.len(), 3);
+        assert!(target.synced[0].iter().all(|&b| b == 0x00));
+        assert!(target.synced[1].iter().all(|&b| b == 0xFF));
+        assert_ne!(target.synced[2], original);
+        assert!(target.synced.iter().all(|pass| pass.len() == original.len()));
+    }
+
+    fn record(pattern: ShredPattern, passes: u32) -> Vec<Vec<u8>> {
+        let mut target = RecordingTarget {
+            data: Cursor::new(vec![0x5A; 9000]),
+            synced: Vec::new(),
+        };
+        let report = overwrite(&mut target, 9000, passes, pattern, &mut OsRng).unwrap();
+        assert_eq!(report.passes, passes);
+        target.synced
+    }
+
+    fn filled_with(pass: &[u8], bytes: [u8; 3]) -> bool {
+        pass.iter().zip(bytes.iter().cycle()).all(|(a, b)| a == b)
+    }
+
+    #[test]
+    fn dod_passes_write_the_documented_fills() {
+        let three = record(ShredPattern::Dod522022M, 3);
+        assert_eq!(three.len(), 3);
+        assert!(filled_with(&three[0], [0x00; 3]));
+        assert!(filled_with(&three[1], [0xFF; 3]));
+        assert!(!filled_with(&three[2], [0x00; 3]) && !filled_with(&three[2], [0xFF; 3]));
+
+        let seven = record(ShredPattern::Dod522022M, 7);
+        assert_eq!(seven.len(), 7);
+        for (pass, fill) in [(0, 0x00), (1, 0xFF), (4, 0x00), (5, 0xFF)] {
+            assert!(filled_with(&seven[pass], [fill; 3]), "pass {}", pass + 1);
+        }
+
+        let mut target = RecordingTarget { data: Cursor::new(Vec::new()), synced: Vec::new() };
+        assert!(overwrite(&mut target, 0, 5, ShredPattern::Dod522022M, &mut OsRng).is_err());
+        assert!(target.synced.is_empty());
+    }
+
+    #[test]
+    fn gutmann_runs_35_passes() {
+        let passes = record(ShredPattern::Gutmann, 35);
+        assert_eq!(passes.len(), 35);
+        assert!(filled_with(&passes[4], [0x55; 3]));
+        assert!(filled_with(&passes[6], [0x92, 0x49, 0x24]));
+        assert!(filled_with(&passes[30], [0xDB, 0x6D, 0xB6]));
+        assert_eq!(shred_tier("nuclear"), Some((ShredPattern::Gutmann, 35)));
+        assert_eq!(shred_tier("military"), Some((ShredPattern::Dod522022M, 7)));
+    }
+
+    #[test]
+    fn shredded_file_is_gone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.txt");
+        fs::write(&path, b"the launch codes are 0000").unwrap();
+
+        let report = shred_file(&path, 3, ShredPattern::Alternating).unwrap();
+
+        assert_eq!(report, ShredReport { passes: 3, bytes_overwritten: 75 });
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,132 @@
+// theater_config.rs - Level prices, delays and flavor text, tunable without a rebuild
+use crate::web_theatre::EncryptionLevel;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+/// Economy and flavor of one encryption level
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelConfig {
+    /// Points charged to encrypt at this level
+    pub cost: u32,
+    /// Points awarded for encrypting at this level
+    pub points: u32,
+    /// Dramatic pause before the drama factor is applied
+    pub base_delay_ms: u64,
+    /// Flavor text every encryption at this level reports
+    pub elements: Vec<String>,
+}
+
+/// Per-level settings, keyed by the lowercase level name:
+///
+/// ```toml
+/// [levels.basic]
+/// cost = 100
+/// points = 100
+/// base_delay_ms = 100
+/// elements = ["Applied ROT13 (just kidding)"]
+/// ```
+///
+/// Every level needs an entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TheaterConfig {
+    pub levels: BTreeMap<String, LevelConfig>,
+}
+
+impl TheaterConfig {
+    /// Parse and validate a TOML config
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let config: TheaterConfig = toml::from_str(text).context("Invalid theater config")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Read a TOML config, or the built-in defaults if there's no file at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::from_toml(&text).with_context(|| format!("In {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Check there's exactly one entry per level
+    pub fn validate(&self) -> Result<()> {
+        for name in self.levels.keys() {
+            if let Err(e) = name.parse::<EncryptionLevel>() {
+                bail!(e);
+            }
+        }
+        let missing: Vec<&str> = EncryptionLevel::ALL
+            .iter()
+            .map(|level| level.as_str())
+            .filter(|name| !self.levels.contains_key(*name))
+            .collect();
+        if !missing.is_empty() {
+            bail!("Theater config has no entry for: {}", missing.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Settings for `level`; present for every level once validated
+    pub fn level(&self, level: &EncryptionLevel) -> &LevelConfig {
+        &self.levels[level.as_str()]
+    }
+}
+
+impl Default for TheaterConfig {
+    /// The prices and flavor text built into `EncryptionLevel`
+    fn default() -> Self {
+        let levels = EncryptionLevel::ALL
+            .iter()
+            .zip(EncryptionLevel::ECONOMY)
+            .map(|(level, (cost, points))| {
+                let config = LevelConfig {
+                    cost,
+                    points,
+                    base_delay_ms: level.base_delay_ms(),
+                    elements: level.flavor_text().iter().map(|text| text.to_string()).collect(),
+                };
+                (level.as_str().to_string(), config)
+            })
+            .collect();
+        Self { levels }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_config_file_overrides_the_defaults() {
+        let mut text = toml::to_string(&TheaterConfig::default()).unwrap();
+        text = text.replace("cost = 66666", "cost = 99999");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("theater.toml");
+        fs::write(&path, &text).unwrap();
+
+        let config = TheaterConfig::load(&path).unwrap();
+        assert_eq!(config.level(&EncryptionLevel::Eldritch).cost, 99999);
+        assert_eq!(config.level(&EncryptionLevel::Basic), TheaterConfig::default().level(&EncryptionLevel::Basic));
+
+        assert_eq!(TheaterConfig::load(&dir.path().join("absent.toml")).unwrap(), TheaterConfig::default());
+    }
+
+    #[test]
+    fn every_level_needs_an_entry() {
+        let mut config = TheaterConfig::default();
+        config.levels.remove("quantum");
+        let text = toml::to_string(&config).unwrap();
+        let err = TheaterConfig::from_toml(&text).unwrap_err();
+        assert!(err.to_string().contains("quantum"), "{}", err);
+
+        let mut config = TheaterConfig::default();
+        let basic = config.levels["basic"].clone();
+        config.levels.insert("ludicrous".to_string(), basic);
+        let text = toml::to_string(&config).unwrap();
+        assert!(TheaterConfig::from_toml(&text).is_err());
+    }
+}
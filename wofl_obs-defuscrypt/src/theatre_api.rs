@@ -0,0 +1,2691 @@
+// theater_api.rs - REST API wrapper for web_theater module
+// This creates a small HTTP server that Python can call instead of using subprocess
+
+use actix_cors::Cors;
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{HttpServiceFactory, ServiceRequest, ServiceResponse},
+    error::{InternalError, JsonPayloadError},
+    http::{header, StatusCode},
+    middleware::{from_fn, Next},
+    web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Result,
+};
+use rand::{rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::armor::{from_armor, is_armored, to_armor};
+use crate::ct::ct_eq;
+use crate::metrics::TheaterMetrics;
+use crate::session::{SessionKey, SessionUser};
+use crate::theater_config::TheaterConfig;
+// Import from your web_theater module
+use crate::web_theatre::{
+    base64_text, check_race, describe_funeral, economy_table, encryption_race, error_code, group_collection,
+    Clock, DataTheater, EncryptionLevel, EncryptionResult, FuneralScheduler, FuneralType, RaceInProgress,
+    RaceParticipant, RaceResults, SystemClock, TheaterError, DEFAULT_MAX_RACE_BYTES, LOOT_BOX_COST,
+};
+
+// Where the server looks for level prices and flavor text unless THEATER_CONFIG says otherwise
+const CONFIG_PATH: &str = "theater.toml";
+// Where the server keeps the key it signs certificates with
+const SIGNING_KEY_PATH: &str = "theater_signing.key";
+// How long /readyz waits for the theater before calling the service busy
+const READY_LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+// Interface and port the server listens on unless told otherwise
+const DEFAULT_BIND_ADDR: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 8080;
+// How long browsers may cache a CORS preflight answer, in seconds
+const CORS_MAX_AGE: usize = 3600;
+// Leaderboard entries returned when the request doesn't ask for a number
+const DEFAULT_LEADERBOARD_LIMIT: usize = 10;
+// Largest request body accepted unless told otherwise
+const DEFAULT_MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+// How long shutdown waits for in-flight requests unless told otherwise; long
+// enough for an Eldritch encryption's dramatic pause
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+// How long a retried encrypt with the same idempotency key gets the original result
+const IDEMPOTENCY_TTL: Duration = Duration::from_secs(10 * 60);
+// Longest idempotency key accepted, so keys can't be used to pad memory
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+// Race stream events buffered for a client that's slow to read them
+const RACE_STREAM_BUFFER: usize = 16;
+// How long finished races can be looked up, and how many are kept at most
+const RACE_TTL: Duration = Duration::from_secs(60 * 60);
+const MAX_RACES: usize = 1000;
+// How long a registered race waits for its stream to connect, and how many may wait at once
+const PENDING_RACE_TTL: Duration = Duration::from_secs(5 * 60);
+const MAX_PENDING_RACES: usize = 1000;
+// Encryptions each user may start per minute unless told otherwise
+const DEFAULT_ENCRYPTS_PER_MINUTE: u32 = 60;
+
+/// Where and how the HTTP server listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    pub bind_addr: String,
+    pub port: u16,
+    pub workers: usize,
+    /// Origins allowed to call the API from a browser; empty means same-origin only
+    pub allowed_origins: Vec<String>,
+    /// Requests with a larger body are refused with 413
+    pub max_body_bytes: usize,
+    /// How long SIGTERM/SIGINT waits for in-flight requests before exiting
+    pub shutdown_timeout_secs: u64,
+    /// Encryptions, and separately decryptions, each user may start in any minute
+    /// before getting 429; 0 means no limit
+    pub encrypts_per_minute: u32,
+    /// Bearer token admin routes demand; they are refused outright when unset
+    pub admin_token: Option<String>,
+    /// Secret session tokens are signed with; without it user ids in request
+    /// bodies are taken on trust
+    pub session_secret: Option<String>,
+    /// Run without `session_secret` anyway; only for local development
+    pub insecure_no_sessions: bool,
+    /// Public root of the site for links the theater hands out, if not gongle.com
+    pub base_url: Option<String>,
+    /// Races over more data than this are refused with 400
+    pub max_race_bytes: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: DEFAULT_BIND_ADDR.to_string(),
+            port: DEFAULT_PORT,
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            allowed_origins: Vec::new(),
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            shutdown_timeout_secs: DEFAULT_SHUTDOWN_TIMEOUT_SECS,
+            encrypts_per_minute: DEFAULT_ENCRYPTS_PER_MINUTE,
+            admin_token: None,
+            session_secret: None,
+            insecure_no_sessions: false,
+            base_url: None,
+            max_race_bytes: DEFAULT_MAX_RACE_BYTES,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Read `THEATER_BIND`, `THEATER_PORT`, `THEATER_WORKERS`,
+    /// `THEATER_MAX_BODY_BYTES`, `THEATER_MAX_RACE_BYTES`, `THEATER_SHUTDOWN_TIMEOUT` (seconds),
+    /// `THEATER_ENCRYPTS_PER_MINUTE`, `THEATER_ADMIN_TOKEN`, `THEATER_SESSION_SECRET`,
+    /// `THEATER_INSECURE_NO_SESSIONS`, `THEATER_BASE_URL` and the comma-separated
+    /// `THEATER_ALLOWED_ORIGINS`, falling back to the defaults for any that aren't
+    /// set. `THEATER_SESSION_SECRET` is required unless
+    /// `THEATER_INSECURE_NO_SESSIONS` is `true`
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Build a config from whatever `var` returns for each variable name
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        use anyhow::Context;
+
+        let mut config = Self::default();
+        if let Some(bind_addr) = var("THEATER_BIND") {
+            config.bind_addr = bind_addr;
+        }
+        if let Some(port) = var("THEATER_PORT") {
+            config.port = port
+                .parse()
+                .with_context(|| format!("THEATER_PORT must be a port number, got {:?}", port))?;
+        }
+        if let Some(workers) = var("THEATER_WORKERS") {
+            config.workers = workers
+                .parse()
+                .with_context(|| format!("THEATER_WORKERS must be a number, got {:?}", workers))?;
+        }
+        if let Some(max_body_bytes) = var("THEATER_MAX_BODY_BYTES") {
+            config.max_body_bytes = max_body_bytes.parse().with_context(|| {
+                format!("THEATER_MAX_BODY_BYTES must be a number, got {:?}", max_body_bytes)
+            })?;
+        }
+        if let Some(max_race_bytes) = var("THEATER_MAX_RACE_BYTES") {
+            config.max_race_bytes = max_race_bytes.parse().with_context(|| {
+                format!("THEATER_MAX_RACE_BYTES must be a number, got {:?}", max_race_bytes)
+            })?;
+        }
+        if let Some(timeout) = var("THEATER_SHUTDOWN_TIMEOUT") {
+            config.shutdown_timeout_secs = timeout.parse().with_context(|| {
+                format!("THEATER_SHUTDOWN_TIMEOUT must be a number of seconds, got {:?}", timeout)
+            })?;
+        }
+        if let Some(rate) = var("THEATER_ENCRYPTS_PER_MINUTE") {
+            config.encrypts_per_minute = rate.parse().with_context(|| {
+                format!("THEATER_ENCRYPTS_PER_MINUTE must be a number, got {:?}", rate)
+            })?;
+        }
+        config.admin_token = var("THEATER_ADMIN_TOKEN").filter(|token| !token.is_empty());
+        config.session_secret = var("THEATER_SESSION_SECRET").filter(|secret| !secret.is_empty());
+        if let Some(insecure) = var("THEATER_INSECURE_NO_SESSIONS") {
+            config.insecure_no_sessions = insecure.parse().with_context(|| {
+                format!("THEATER_INSECURE_NO_SESSIONS must be true or false, got {:?}", insecure)
+            })?;
+        }
+        config.base_url = var("THEATER_BASE_URL").filter(|url| !url.is_empty());
+        if let Some(origins) = var("THEATER_ALLOWED_ORIGINS") {
+            config.allowed_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+
+        if config.port == 0 {
+            anyhow::bail!("THEATER_PORT must not be 0");
+        }
+        if config.workers == 0 {
+            anyhow::bail!("THEATER_WORKERS must be at least 1");
+        }
+        if config.session_secret.is_none() && !config.insecure_no_sessions {
+            anyhow::bail!(
+                "THEATER_SESSION_SECRET must be set; THEATER_INSECURE_NO_SESSIONS=true runs without \
+                 sessions, trusting user ids in request bodies"
+            );
+        }
+        Ok(config)
+    }
+
+    /// CORS middleware letting the configured origins call the API
+    pub fn cors(&self) -> Cors {
+        let cors = Cors::default()
+            .allowed_methods(["GET", "POST"])
+            .allowed_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+            .max_age(CORS_MAX_AGE);
+        self.allowed_origins.iter().fold(cors, |cors, origin| match origin.as_str() {
+            "*" => cors.allow_any_origin(),
+            origin => cors.allowed_origin(origin),
+        })
+    }
+
+    /// JSON extractor settings enforcing `max_body_bytes`, answering oversized
+    /// bodies with a 413 `ApiResponse`
+    pub fn json_config(&self) -> web::JsonConfig {
+        web::JsonConfig::default()
+            .limit(self.max_body_bytes)
+            .error_handler(|error, _| match error {
+                JsonPayloadError::Overflow { limit } | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+                    let response = error_response(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        ApiError::Other {
+                            code: "PAYLOAD_TOO_LARGE",
+                            message: format!("Request body is larger than {} bytes", limit),
+                        },
+                    );
+                    InternalError::from_response(error, response).into()
+                }
+                error => error.into(),
+            })
+    }
+
+    /// Raw payload settings enforcing `max_body_bytes`
+    pub fn payload_config(&self) -> web::PayloadConfig {
+        web::PayloadConfig::new(self.max_body_bytes)
+    }
+}
+
+#[derive(Deserialize)]
+struct EncryptRequest {
+    user_id: u64,
+    data: String,
+    level: String,
+    /// Describe the encryption without performing it
+    #[serde(default)]
+    dry_run: bool,
+    /// Also return the ciphertext as armored text
+    #[serde(default)]
+    armor: bool,
+    /// Weakest level the data may be encrypted at, e.g. "paranoid"
+    min_level: Option<String>,
+    /// Client-chosen id for this operation; a retry with the same key replays the first result
+    idempotency_key: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ArmoredEncryption {
+    #[serde(flatten)]
+    result: EncryptionResult,
+    armored_ciphertext: String,
+}
+
+#[derive(Deserialize)]
+struct DecryptRequest {
+    user_id: u64,
+    /// Base64 or armored ciphertext as returned by /encrypt
+    ciphertext: String,
+    /// Needed when the data was encrypted under a custom password
+    password: Option<String>,
+    /// Always return the plaintext base64-encoded
+    #[serde(default)]
+    binary: bool,
+}
+
+#[derive(Serialize)]
+struct DecryptResponse {
+    plaintext: String,
+    /// "utf8", or "base64" in binary mode or when the bytes aren't valid UTF-8
+    encoding: &'static str,
+}
+
+#[derive(Deserialize)]
+struct BatchEncryptRequest {
+    user_id: u64,
+    items: Vec<String>,
+    level: String,
+    /// Encrypt under this password instead of the theatrical one
+    password: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BatchEncryptResponse<T> {
+    batch_size: usize,
+    results: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct FuneralRequest {
+    user_id: u64,
+    data_ids: Vec<String>,
+    funeral_type: FuneralChoice,
+    /// POSTed the schedule, with a completed_at timestamp, once the funeral has been held
+    #[serde(default)]
+    webhook_url: Option<String>,
+    #[serde(flatten)]
+    params: FuneralParams,
+}
+
+/// A funeral picked either by name or as a fully specified tagged ceremony
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FuneralChoice {
+    Named(String),
+    Detailed(FuneralType),
+}
+
+impl FuneralChoice {
+    /// Resolve the ceremony, overriding its settings with any the request supplied
+    fn into_funeral_type(self, params: FuneralParams) -> Result<FuneralType, TheaterError> {
+        let funeral_type = match self {
+            FuneralChoice::Named(name) => funeral_type_from_name(&name),
+            FuneralChoice::Detailed(funeral_type) => funeral_type,
+        };
+        let funeral_type = params.apply(funeral_type);
+        funeral_type.validate()?;
+        Ok(funeral_type)
+    }
+}
+
+/// Per-type ceremony settings; omitted ones keep the stock ceremony's value
+#[derive(Default, Deserialize)]
+struct FuneralParams {
+    longboat_size: Option<u32>,
+    burning_arrows: Option<u32>,
+    trajectory: Option<String>,
+    escape_velocity: Option<f64>,
+    superposition: Option<bool>,
+    observer_count: Option<u32>,
+    tentacles: Option<u32>,
+    dimensions_breached: Option<u32>,
+    sanity_cost: Option<i32>,
+}
+
+impl FuneralParams {
+    /// Overwrite the matching variant's fields; settings for other types are ignored
+    fn apply(self, mut funeral_type: FuneralType) -> FuneralType {
+        match &mut funeral_type {
+            FuneralType::Viking { longboat_size, burning_arrows } => {
+                *longboat_size = self.longboat_size.unwrap_or(*longboat_size);
+                *burning_arrows = self.burning_arrows.unwrap_or(*burning_arrows);
+            },
+            FuneralType::Space { trajectory, escape_velocity } => {
+                if let Some(custom) = self.trajectory {
+                    *trajectory = custom;
+                }
+                *escape_velocity = self.escape_velocity.unwrap_or(*escape_velocity);
+            },
+            FuneralType::Quantum { superposition, observer_count } => {
+                *superposition = self.superposition.unwrap_or(*superposition);
+                *observer_count = self.observer_count.unwrap_or(*observer_count);
+            },
+            FuneralType::Eldritch { tentacles, dimensions_breached, sanity_cost } => {
+                *tentacles = self.tentacles.unwrap_or(*tentacles);
+                *dimensions_breached = self.dimensions_breached.unwrap_or(*dimensions_breached);
+                *sanity_cost = self.sanity_cost.unwrap_or(*sanity_cost);
+            },
+            FuneralType::Custom { .. } => {},
+        }
+        funeral_type
+    }
+}
+
+#[derive(Deserialize)]
+struct LootBoxRequest {
+    user_id: u64,
+}
+
+#[derive(Deserialize)]
+struct CertificateRequest {
+    user_id: u64,
+    user_email: String,
+    encrypted_count: u64,
+}
+
+#[derive(Deserialize)]
+struct ThreatLevelRequest {
+    user_id: u64,
+    encrypted_count: u64,
+    unencrypted_count: u64,
+}
+
+#[derive(Deserialize)]
+struct FuneralCancelRequest {
+    ceremony_id: String,
+}
+
+#[derive(Deserialize)]
+struct RestoreRequest {
+    user_id: u64,
+    data_id: String,
+}
+
+#[derive(Deserialize)]
+struct FuneralPreviewRequest {
+    funeral_type: FuneralChoice,
+    #[serde(flatten)]
+    params: FuneralParams,
+    data_count: usize,
+}
+
+#[derive(Serialize)]
+struct RaceStarted {
+    race_id: String,
+    #[serde(flatten)]
+    race: RaceResults,
+}
+
+#[derive(Deserialize)]
+struct RaceRequest {
+    participants: Vec<RaceParticipant>,
+    data_size: usize,
+    /// Only register the race; it runs once GET /race/{id}/stream connects
+    #[serde(default)]
+    stream: bool,
+}
+
+/// A race registered for streaming, waiting for its stream to connect
+struct PendingRace {
+    participants: Vec<RaceParticipant>,
+    data_size: usize,
+}
+
+#[derive(Serialize)]
+struct RaceRegistered {
+    race_id: String,
+    stream_url: String,
+}
+
+/// One racer crossing the line, as sent to /race/{id}/stream
+#[derive(Serialize)]
+struct RaceFinish<'a> {
+    name: &'a str,
+    time_ms: u64,
+    position: usize,
+}
+
+#[derive(Deserialize)]
+struct QuickRaceRequest {
+    user_id: u64,
+    data_size: usize,
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    #[serde(default = "default_leaderboard_limit")]
+    limit: usize,
+}
+
+fn default_leaderboard_limit() -> usize {
+    DEFAULT_LEADERBOARD_LIMIT
+}
+
+#[derive(Serialize)]
+struct ApiResponse<T> {
+    success: bool,
+    data: Option<T>,
+    error: Option<ApiError>,
+}
+
+/// Error body with a stable code for clients to match on
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ApiError {
+    Theater(TheaterError),
+    Other { code: &'static str, message: String },
+}
+
+/// Fresh id tying together everything logged for one request
+fn new_request_id() -> String {
+    format!("REQ-{:016x}", OsRng.gen::<u64>())
+}
+
+/// HTTP status for each theater failure
+fn status_for(error: &TheaterError) -> StatusCode {
+    match error {
+        TheaterError::KeyDerivation | TheaterError::Encrypt | TheaterError::NonceReuse => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        },
+        TheaterError::Decrypt(_) | TheaterError::Undecryptable => StatusCode::FORBIDDEN,
+        TheaterError::BadMagic
+        | TheaterError::BadArmor(_)
+        | TheaterError::UnsupportedVersion(_)
+        | TheaterError::UnsupportedCipher(_)
+        | TheaterError::InvalidKdf(_)
+        | TheaterError::InvalidLevel(_)
+        | TheaterError::LevelTooLow { .. }
+        | TheaterError::InvalidFuneralParam { .. }
+        | TheaterError::NoParticipants
+        | TheaterError::TooManyParticipants { .. }
+        | TheaterError::RaceTooLarge { .. }
+        | TheaterError::DuplicateParticipant(_)
+        | TheaterError::UnknownCeremony(_) => StatusCode::BAD_REQUEST,
+        TheaterError::LevelMismatch { .. } => StatusCode::BAD_REQUEST,
+        TheaterError::InputTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        TheaterError::InsufficientPoints { .. } => StatusCode::PAYMENT_REQUIRED,
+        TheaterError::RateLimited { .. } | TheaterError::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+        TheaterError::NotFound(_) | TheaterError::UnknownUser(_) => StatusCode::NOT_FOUND,
+        TheaterError::InvalidSession(_) => StatusCode::UNAUTHORIZED,
+        TheaterError::SessionMismatch { .. } => StatusCode::FORBIDDEN,
+        TheaterError::AlreadyExecuted(_) => StatusCode::CONFLICT,
+        TheaterError::NothingToRestore(_) => StatusCode::NOT_FOUND,
+        TheaterError::RestoreWindowExpired(_) => StatusCode::GONE,
+    }
+}
+
+/// Build an error response with the given status and body
+fn error_response(status: StatusCode, error: ApiError) -> HttpResponse {
+    HttpResponse::build(status).json(ApiResponse::<()> {
+        success: false,
+        data: None,
+        error: Some(error),
+    })
+}
+
+/// Map a failed theater call to a response, using its `TheaterError` when it has one
+fn theater_error_response(error: anyhow::Error) -> HttpResponse {
+    tracing::error!(error_code = error_code(&error), error = %format!("{:#}", error), "request failed");
+    match error.downcast::<TheaterError>() {
+        Ok(e) => error_response(status_for(&e), ApiError::Theater(e)),
+        Err(error) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Other { code: "INTERNAL", message: error.to_string() },
+        ),
+    }
+}
+
+/// An encryption remembered under its idempotency key
+struct CachedEncryption {
+    stored_at: SystemTime,
+    /// Hash of the level and data, so a reused key with a different request is caught
+    fingerprint: [u8; 32],
+    /// None while the first request with the key is still encrypting
+    result: Option<EncryptionResult>,
+}
+
+/// What a retried request's idempotency key turned up
+enum Replay {
+    Hit(EncryptionResult),
+    Mismatch,
+    /// The first request with this key hasn't finished yet
+    InFlight,
+    /// Nothing yet; the key is now reserved for this request
+    Miss,
+}
+
+/// Recent encryptions by (user_id, idempotency key), forgotten after `IDEMPOTENCY_TTL`
+#[derive(Default)]
+struct IdempotencyCache {
+    entries: HashMap<(u64, String), CachedEncryption>,
+}
+
+impl IdempotencyCache {
+    fn fingerprint(level: &EncryptionLevel, data: &[u8]) -> [u8; 32] {
+        Sha256::new().chain_update(level.as_str()).chain_update([0]).chain_update(data).finalize().into()
+    }
+
+    /// Look up a key, reserving it on a miss until `remember` or `release`
+    fn lookup(&mut self, key: &(u64, String), fingerprint: &[u8; 32], now: SystemTime) -> Replay {
+        self.entries.retain(|_, cached| !cached.expired(now));
+        match self.entries.get(key) {
+            Some(cached) if cached.fingerprint != *fingerprint => Replay::Mismatch,
+            Some(CachedEncryption { result: Some(result), .. }) => Replay::Hit(result.clone()),
+            Some(CachedEncryption { result: None, .. }) => Replay::InFlight,
+            None => {
+                let reserved = CachedEncryption { stored_at: now, fingerprint: *fingerprint, result: None };
+                self.entries.insert(key.clone(), reserved);
+                Replay::Miss
+            },
+        }
+    }
+
+    fn remember(&mut self, key: (u64, String), fingerprint: [u8; 32], result: EncryptionResult, now: SystemTime) {
+        self.entries.insert(key, CachedEncryption { stored_at: now, fingerprint, result: Some(result) });
+    }
+
+    /// Give up a reservation whose encryption failed, so a retry runs it again
+    fn release(&mut self, key: &(u64, String)) {
+        self.entries.remove(key);
+    }
+}
+
+impl CachedEncryption {
+    fn expired(&self, now: SystemTime) -> bool {
+        now.duration_since(self.stored_at).unwrap_or_default() >= IDEMPOTENCY_TTL
+    }
+}
+
+/// Races by race id, each forgotten `ttl` after it was stored; once
+/// `capacity` are kept, storing another drops the oldest
+struct RaceBoard<T> {
+    entries: HashMap<String, (SystemTime, T)>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl<T> RaceBoard<T> {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self { entries: HashMap::new(), ttl, capacity }
+    }
+
+    fn insert(&mut self, race_id: String, race: T, now: SystemTime) {
+        self.evict_expired(now);
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&race_id) {
+            let oldest = self.entries.iter().min_by_key(|(_, (stored_at, _))| *stored_at).map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(race_id, (now, race));
+    }
+
+    fn get(&mut self, race_id: &str, now: SystemTime) -> Option<&T> {
+        self.evict_expired(now);
+        self.entries.get(race_id).map(|(_, race)| race)
+    }
+
+    fn remove(&mut self, race_id: &str, now: SystemTime) -> Option<T> {
+        self.evict_expired(now);
+        self.entries.remove(race_id).map(|(_, race)| race)
+    }
+
+    fn evict_expired(&mut self, now: SystemTime) {
+        let ttl = self.ttl;
+        self.entries.retain(|_, (stored_at, _)| now.duration_since(*stored_at).unwrap_or_default() < ttl);
+    }
+}
+
+struct AppState {
+    theater: Arc<Mutex<DataTheater>>,
+    /// Where achievements are persisted, if anywhere
+    achievements_path: Option<PathBuf>,
+    funerals: Arc<Mutex<FuneralScheduler>>,
+    /// Finished races by race id, so clients can look them up for a while
+    races: Arc<Mutex<RaceBoard<RaceResults>>>,
+    /// Races waiting for a client to stream them, by race id
+    pending_races: Arc<Mutex<RaceBoard<PendingRace>>>,
+    /// Encryptions replayed to clients that retry with the same idempotency key
+    idempotency: Arc<Mutex<IdempotencyCache>>,
+    /// Bearer token admin routes demand, if admin routes are enabled at all
+    admin_token: Option<String>,
+    /// Checks the session tokens user routes demand; None trusts body user ids
+    sessions: Option<SessionKey>,
+    /// Where "now" comes from for session expiry and idempotency and race TTLs
+    clock: Arc<dyn Clock>,
+    metrics: TheaterMetrics,
+}
+
+/// The token in an `Authorization: Bearer` header, if there is one
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Middleware for user routes: when sessions are on, refuse requests without a
+/// valid bearer session token and leave its user in the request extensions
+async fn require_session(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>> {
+    let verified = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|state| Some((state, state.sessions.as_ref()?)))
+        .map(|(state, sessions)| {
+            let token = bearer_token(req.request()).ok_or(TheaterError::InvalidSession("missing token"))?;
+            sessions.verify(token, state.clock.now())
+        });
+    match verified {
+        Some(Ok(user_id)) => {
+            req.extensions_mut().insert(SessionUser(user_id));
+        },
+        Some(Err(e)) => {
+            let refused = error_response(status_for(&e), ApiError::Theater(e));
+            return Ok(req.into_response(refused).map_into_right_body());
+        },
+        None => {},
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+/// The user a request acts for: its session's user, who must be the one the
+/// request names, or the named user when sessions are off
+fn acting_user(session: Option<web::ReqData<SessionUser>>, claimed: u64) -> Result<u64, TheaterError> {
+    match session.map(web::ReqData::into_inner) {
+        Some(SessionUser(user_id)) if user_id != claimed => {
+            Err(TheaterError::SessionMismatch { session: user_id, claimed })
+        },
+        Some(SessionUser(user_id)) => Ok(user_id),
+        None => Ok(claimed),
+    }
+}
+
+/// The response to refuse with, unless the request carries the admin bearer token
+fn require_admin(req: &HttpRequest, state: &AppState) -> Option<HttpResponse> {
+    let Some(expected) = &state.admin_token else {
+        return Some(error_response(
+            StatusCode::FORBIDDEN,
+            ApiError::Other { code: "ADMIN_DISABLED", message: "No admin token is configured".to_string() },
+        ));
+    };
+    let presented = bearer_token(req).unwrap_or_default();
+
+    (!ct_eq(presented.as_bytes(), expected.as_bytes())).then(|| {
+        error_response(
+            StatusCode::UNAUTHORIZED,
+            ApiError::Other { code: "UNAUTHORIZED", message: "Admin token missing or wrong".to_string() },
+        )
+    })
+}
+
+/// A successful encryption, with the armored ciphertext alongside if asked for
+fn encryption_response(result: EncryptionResult, armor: bool) -> HttpResponse {
+    if armor {
+        let armored_ciphertext = to_armor(&result.ciphertext);
+        return HttpResponse::Ok().json(ApiResponse {
+            success: true,
+            data: Some(ArmoredEncryption { result, armored_ciphertext }),
+            error: None,
+        });
+    }
+    HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(result),
+        error: None,
+    })
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = data.user_id, level = %data.level))]
+async fn encrypt_handler(
+    data: web::Json<EncryptRequest>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, data.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let level: EncryptionLevel = match data.level.parse() {
+        Ok(level) => level,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    if let Some(min_level) = &data.min_level {
+        let min: EncryptionLevel = match min_level.parse() {
+            Ok(min) => min,
+            Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+        };
+        if !level.at_least(min.clone()) {
+            let e = TheaterError::LevelTooLow { level, min };
+            return Ok(error_response(status_for(&e), ApiError::Theater(e)));
+        }
+    }
+    if data.idempotency_key.as_ref().is_some_and(|key| key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN) {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            ApiError::Other {
+                code: "INVALID_IDEMPOTENCY_KEY",
+                message: format!("Idempotency keys must be 1 to {} bytes", MAX_IDEMPOTENCY_KEY_LEN),
+            },
+        ));
+    }
+
+    if data.dry_run {
+        return Ok(HttpResponse::Ok().json(ApiResponse {
+            success: true,
+            data: Some(state.theater.lock().await.dry_run_encryption(user_id, level)),
+            error: None,
+        }));
+    }
+
+    // A miss reserves the key, so a retry racing the original is turned away
+    // instead of encrypting (and charging) a second time
+    let replay_key = data.idempotency_key.clone().map(|key| (user_id, key));
+    let fingerprint = IdempotencyCache::fingerprint(&level, data.data.as_bytes());
+    if let Some(key) = &replay_key {
+        match state.idempotency.lock().await.lookup(key, &fingerprint, state.clock.now()) {
+            Replay::Hit(result) => return Ok(encryption_response(result, data.armor)),
+            Replay::Mismatch => {
+                return Ok(error_response(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    ApiError::Other {
+                        code: "IDEMPOTENCY_KEY_REUSED",
+                        message: "This idempotency key was already used for a different request".to_string(),
+                    },
+                ))
+            },
+            Replay::InFlight => {
+                return Ok(error_response(
+                    StatusCode::CONFLICT,
+                    ApiError::Other {
+                        code: "IDEMPOTENCY_KEY_IN_FLIGHT",
+                        message: "A request with this idempotency key is still running, retry shortly".to_string(),
+                    },
+                ))
+            },
+            Replay::Miss => {},
+        }
+    }
+
+    match DataTheater::encrypt_shared(&state.theater, user_id, data.data.as_bytes(), level.clone()).await {
+        Ok(result) => {
+            state.metrics.record_encryption(&level, &result);
+            if let Some(key) = replay_key {
+                state.idempotency.lock().await.remember(key, fingerprint, result.clone(), state.clock.now());
+            }
+            Ok(encryption_response(result, data.armor))
+        },
+        Err(e) => {
+            if let Some(key) = &replay_key {
+                state.idempotency.lock().await.release(key);
+            }
+            Ok(theater_error_response(e))
+        },
+    }
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = data.user_id))]
+async fn decrypt_handler(
+    data: web::Json<DecryptRequest>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, data.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let ciphertext = if is_armored(&data.ciphertext) {
+        match from_armor(&data.ciphertext) {
+            Ok(ciphertext) => ciphertext,
+            Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+        }
+    } else {
+        match base64_text::decode(&data.ciphertext) {
+            Ok(ciphertext) => ciphertext,
+            Err(e) => {
+                return Ok(error_response(
+                    StatusCode::BAD_REQUEST,
+                    ApiError::Other { code: "INVALID_BASE64", message: format!("Ciphertext is not valid base64: {}", e) },
+                ))
+            },
+        }
+    };
+
+    let decrypted = DataTheater::decrypt_shared(&state.theater, user_id, ciphertext, data.password.as_deref()).await;
+    let plaintext = match decrypted {
+        Ok(plaintext) => plaintext,
+        Err(e) => return Ok(theater_error_response(e)),
+    };
+
+    let response = match String::from_utf8(plaintext) {
+        Ok(text) if !data.binary => DecryptResponse { plaintext: text, encoding: "utf8" },
+        Ok(text) => DecryptResponse { plaintext: base64_text::encode(text), encoding: "base64" },
+        Err(e) => DecryptResponse { plaintext: base64_text::encode(e.into_bytes()), encoding: "base64" },
+    };
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(response),
+        error: None,
+    }))
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = data.user_id, level = %data.level))]
+async fn batch_encrypt_handler(
+    data: web::Json<BatchEncryptRequest>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, data.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let level: EncryptionLevel = match data.level.parse() {
+        Ok(level) => level,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let BatchEncryptRequest { items, password, .. } = data.into_inner();
+
+    let results =
+        DataTheater::encrypt_batch_shared(&state.theater, user_id, &items, level.clone(), password.as_deref()).await;
+
+    match results {
+        Ok(results) => {
+            for result in &results {
+                state.metrics.record_encryption(&level, result);
+            }
+            Ok(HttpResponse::Ok().json(ApiResponse {
+                success: true,
+                data: Some(BatchEncryptResponse {
+                    batch_size: results.len(),
+                    results,
+                }),
+                error: None,
+            }))
+        },
+        Err(e) => Ok(theater_error_response(e)),
+    }
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = data.user_id))]
+async fn funeral_handler(
+    data: web::Json<FuneralRequest>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, data.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let data = data.into_inner();
+    let funeral_type = match data.funeral_type.into_funeral_type(data.params) {
+        Ok(funeral_type) => funeral_type,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let mut theater = state.theater.lock().await;
+    
+    match theater.schedule_funeral(
+        user_id,
+        data.data_ids,
+        funeral_type,
+        data.webhook_url,
+    ).await {
+        Ok(schedule) => {
+            state.metrics.record_funeral(&schedule.funeral_type);
+            state.funerals.lock().await.enqueue(schedule.clone());
+            Ok(HttpResponse::Ok().json(ApiResponse {
+                success: true,
+                data: Some(schedule),
+                error: None,
+            }))
+        },
+        Err(e) => Ok(theater_error_response(e)),
+    }
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), ceremony_id = %data.ceremony_id))]
+async fn funeral_cancel_handler(
+    data: web::Json<FuneralCancelRequest>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    // Someone else's funeral looks just like one that doesn't exist
+    if let Some(SessionUser(user_id)) = session.map(web::ReqData::into_inner) {
+        if !data.ceremony_id.starts_with(&format!("FUNERAL-{}-", user_id)) {
+            let e = TheaterError::NotFound(data.ceremony_id.clone());
+            return Ok(error_response(status_for(&e), ApiError::Theater(e)));
+        }
+    }
+    match state.funerals.lock().await.cancel_funeral(&data.ceremony_id) {
+        Ok(()) => Ok(HttpResponse::Ok().json(ApiResponse {
+            success: true,
+            data: Some(serde_json::json!({ "ceremony_id": data.ceremony_id })),
+            error: None,
+        })),
+        Err(e) => Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    }
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), data_id = %data.data_id))]
+async fn restore_handler(
+    data: web::Json<RestoreRequest>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, data.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let restored = match state.funerals.lock().await.restore(user_id, &data.data_id) {
+        Ok(()) => serde_json::json!({ "data_id": data.data_id }),
+        // Not shredded by a funeral, but it may be an encryption to reverse
+        Err(TheaterError::NothingToRestore(_)) => match state.theater.lock().await.restore(user_id, &data.data_id) {
+            Ok(plaintext) => serde_json::json!({ "data_id": data.data_id, "data": base64_text::encode(plaintext) }),
+            Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+        },
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    Ok(HttpResponse::Ok().json(ApiResponse { success: true, data: Some(restored), error: None }))
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id()))]
+async fn funeral_preview_handler(data: web::Json<FuneralPreviewRequest>) -> Result<HttpResponse> {
+    let data = data.into_inner();
+    let funeral_type = match data.funeral_type.into_funeral_type(data.params) {
+        Ok(funeral_type) => funeral_type,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(describe_funeral(&funeral_type, data.data_count)),
+        error: None,
+    }))
+}
+
+/// Build the stock ceremony for a funeral name sent by the frontend; any
+/// other name is looked up in the theater's ceremony registry
+pub fn funeral_type_from_name(name: &str) -> FuneralType {
+    FuneralType::stock(name).unwrap_or_else(|| FuneralType::Custom { ceremony: name.to_string() })
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id()))]
+async fn race_handler(
+    data: web::Json<RaceRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let RaceRequest { participants, data_size, stream } = data.into_inner();
+    let limits = state.theater.lock().await.race_limits();
+
+    if stream {
+        if let Err(e) = check_race(&participants, data_size, limits) {
+            return Ok(error_response(status_for(&e), ApiError::Theater(e)));
+        }
+        let race_id = format!("RACE-{:08x}", OsRng.gen::<u32>());
+        let pending = PendingRace { participants, data_size };
+        state.pending_races.lock().await.insert(race_id.clone(), pending, state.clock.now());
+        let stream_url = format!("/api/theater/race/{}/stream", race_id);
+        return Ok(HttpResponse::Ok().json(ApiResponse {
+            success: true,
+            data: Some(RaceRegistered { race_id, stream_url }),
+            error: None,
+        }));
+    }
+
+    match encryption_race(participants, data_size, limits, &mut OsRng).await {
+        Ok(race) => {
+            let race_id = format!("RACE-{:08x}", OsRng.gen::<u32>());
+            state.races.lock().await.insert(race_id.clone(), race.clone(), state.clock.now());
+            Ok(HttpResponse::Ok().json(ApiResponse {
+                success: true,
+                data: Some(RaceStarted { race_id, race }),
+                error: None,
+            }))
+        },
+        Err(e) => Ok(theater_error_response(e)),
+    }
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), race_id = %path.as_str()))]
+async fn get_race(path: web::Path<String>, state: web::Data<AppState>) -> Result<HttpResponse> {
+    let race_id = path.into_inner();
+    match state.races.lock().await.get(&race_id, state.clock.now()) {
+        Some(race) => Ok(HttpResponse::Ok().json(ApiResponse {
+            success: true,
+            data: Some(race),
+            error: None,
+        })),
+        None => Ok(error_response(
+            StatusCode::NOT_FOUND,
+            ApiError::Other { code: "RACE_NOT_FOUND", message: format!("No race with id '{}'", race_id) },
+        )),
+    }
+}
+
+/// One Server-Sent Event carrying `data` as JSON
+fn sse_event(event: &str, data: &impl Serialize) -> web::Bytes {
+    let json = serde_json::to_string(data).unwrap_or_else(|_| "{}".to_string());
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", event, json))
+}
+
+/// Stream a race as Server-Sent Events: a `finish` event per racer as they
+/// cross the line, then a `winner` event. A registered race starts when its
+/// stream connects and is called off if the client hangs up; a finished race
+/// is replayed from its results.
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), race_id = %path.as_str()))]
+async fn race_stream(path: web::Path<String>, state: web::Data<AppState>) -> Result<HttpResponse> {
+    let race_id = path.into_inner();
+    let pending = state.pending_races.lock().await.remove(&race_id, state.clock.now());
+
+    let rx = match pending {
+        Some(PendingRace { participants, data_size }) => {
+            let limits = state.theater.lock().await.race_limits();
+            let race = match RaceInProgress::start(participants, data_size, limits, &mut OsRng) {
+                Ok(race) => race,
+                Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+            };
+            let (tx, rx) = mpsc::channel(RACE_STREAM_BUFFER);
+            tokio::spawn(run_streamed_race(race_id, race, state.races.clone(), state.clock.clone(), tx));
+            rx
+        },
+        None => match state.races.lock().await.get(&race_id, state.clock.now()) {
+            Some(race) => {
+                let (tx, rx) = mpsc::channel(race.results.len() + 1);
+                for (position, result) in (1..).zip(&race.results) {
+                    let finish = RaceFinish { name: &result.name, time_ms: result.time_ms, position };
+                    let _ = tx.try_send(sse_event("finish", &finish));
+                }
+                let _ = tx.try_send(sse_event("winner", &serde_json::json!({ "winner": race.winner })));
+                rx
+            },
+            None => {
+                return Ok(error_response(
+                    StatusCode::NOT_FOUND,
+                    ApiError::Other { code: "RACE_NOT_FOUND", message: format!("No race with id '{}'", race_id) },
+                ))
+            },
+        },
+    };
+
+    let events = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (Ok::<_, actix_web::Error>(event), rx))
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoCache]))
+        .streaming(events))
+}
+
+/// Run a streamed race to the end, sending events to `tx`, then keep its
+/// results like any other race. Stops the racers as soon as `tx` closes.
+async fn run_streamed_race(
+    race_id: String,
+    mut race: RaceInProgress,
+    races: Arc<Mutex<RaceBoard<RaceResults>>>,
+    clock: Arc<dyn Clock>,
+    tx: mpsc::Sender<web::Bytes>,
+) {
+    loop {
+        let event = tokio::select! {
+            _ = tx.closed() => {
+                tracing::info!(race_id = %race_id, "race stream closed, calling off the race");
+                return;
+            },
+            next = race.next_finisher() => match next {
+                Ok(Some((position, result))) => {
+                    sse_event("finish", &RaceFinish { name: &result.name, time_ms: result.time_ms, position })
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    let error = serde_json::json!({ "message": e.to_string() });
+                    let _ = tx.send(sse_event("error", &error)).await;
+                    return;
+                },
+            },
+        };
+        if tx.send(event).await.is_err() {
+            return;
+        }
+    }
+
+    let results = race.finish(&mut OsRng);
+    let winner = sse_event("winner", &serde_json::json!({ "winner": results.winner }));
+    races.lock().await.insert(race_id, results, clock.now());
+    let _ = tx.send(winner).await;
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = data.user_id))]
+async fn quick_race_handler(
+    data: web::Json<QuickRaceRequest>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, data.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let racer = RaceParticipant {
+        name: format!("User {}", user_id),
+        encryption_speed: 1.0,
+        vehicle: "🏎️".to_string(),
+        trash_talk: "Prepare to be decrypted!".to_string(),
+    };
+
+    match DataTheater::quick_race_shared(&state.theater, user_id, racer, data.data_size).await {
+        Ok(results) => {
+            state.metrics.record_points_awarded(results.points_awarded);
+            Ok(HttpResponse::Ok().json(ApiResponse {
+                success: true,
+                data: Some(results),
+                error: None,
+            }))
+        },
+        Err(e) => Ok(theater_error_response(e)),
+    }
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = data.user_id))]
+async fn lootbox_handler(
+    data: web::Json<LootBoxRequest>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, data.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let mut theater = state.theater.lock().await;
+    let loot = match theater.open_loot_box(user_id) {
+        Ok(loot) => loot,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    state.metrics.record_points_spent(LOOT_BOX_COST);
+    state.metrics.record_points_awarded(loot.bonus);
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(loot),
+        error: None,
+    }))
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = *path))]
+async fn collection_handler(
+    path: web::Path<u64>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, *path) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let theater = state.theater.lock().await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(group_collection(&theater.collection(user_id))),
+        error: None,
+    }))
+}
+
+/// Everything held for a user as a downloadable JSON file
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = *path))]
+async fn export_user_handler(
+    path: web::Path<u64>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, path.into_inner()) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let mut export = state.theater.lock().await.export_user(user_id);
+    export.funerals = state.funerals.lock().await.pending_for(user_id);
+
+    Ok(HttpResponse::Ok()
+        .insert_header(header::ContentDisposition::attachment(format!("gongle-user-{}.json", user_id)))
+        .json(export))
+}
+
+/// Erase everything held for a user, for deletion requests; admin only
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = *path))]
+async fn purge_user_handler(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Some(refused) = require_admin(&req, &state) {
+        return Ok(refused);
+    }
+    let user_id = path.into_inner();
+    let mut theater = state.theater.lock().await;
+
+    let mut report = match theater.purge_user(user_id) {
+        Ok(report) => report,
+        Err(e) => return Ok(theater_error_response(e)),
+    };
+    let funerals = state.funerals.lock().await.purge_user(user_id);
+    report.pending_funerals = funerals.pending_funerals;
+    report.data_items = funerals.data_items;
+    state.idempotency.lock().await.entries.retain(|(owner, _), _| *owner != user_id);
+
+    // Otherwise the next restart would bring the achievements back
+    if let Some(path) = &state.achievements_path {
+        if let Err(e) = theater.save_achievements(path) {
+            return Ok(theater_error_response(e));
+        }
+    }
+    tracing::info!(?report, "user purged");
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(report),
+        error: None,
+    }))
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), limit = query.limit))]
+async fn leaderboard_handler(
+    query: web::Query<LeaderboardQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let theater = state.theater.lock().await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(theater.leaderboard(query.limit)),
+        error: None,
+    }))
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = data.user_id))]
+async fn certificate_handler(
+    data: web::Json<CertificateRequest>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, data.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let mut theater = state.theater.lock().await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(theater.generate_certificate(user_id, &data.user_email, data.encrypted_count)),
+        error: None,
+    }))
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), user_id = data.user_id))]
+async fn threat_level_handler(
+    data: web::Json<ThreatLevelRequest>,
+    session: Option<web::ReqData<SessionUser>>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let user_id = match acting_user(session, data.user_id) {
+        Ok(user_id) => user_id,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+    let theater = state.theater.lock().await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(theater.threat_level(user_id, data.encrypted_count, data.unencrypted_count)),
+        error: None,
+    }))
+}
+
+/// Body of the health and readiness probes
+#[derive(Serialize)]
+struct HealthStatus {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+/// Liveness: the process is up and serving requests
+async fn healthz_handler() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(HealthStatus { status: "ok", reason: None }))
+}
+
+/// Readiness: the theater can be locked and the achievements file can be reached
+async fn readyz_handler(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let unavailable = |reason: String| {
+        HttpResponse::ServiceUnavailable().json(HealthStatus {
+            status: "unavailable",
+            reason: Some(reason),
+        })
+    };
+
+    if tokio::time::timeout(READY_LOCK_TIMEOUT, state.theater.lock()).await.is_err() {
+        return Ok(unavailable("theater is busy".to_string()));
+    }
+    if let Some(path) = &state.achievements_path {
+        // A missing file is fine as long as it can be created later
+        let reachable = match std::fs::metadata(path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+                dir.map_or(Ok(()), |dir| std::fs::metadata(dir).map(|_| ()))
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = reachable {
+            return Ok(unavailable(format!("achievements file {} is unreachable: {}", path.display(), e)));
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(HealthStatus { status: "ok", reason: None }))
+}
+
+/// Prometheus scrape target
+async fn metrics_handler(state: web::Data<AppState>) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(state.metrics.render()))
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %new_request_id(), level = %path.as_str()))]
+async fn estimate_handler(path: web::Path<String>, state: web::Data<AppState>) -> Result<HttpResponse> {
+    let level: EncryptionLevel = match path.parse() {
+        Ok(level) => level,
+        Err(e) => return Ok(error_response(status_for(&e), ApiError::Theater(e))),
+    };
+
+    let theater = state.theater.lock().await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(theater.estimate(&level)),
+        error: None,
+    }))
+}
+
+async fn economy_handler(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let theater = state.theater.lock().await;
+
+    Ok(HttpResponse::Ok().json(ApiResponse {
+        success: true,
+        data: Some(economy_table(theater.config())),
+        error: None,
+    }))
+}
+
+/// A route acting for a user, which needs a session token when sessions are on
+fn user_route(path: &str, route: actix_web::Route) -> impl HttpServiceFactory {
+    web::resource(path).wrap(from_fn(require_session)).route(route)
+}
+
+/// Register the theater routes on an actix `App`
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/healthz", web::get().to(healthz_handler))
+        .route("/readyz", web::get().to(readyz_handler))
+        .route("/metrics", web::get().to(metrics_handler));
+    cfg.service(
+        web::scope("/api/theater")
+            .service(user_route("/encrypt", web::post().to(encrypt_handler)))
+            .service(user_route("/encrypt/batch", web::post().to(batch_encrypt_handler)))
+            .service(user_route("/decrypt", web::post().to(decrypt_handler)))
+            .service(user_route("/funeral", web::post().to(funeral_handler)))
+            .service(user_route("/funeral/cancel", web::post().to(funeral_cancel_handler)))
+            .route("/funeral/preview", web::post().to(funeral_preview_handler))
+            .service(user_route("/restore", web::post().to(restore_handler)))
+            .route("/race", web::post().to(race_handler))
+            .service(user_route("/race/quick", web::post().to(quick_race_handler)))
+            .route("/race/{id}", web::get().to(get_race))
+            .route("/race/{id}/stream", web::get().to(race_stream))
+            .service(user_route("/lootbox", web::post().to(lootbox_handler)))
+            .service(user_route("/collection/{user_id}", web::get().to(collection_handler)))
+            .route("/user/{user_id}", web::delete().to(purge_user_handler))
+            .service(user_route("/user/{user_id}/export", web::get().to(export_user_handler)))
+            .route("/leaderboard", web::get().to(leaderboard_handler))
+            .service(user_route("/certificate", web::post().to(certificate_handler)))
+            .service(user_route("/threat_level", web::post().to(threat_level_handler)))
+            .route("/estimate/{level}", web::get().to(estimate_handler))
+            .route("/economy", web::get().to(economy_handler)),
+    );
+}
+
+/// Run the theater API until the server is stopped
+pub async fn run(config: ServerConfig) -> std::io::Result<()> {
+    let achievements_path = std::env::var_os("THEATER_ACHIEVEMENTS").map(PathBuf::from);
+    let config_path = std::env::var_os("THEATER_CONFIG").map_or_else(|| PathBuf::from(CONFIG_PATH), PathBuf::from);
+    let mut theater = TheaterConfig::load(&config_path)
+        .and_then(|theater_config| DataTheater::new().with_billing().with_config(theater_config))
+        .map(|theater| theater.with_max_race_bytes(config.max_race_bytes))
+        .and_then(|theater| theater.with_signing_key_from(Path::new(SIGNING_KEY_PATH)))
+        .map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
+    if config.encrypts_per_minute > 0 {
+        theater = theater.with_encrypt_rate_limit(config.encrypts_per_minute);
+    }
+    if let Some(base_url) = &config.base_url {
+        theater = theater
+            .with_base_url(base_url)
+            .map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
+    }
+    if let Some(path) = &achievements_path {
+        theater = theater
+            .with_achievements_from(path)
+            .map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
+    }
+    let state = web::Data::new(AppState {
+        theater: Arc::new(Mutex::new(theater)),
+        achievements_path,
+        funerals: Arc::new(Mutex::new(FuneralScheduler::new())),
+        races: Arc::new(Mutex::new(RaceBoard::new(RACE_TTL, MAX_RACES))),
+        pending_races: Arc::new(Mutex::new(RaceBoard::new(PENDING_RACE_TTL, MAX_PENDING_RACES))),
+        idempotency: Arc::new(Mutex::new(IdempotencyCache::default())),
+        admin_token: config.admin_token.clone(),
+        sessions: config.session_secret.as_ref().map(SessionKey::new),
+        clock: Arc::new(SystemClock),
+        metrics: TheaterMetrics::new(),
+    });
+    if state.sessions.is_none() {
+        tracing::warn!("running without sessions, so user ids in request bodies are trusted");
+    }
+    let scheduler = FuneralScheduler::spawn(state.funerals.clone());
+
+    let listener = std::net::TcpListener::bind((config.bind_addr.as_str(), config.port))?;
+    let served = serve(state, &config, listener)?.await;
+
+    // Every request has drained by now, so nothing else touches the funerals
+    scheduler.abort();
+    match scheduler.await {
+        Err(e) if e.is_panic() => tracing::error!(error = %e, "funeral scheduler panicked"),
+        _ => tracing::info!("funeral scheduler stopped"),
+    }
+    served
+}
+
+/// Start serving on `listener`. SIGTERM and SIGINT stop new connections and
+/// give in-flight requests `shutdown_timeout_secs` to finish.
+fn serve(
+    state: web::Data<AppState>,
+    config: &ServerConfig,
+    listener: std::net::TcpListener,
+) -> std::io::Result<actix_web::dev::Server> {
+    let server_config = config.clone();
+    Ok(HttpServer::new(move || {
+        App::new()
+            .wrap(server_config.cors())
+            .app_data(server_config.json_config())
+            .app_data(server_config.payload_config())
+            .app_data(state.clone())
+            .configure(configure)
+    })
+    .workers(config.workers)
+    .shutdown_timeout(config.shutdown_timeout_secs)
+    .listen(listener)?
+    .run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use crate::web_theatre::{load_achievements, AuditOutcome, EconomyTable, MockClock};
+
+    /// A quick theater with no dramatic pauses
+    fn test_theater() -> DataTheater {
+        DataTheater::new()
+            .with_pbkdf2_rounds(1000)
+            .unwrap()
+            .without_theatrics()
+    }
+
+    fn test_state() -> web::Data<AppState> {
+        test_state_with(test_theater(), |_| {})
+    }
+
+    /// A fresh state around `theater`, with `adjust` changing whatever else a test needs
+    fn test_state_with(theater: DataTheater, adjust: impl FnOnce(&mut AppState)) -> web::Data<AppState> {
+        let mut state = AppState {
+            theater: Arc::new(Mutex::new(theater)),
+            achievements_path: None,
+            funerals: Arc::new(Mutex::new(FuneralScheduler::new())),
+            races: Arc::new(Mutex::new(RaceBoard::new(RACE_TTL, MAX_RACES))),
+            pending_races: Arc::new(Mutex::new(RaceBoard::new(PENDING_RACE_TTL, MAX_PENDING_RACES))),
+            idempotency: Arc::new(Mutex::new(IdempotencyCache::default())),
+            admin_token: None,
+            sessions: None,
+            clock: Arc::new(SystemClock),
+            metrics: TheaterMetrics::new(),
+        };
+        adjust(&mut state);
+        web::Data::new(state)
+    }
+
+    #[actix_web::test]
+    async fn batch_encrypt_reports_size_and_uses_the_password() {
+        let state = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/encrypt/batch")
+            .set_json(serde_json::json!({
+                "user_id": 9,
+                "items": ["one", "two", "three"],
+                "level": "basic",
+                "password": "correct horse",
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["data"]["batch_size"], 3);
+        let results = body["data"]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+
+        let ciphertext = base64_text::decode(results[1]["ciphertext"].as_str().unwrap()).unwrap();
+        let mut theater = state.theater.lock().await;
+        assert_eq!(theater.decrypt_auto(9, &ciphertext, Some("correct horse")).unwrap(), b"two");
+        assert!(theater.decrypt_auto(9, &ciphertext, None).is_err());
+    }
+
+    #[actix_web::test]
+    async fn decrypt_undoes_encrypt_over_http() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/encrypt")
+            .set_json(serde_json::json!({ "user_id": 5, "data": "round trip", "level": "tinfoil" }))
+            .to_request();
+        let encrypted: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let ciphertext = encrypted["data"]["ciphertext"].clone();
+
+        let decrypt = |body: serde_json::Value| {
+            test::TestRequest::post().uri("/api/theater/decrypt").set_json(body).to_request()
+        };
+
+        let req = decrypt(serde_json::json!({ "user_id": 5, "ciphertext": ciphertext }));
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"], serde_json::json!({ "plaintext": "round trip", "encoding": "utf8" }));
+
+        let req = decrypt(serde_json::json!({ "user_id": 5, "ciphertext": ciphertext, "binary": true }));
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"]["plaintext"], base64_text::encode("round trip"));
+
+        // Ciphertexts are bound to their owner
+        let req = decrypt(serde_json::json!({ "user_id": 6, "ciphertext": ciphertext }));
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let req = decrypt(serde_json::json!({ "user_id": 5, "ciphertext": "not base64!" }));
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn armored_ciphertexts_decrypt() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/encrypt")
+            .set_json(serde_json::json!({ "user_id": 5, "data": "by email", "level": "basic", "armor": true }))
+            .to_request();
+        let encrypted: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let armored = encrypted["data"]["armored_ciphertext"].as_str().unwrap().to_string();
+        assert!(armored.starts_with("-----BEGIN GONGLE ENCRYPTED-----"));
+        assert!(encrypted["data"]["ciphertext"].is_string());
+
+        let req = test::TestRequest::post()
+            .uri("/api/theater/decrypt")
+            .set_json(serde_json::json!({ "user_id": 5, "ciphertext": armored }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"]["plaintext"], "by email");
+
+        let truncated = armored.replace("-----END GONGLE ENCRYPTED-----", "");
+        let req = test::TestRequest::post()
+            .uri("/api/theater/decrypt")
+            .set_json(serde_json::json!({ "user_id": 5, "ciphertext": truncated }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let error: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(error["error"]["code"], "BAD_ARMOR");
+    }
+
+    #[actix_web::test]
+    async fn unknown_level_is_a_bad_request() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/encrypt")
+            .set_json(serde_json::json!({ "user_id": 1, "data": "hi", "level": "quantam" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "INVALID_LEVEL");
+        assert_eq!(body["error"]["details"]["level"], "quantam");
+        assert!(body["error"]["message"].as_str().unwrap().contains("basic, premium, paranoid"));
+    }
+
+    #[actix_web::test]
+    async fn funeral_preview_matches_scheduled_epitaph() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/theater/funeral/preview")
+            .set_json(serde_json::json!({ "funeral_type": "viking", "data_count": 7 }))
+            .to_request();
+        let preview: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        let data_ids: Vec<String> = (0..7).map(|i| i.to_string()).collect();
+        let req = test::TestRequest::post()
+            .uri("/api/theater/funeral")
+            .set_json(serde_json::json!({
+                "user_id": 1,
+                "data_ids": data_ids,
+                "funeral_type": "viking",
+            }))
+            .to_request();
+        let schedule: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(preview["data"]["epitaph"], schedule["data"]["epitaph"]);
+        assert_eq!(preview["data"]["cost"], 10000);
+    }
+
+    #[actix_web::test]
+    async fn funeral_accepts_tagged_ceremony_params() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/funeral")
+            .set_json(serde_json::json!({
+                "user_id": 1,
+                "data_ids": ["a"],
+                "funeral_type": { "type": "viking", "longboat_size": 200, "burning_arrows": 9 },
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["success"], true);
+        assert_eq!(
+            body["data"]["funeral_type"],
+            serde_json::json!({ "type": "viking", "longboat_size": 200, "burning_arrows": 9 })
+        );
+    }
+
+    #[actix_web::test]
+    async fn funeral_params_override_defaults() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/funeral")
+            .set_json(serde_json::json!({
+                "user_id": 1,
+                "data_ids": ["a"],
+                "funeral_type": "space",
+                "trajectory": "Jupiter",
+            }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(
+            body["data"]["funeral_type"],
+            serde_json::json!({ "type": "space", "trajectory": "Jupiter", "escape_velocity": 11.2 })
+        );
+    }
+
+    #[actix_web::test]
+    async fn out_of_range_funeral_params_are_rejected() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/funeral")
+            .set_json(serde_json::json!({
+                "user_id": 1,
+                "data_ids": ["a"],
+                "funeral_type": "viking",
+                "burning_arrows": 10_001,
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "INVALID_FUNERAL_PARAM");
+        assert_eq!(body["error"]["details"]["field"], "burning_arrows");
+    }
+
+    #[actix_web::test]
+    async fn cancel_removes_a_scheduled_funeral() {
+        let state = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/funeral")
+            .set_json(serde_json::json!({ "user_id": 1, "data_ids": ["a"], "funeral_type": "viking" }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let ceremony_id = body["data"]["ceremony_id"].clone();
+        assert_eq!(state.funerals.lock().await.pending_count(), 1);
+
+        let cancel = |ceremony_id: serde_json::Value| {
+            test::TestRequest::post()
+                .uri("/api/theater/funeral/cancel")
+                .set_json(serde_json::json!({ "ceremony_id": ceremony_id }))
+                .to_request()
+        };
+        let resp = test::call_service(&app, cancel(ceremony_id.clone())).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(state.funerals.lock().await.pending_count(), 0);
+
+        let resp = test::call_service(&app, cancel(ceremony_id)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn started_race_can_be_polled() {
+        let state = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/race")
+            .set_json(serde_json::json!({
+                "participants": [
+                    { "name": "Tortoise", "encryption_speed": 1000.0, "vehicle": "shell", "trash_talk": "" },
+                    { "name": "Hare", "encryption_speed": 2000.0, "vehicle": "legs", "trash_talk": "" },
+                ],
+                "data_size": 10,
+            }))
+            .to_request();
+        let started: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let race_id = started["data"]["race_id"].as_str().unwrap().to_string();
+        assert!(state.races.lock().await.entries.contains_key(&race_id));
+
+        let req = test::TestRequest::get().uri(&format!("/api/theater/race/{}", race_id)).to_request();
+        let polled: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(polled["data"]["winner"], started["data"]["winner"]);
+        assert_eq!(polled["data"]["results"], started["data"]["results"]);
+    }
+
+    #[actix_web::test]
+    async fn race_stream_reports_finishers_in_order() {
+        let state = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/race")
+            .set_json(serde_json::json!({
+                "participants": [
+                    { "name": "Tortoise", "encryption_speed": 100.0, "vehicle": "shell", "trash_talk": "" },
+                    { "name": "Hare", "encryption_speed": 1000.0, "vehicle": "legs", "trash_talk": "" },
+                    { "name": "Snail", "encryption_speed": 50.0, "vehicle": "slime", "trash_talk": "" },
+                ],
+                "data_size": 10,
+                "stream": true,
+            }))
+            .to_request();
+        let registered: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let stream_url = registered["data"]["stream_url"].as_str().unwrap().to_string();
+
+        let req = test::TestRequest::get().uri(&stream_url).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "text/event-stream");
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+
+        let events: Vec<(&str, serde_json::Value)> = body
+            .split_terminator("\n\n")
+            .map(|event| {
+                let (name, data) = event.split_once('\n').unwrap();
+                let data = serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap();
+                (name.strip_prefix("event: ").unwrap(), data)
+            })
+            .collect();
+        let names: Vec<&str> = events.iter().map(|(_, data)| data["name"].as_str().unwrap_or("")).collect();
+        assert_eq!(names, ["Hare", "Tortoise", "Snail", ""]);
+        for (position, (name, data)) in (1..).zip(&events[..3]) {
+            assert_eq!((*name, &data["position"]), ("finish", &serde_json::json!(position)));
+        }
+        assert_eq!(events[3], ("winner", serde_json::json!({ "winner": "Hare" })));
+
+        // Once run, the race is kept like any other and can't be streamed live again
+        let race_id = registered["data"]["race_id"].as_str().unwrap();
+        assert_eq!(state.races.lock().await.entries[race_id].1.winner, "Hare");
+        assert!(state.pending_races.lock().await.entries.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn duplicate_racers_are_rejected() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let racer = serde_json::json!({ "name": "User_42", "encryption_speed": 1000.0, "vehicle": "", "trash_talk": "" });
+        let req = test::TestRequest::post()
+            .uri("/api/theater/race")
+            .set_json(serde_json::json!({ "participants": [racer, racer], "data_size": 10 }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "DUPLICATE_PARTICIPANT");
+        assert_eq!(body["error"]["details"]["name"], "User_42");
+    }
+
+    #[actix_web::test]
+    async fn oversized_races_are_rejected() {
+        let state = test_state_with(test_theater().with_max_race_bytes(1024), |_| {});
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let racer = serde_json::json!({ "name": "User_42", "encryption_speed": 1000.0, "vehicle": "", "trash_talk": "" });
+        let huge = 20_000_000_000_000_000u64;
+        let requests = [
+            ("/api/theater/race", serde_json::json!({ "participants": [racer], "data_size": huge })),
+            ("/api/theater/race", serde_json::json!({ "participants": [racer], "data_size": 1025, "stream": true })),
+            ("/api/theater/race/quick", serde_json::json!({ "user_id": 42, "data_size": huge })),
+        ];
+
+        for (uri, body) in requests {
+            let req = test::TestRequest::post().uri(uri).set_json(body).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(body["error"]["code"], "RACE_TOO_LARGE");
+            assert_eq!(body["error"]["details"]["limit"], 1024);
+        }
+        assert!(state.pending_races.lock().await.entries.is_empty());
+        assert_eq!(state.theater.lock().await.balance(42), 0);
+    }
+
+    #[actix_web::test]
+    async fn retried_encrypt_replays_the_first_result() {
+        let state = test_state_with(test_theater().with_billing(), |_| {});
+        state.theater.lock().await.credit_points(4, 10_000);
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let encrypt = |data: &str| {
+            test::TestRequest::post()
+                .uri("/api/theater/encrypt")
+                .set_json(serde_json::json!({
+                    "user_id": 4,
+                    "data": data,
+                    "level": "tinfoil",
+                    "idempotency_key": "retry-me",
+                }))
+                .to_request()
+        };
+
+        let first: serde_json::Value = test::call_and_read_body_json(&app, encrypt("once")).await;
+        let second: serde_json::Value = test::call_and_read_body_json(&app, encrypt("once")).await;
+        assert_eq!(first["success"], true);
+        assert_eq!(first, second);
+        assert_eq!(state.theater.lock().await.balance(4), 10_000 - 5000 + 2500);
+        assert!(state.metrics.render().contains("gongle_points_spent_total 5000\n"));
+
+        let resp = test::call_service(&app, encrypt("twice")).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "IDEMPOTENCY_KEY_REUSED");
+    }
+
+    #[actix_web::test]
+    async fn idempotency_keys_are_reserved_until_the_first_request_settles() {
+        let mut cache = IdempotencyCache::default();
+        let key = (4, "retry-me".to_string());
+        let fingerprint = IdempotencyCache::fingerprint(&EncryptionLevel::Basic, b"once");
+        let now = SystemTime::now();
+
+        assert!(matches!(cache.lookup(&key, &fingerprint, now), Replay::Miss));
+        assert!(matches!(cache.lookup(&key, &fingerprint, now), Replay::InFlight));
+        let other = IdempotencyCache::fingerprint(&EncryptionLevel::Basic, b"twice");
+        assert!(matches!(cache.lookup(&key, &other, now), Replay::Mismatch));
+
+        cache.release(&key);
+        assert!(matches!(cache.lookup(&key, &fingerprint, now), Replay::Miss));
+        assert!(matches!(cache.lookup(&key, &fingerprint, now + IDEMPOTENCY_TTL), Replay::Miss));
+    }
+
+    #[actix_web::test]
+    async fn race_boards_forget_old_races_and_stay_bounded() {
+        let mut board = RaceBoard::new(Duration::from_secs(60), 2);
+        let now = SystemTime::now();
+        board.insert("RACE-1".to_string(), 1, now);
+        board.insert("RACE-2".to_string(), 2, now + Duration::from_secs(1));
+        board.insert("RACE-3".to_string(), 3, now + Duration::from_secs(2));
+
+        // At capacity the oldest race made room for the newest
+        assert_eq!(board.get("RACE-1", now + Duration::from_secs(2)), None);
+        assert_eq!(board.get("RACE-3", now + Duration::from_secs(2)), Some(&3));
+        assert_eq!(board.remove("RACE-2", now + Duration::from_secs(61)), None);
+        assert_eq!(board.entries.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn unstreamed_races_expire() {
+        let clock = Arc::new(MockClock::new(SystemTime::now()));
+        let state = test_state_with(test_theater(), |state| state.clock = clock.clone());
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let racer = serde_json::json!({ "name": "User_42", "encryption_speed": 1000.0, "vehicle": "", "trash_talk": "" });
+        let req = test::TestRequest::post()
+            .uri("/api/theater/race")
+            .set_json(serde_json::json!({ "participants": [racer], "data_size": 10, "stream": true }))
+            .to_request();
+        let registered: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        clock.advance(PENDING_RACE_TTL);
+        let req = test::TestRequest::get().uri(registered["data"]["stream_url"].as_str().unwrap()).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(state.pending_races.lock().await.entries.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn encryptions_are_charged_once_they_succeed() {
+        let state = test_state_with(test_theater().with_billing(), |_| {});
+        state.theater.lock().await.credit_points(4, 150);
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let encrypt = |level: &str| {
+            test::TestRequest::post()
+                .uri("/api/theater/encrypt")
+                .set_json(serde_json::json!({ "user_id": 4, "data": "pay me", "level": level }))
+                .to_request()
+        };
+
+        assert_eq!(test::call_service(&app, encrypt("basic")).await.status(), StatusCode::OK);
+        assert_eq!(state.theater.lock().await.balance(4), 150 - 100 + 100);
+
+        let resp = test::call_service(&app, encrypt("premium")).await;
+        assert_eq!(resp.status(), StatusCode::PAYMENT_REQUIRED);
+        let theater = state.theater.lock().await;
+        assert_eq!(theater.balance(4), 150);
+        let outcomes: Vec<_> = theater.audit_log().iter().map(|entry| entry.outcome.clone()).collect();
+        assert_eq!(
+            outcomes,
+            [AuditOutcome::Success, AuditOutcome::Failed("Insufficient points: need 500, have 150".to_string())]
+        );
+    }
+
+    #[actix_web::test]
+    async fn restore_reverses_a_recent_encryption() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/encrypt")
+            .set_json(serde_json::json!({ "user_id": 4, "data": "undo me", "level": "basic" }))
+            .to_request();
+        let encrypted: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let data_id = encrypted["data"]["data_id"].as_str().unwrap();
+        let restore = || {
+            test::TestRequest::post()
+                .uri("/api/theater/restore")
+                .set_json(serde_json::json!({ "user_id": 4, "data_id": data_id }))
+                .to_request()
+        };
+
+        let restored: serde_json::Value = test::call_and_read_body_json(&app, restore()).await;
+        assert_eq!(restored["data"]["data"], base64_text::encode("undo me"));
+        assert_eq!(test::call_service(&app, restore()).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn encrypt_spam_is_rate_limited_per_user() {
+        let limit = 3;
+        let state = test_state_with(test_theater().with_encrypt_rate_limit(limit), |_| {});
+        let app = test::init_service(App::new().app_data(state).configure(configure)).await;
+        let encrypt = |user_id: u64| {
+            test::TestRequest::post()
+                .uri("/api/theater/encrypt")
+                .set_json(serde_json::json!({ "user_id": user_id, "data": "spam", "level": "basic" }))
+                .to_request()
+        };
+
+        for _ in 0..limit {
+            assert_eq!(test::call_service(&app, encrypt(1)).await.status(), StatusCode::OK);
+        }
+        let resp = test::call_service(&app, encrypt(1)).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "RATE_LIMITED");
+        assert_eq!(body["error"]["details"]["limit"], limit);
+
+        assert_eq!(test::call_service(&app, encrypt(2)).await.status(), StatusCode::OK);
+
+        let batch = |user_id: u64, size: usize| {
+            test::TestRequest::post()
+                .uri("/api/theater/encrypt/batch")
+                .set_json(serde_json::json!({ "user_id": user_id, "items": vec!["spam"; size], "level": "basic" }))
+                .to_request()
+        };
+        let resp = test::call_service(&app, batch(2, limit as usize)).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(test::call_service(&app, batch(2, limit as usize - 1)).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, encrypt(2)).await.status(), StatusCode::TOO_MANY_REQUESTS);
+        let resp = test::call_service(&app, batch(3, limit as usize + 1)).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn decrypt_spam_is_rate_limited_per_user() {
+        let limit = 2;
+        let state = test_state_with(test_theater().with_encrypt_rate_limit(limit), |_| {});
+        let ciphertext = state
+            .theater
+            .lock()
+            .await
+            .encrypt_with_drama(1, b"spam", EncryptionLevel::Basic, None)
+            .await
+            .unwrap()
+            .ciphertext;
+        let app = test::init_service(App::new().app_data(state).configure(configure)).await;
+        let decrypt = |user_id: u64| {
+            test::TestRequest::post()
+                .uri("/api/theater/decrypt")
+                .set_json(serde_json::json!({ "user_id": user_id, "ciphertext": base64_text::encode(&ciphertext) }))
+                .to_request()
+        };
+
+        // The encryption above didn't use up a decryption slot
+        for _ in 0..limit {
+            let body: serde_json::Value = test::call_and_read_body_json(&app, decrypt(1)).await;
+            assert_eq!(body["data"]["plaintext"], "spam");
+        }
+        let resp = test::call_service(&app, decrypt(1)).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "RATE_LIMITED");
+
+        // Other users have slots of their own
+        let resp = test::call_service(&app, decrypt(2)).await;
+        assert_ne!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn unknown_race_is_404() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let req = test::TestRequest::get().uri("/api/theater/race/RACE-nope").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "RACE_NOT_FOUND");
+    }
+
+    #[actix_web::test]
+    async fn threat_level_rises_with_unencrypted_data() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+
+        for (encrypted, unencrypted, severity) in [(10, 0, 1), (0, 10, 10)] {
+            let req = test::TestRequest::post()
+                .uri("/api/theater/threat_level")
+                .set_json(serde_json::json!({
+                    "user_id": 1,
+                    "encrypted_count": encrypted,
+                    "unencrypted_count": unencrypted,
+                }))
+                .to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(body["data"]["severity"], severity);
+        }
+    }
+
+    #[actix_web::test]
+    async fn healthz_answers_on_a_running_server() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = ServerConfig { workers: 1, ..ServerConfig::default() };
+        let server = serve(test_state(), &config, listener).unwrap();
+        let handle = server.handle();
+        tokio::spawn(server);
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        handle.stop(true).await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with(r#"{"status":"ok"}"#), "{}", response);
+    }
+
+    #[actix_web::test]
+    async fn shutdown_lets_in_flight_requests_finish() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Theatrics stay on so the request sits in its dramatic pause
+        let state = test_state();
+        *state.theater.lock().await = DataTheater::new().with_pbkdf2_rounds(1000).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = ServerConfig { workers: 1, shutdown_timeout_secs: 10, ..ServerConfig::default() };
+        let server = serve(state, &config, listener).unwrap();
+        let handle = server.handle();
+        let stopped = tokio::spawn(server);
+
+        let body = r#"{"user_id":1,"data":"slow","level":"premium"}"#;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "POST /api/theater/encrypt HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let stopping = tokio::spawn(handle.stop(true));
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        stopping.await.unwrap();
+        stopped.await.unwrap().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains(r#""success":true"#), "{}", response);
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn purged_user_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("achievements.json");
+        let state = test_state_with(test_theater(), |state| {
+            state.achievements_path = Some(path.clone());
+            state.admin_token = Some("hunter2".to_string());
+        });
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+
+        {
+            let mut theater = state.theater.lock().await;
+            theater.credit_points(7, 1000 + LOOT_BOX_COST);
+            theater.credit_points(8, 1000);
+            theater.purchase_encryption(7, b"paid for", EncryptionLevel::Basic).await.unwrap();
+        }
+        for (user_id, idempotency_key) in [(7, "retry-me"), (8, "keep-me")] {
+            let req = test::TestRequest::post()
+                .uri("/api/theater/encrypt")
+                .set_json(serde_json::json!({
+                    "user_id": user_id,
+                    "data": base64_text::encode(b"secret"),
+                    "level": "basic",
+                    "idempotency_key": idempotency_key,
+                }))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        }
+        let req = test::TestRequest::post()
+            .uri("/api/theater/lootbox")
+            .set_json(serde_json::json!({ "user_id": 7 }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = test::TestRequest::post()
+            .uri("/api/theater/funeral")
+            .set_json(serde_json::json!({ "user_id": 7, "data_ids": ["diary"], "funeral_type": "viking" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        state.funerals.lock().await.store(7, "diary", b"dear diary".to_vec());
+
+        let purge = |token: Option<&str>| {
+            let mut req = test::TestRequest::delete().uri("/api/theater/user/7");
+            if let Some(token) = token {
+                req = req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+            }
+            req.to_request()
+        };
+        assert_eq!(test::call_service(&app, purge(None)).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(test::call_service(&app, purge(Some("hunter3"))).await.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = test::call_service(&app, purge(Some("hunter2"))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let report = &body["data"];
+        assert_eq!((report["achievements"].as_u64(), report["audit_entries"].as_u64()), (Some(1), Some(2)));
+        assert_eq!((report["loot_box_items"].as_u64(), report["pending_funerals"].as_u64()), (Some(1), Some(1)));
+        assert_eq!(report["data_items"], 1);
+        assert_eq!(report["encrypted_items"], 2);
+
+        {
+            let theater = state.theater.lock().await;
+            assert_eq!(theater.balance(7), 0);
+            assert!(theater.collection(7).is_empty());
+            assert!(theater.audit_log().iter().all(|entry| entry.user_id != 7));
+            assert_eq!(theater.balance(8), 1000);
+        }
+        let achievements = load_achievements(&path).unwrap();
+        assert!(!achievements.contains_key(&7));
+        assert!(achievements.contains_key(&8));
+        let funerals = state.funerals.lock().await;
+        assert_eq!(funerals.pending_count(), 0);
+        assert!(funerals.get(7, "diary").is_none());
+        drop(funerals);
+        let cached: Vec<u64> = state.idempotency.lock().await.entries.keys().map(|(user_id, _)| *user_id).collect();
+        assert_eq!(cached, [8]);
+
+        let resp = test::call_service(&app, purge(Some("hunter2"))).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        assert_eq!(test::call_service(&app, purge(Some("hunter2"))).await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn export_carries_armored_ciphertexts_and_achievements() {
+        let state = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/encrypt")
+            .set_json(serde_json::json!({ "user_id": 7, "data": base64_text::encode(b"secret"), "level": "basic" }))
+            .to_request();
+        let encrypted: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let req = test::TestRequest::post()
+            .uri("/api/theater/funeral")
+            .set_json(serde_json::json!({ "user_id": 7, "data_ids": ["diary"], "funeral_type": "viking" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri("/api/theater/user/7/export").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let disposition = resp.headers().get(header::CONTENT_DISPOSITION).unwrap().to_str().unwrap();
+        assert!(disposition.starts_with("attachment"), "{}", disposition);
+        assert!(disposition.contains("gongle-user-7.json"), "{}", disposition);
+        let export: serde_json::Value = test::read_body_json(resp).await;
+
+        let items = export["encrypted_items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["data_id"], encrypted["data"]["data_id"]);
+        let armored = items[0]["armored_ciphertext"].as_str().unwrap();
+        let ciphertext = base64_text::decode(encrypted["data"]["ciphertext"].as_str().unwrap()).unwrap();
+        assert_eq!(from_armor(armored).unwrap(), ciphertext);
+        assert_eq!(export["achievements"][0]["id"], "first_basic");
+        assert_eq!(export["funerals"][0]["data_ids"][0], "diary");
+
+        let req = test::TestRequest::get().uri("/api/theater/user/8/export").to_request();
+        let stranger: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert!(stranger["encrypted_items"].as_array().unwrap().is_empty());
+        assert!(stranger["achievements"].as_array().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn session_tokens_decide_who_a_request_acts_for() {
+        let key = SessionKey::new("sekrit");
+        let state = test_state_with(test_theater(), |state| state.sessions = Some(SessionKey::new("sekrit")));
+        let app = test::init_service(App::new().app_data(state).configure(configure)).await;
+        let encrypt = |user_id: u64, token: Option<String>| {
+            let mut req = test::TestRequest::post().uri("/api/theater/encrypt").set_json(serde_json::json!({
+                "user_id": user_id,
+                "data": base64_text::encode(b"secret"),
+                "level": "basic",
+            }));
+            if let Some(token) = token {
+                req = req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token)));
+            }
+            req.to_request()
+        };
+        let in_an_hour = SystemTime::now() + Duration::from_secs(3600);
+
+        let resp = test::call_service(&app, encrypt(7, Some(key.issue(7, in_an_hour)))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["data"]["data_id"].as_str().unwrap().starts_with("GONGLE-7-"));
+
+        let expired = key.issue(7, SystemTime::now() - Duration::from_secs(1));
+        let resp = test::call_service(&app, encrypt(7, Some(expired))).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "INVALID_SESSION");
+        assert_eq!(body["error"]["details"]["reason"], "expired");
+
+        let resp = test::call_service(&app, encrypt(7, None)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = test::call_service(&app, encrypt(8, Some(key.issue(7, in_an_hour)))).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "SESSION_MISMATCH");
+        assert_eq!(body["error"]["details"]["session_user_id"], 7);
+
+        let req = test::TestRequest::get()
+            .uri("/api/theater/collection/8")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", key.issue(7, in_an_hour))))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+        // Nothing user-specific behind these, so they stay open
+        let req = test::TestRequest::get().uri("/api/theater/leaderboard").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn readyz_needs_a_reachable_achievements_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let ready = |path: PathBuf| {
+            let state = test_state_with(test_theater(), |state| state.achievements_path = Some(path));
+            async move {
+                let app = test::init_service(App::new().app_data(state).configure(configure)).await;
+                let req = test::TestRequest::get().uri("/readyz").to_request();
+                test::call_service(&app, req).await.status()
+            }
+        };
+
+        assert_eq!(ready(dir.path().join("achievements.json")).await, StatusCode::OK);
+        let status = ready(dir.path().join("gone").join("achievements.json")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn server_config_reads_the_environment() {
+        // Sessions are opted out of unless a test sets its own secret
+        let vars = |pairs: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                let insecure = &[("THEATER_INSECURE_NO_SESSIONS", "true")];
+                pairs.iter().chain(insecure).find(|(key, _)| *key == name).map(|(_, value)| value.to_string())
+            }
+        };
+
+        let config = ServerConfig::from_vars(vars(&[
+            ("THEATER_BIND", "0.0.0.0"),
+            ("THEATER_PORT", "9090"),
+            ("THEATER_WORKERS", "3"),
+        ]))
+        .unwrap();
+        assert_eq!((config.bind_addr.as_str(), config.port, config.workers), ("0.0.0.0", 9090, 3));
+        assert!(config.allowed_origins.is_empty());
+
+        let defaults = ServerConfig::from_vars(vars(&[])).unwrap();
+        assert_eq!((defaults.bind_addr.as_str(), defaults.port), ("127.0.0.1", 8080));
+        assert!(defaults.workers >= 1);
+
+        let cors = ServerConfig::from_vars(vars(&[(
+            "THEATER_ALLOWED_ORIGINS",
+            "https://gongle.example, http://localhost:5000,",
+        )]))
+        .unwrap();
+        assert_eq!(cors.allowed_origins, ["https://gongle.example", "http://localhost:5000"]);
+
+        assert!(ServerConfig::from_vars(vars(&[("THEATER_PORT", "0")])).is_err());
+        assert!(ServerConfig::from_vars(vars(&[("THEATER_MAX_BODY_BYTES", "lots")])).is_err());
+        assert!(ServerConfig::from_vars(vars(&[("THEATER_MAX_RACE_BYTES", "lots")])).is_err());
+        assert_eq!(defaults.max_race_bytes, DEFAULT_MAX_RACE_BYTES);
+        let races = ServerConfig::from_vars(vars(&[("THEATER_MAX_RACE_BYTES", "4096")])).unwrap();
+        assert_eq!(races.max_race_bytes, 4096);
+        assert!(ServerConfig::from_vars(vars(&[("THEATER_SHUTDOWN_TIMEOUT", "soon")])).is_err());
+        assert!(ServerConfig::from_vars(vars(&[("THEATER_ENCRYPTS_PER_MINUTE", "many")])).is_err());
+        let limited = ServerConfig::from_vars(vars(&[("THEATER_ENCRYPTS_PER_MINUTE", "5")])).unwrap();
+        assert_eq!(limited.encrypts_per_minute, 5);
+        assert_eq!(defaults.admin_token, None);
+        let admin = ServerConfig::from_vars(vars(&[("THEATER_ADMIN_TOKEN", "hunter2")])).unwrap();
+        assert_eq!(admin.admin_token.as_deref(), Some("hunter2"));
+        assert_eq!(defaults.session_secret, None);
+        let sessions = ServerConfig::from_vars(vars(&[("THEATER_SESSION_SECRET", "sekrit")])).unwrap();
+        assert_eq!(sessions.session_secret.as_deref(), Some("sekrit"));
+        assert!(ServerConfig::from_vars(|_| None).is_err());
+        let secured = ServerConfig::from_vars(|name| (name == "THEATER_SESSION_SECRET").then(|| "sekrit".to_string()));
+        assert!(!secured.unwrap().insecure_no_sessions);
+        assert!(ServerConfig::from_vars(vars(&[("THEATER_INSECURE_NO_SESSIONS", "yes")])).is_err());
+        let hosted = ServerConfig::from_vars(vars(&[("THEATER_BASE_URL", "https://gongle.example")])).unwrap();
+        assert_eq!(hosted.base_url.as_deref(), Some("https://gongle.example"));
+        assert!(ServerConfig::from_vars(vars(&[("THEATER_PORT", "http")])).is_err());
+        assert!(ServerConfig::from_vars(vars(&[("THEATER_WORKERS", "0")])).is_err());
+    }
+
+    #[actix_web::test]
+    async fn cors_lets_allowed_origins_in() {
+        let config = ServerConfig {
+            allowed_origins: vec!["https://gongle.example".to_string()],
+            ..ServerConfig::default()
+        };
+        let app = test::init_service(
+            App::new().wrap(config.cors()).app_data(test_state()).configure(configure),
+        )
+        .await;
+
+        for path in ["/api/theater/encrypt", "/api/theater/funeral"] {
+            let req = test::TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri(path)
+                .insert_header((header::ORIGIN, "https://gongle.example"))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type, authorization"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success(), "preflight for {} got {}", path, resp.status());
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/api/theater/economy")
+            .insert_header((header::ORIGIN, "https://gongle.example"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://gongle.example"
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/api/theater/economy")
+            .insert_header((header::ORIGIN, "https://evil.example"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[actix_web::test]
+    async fn oversized_bodies_get_413() {
+        let config = ServerConfig { max_body_bytes: 256, ..ServerConfig::default() };
+        let app = test::init_service(
+            App::new()
+                .app_data(config.json_config())
+                .app_data(config.payload_config())
+                .app_data(test_state())
+                .configure(configure),
+        )
+        .await;
+
+        let body = |size: usize| {
+            let mut body = serde_json::to_vec(&serde_json::json!({
+                "user_id": 1,
+                "data": "",
+                "level": "basic",
+            }))
+            .unwrap();
+            let padding = size - body.len();
+            body.splice(body.len() - 1..body.len() - 1, std::iter::repeat_n(b' ', padding));
+            body
+        };
+        let post = |body: Vec<u8>| {
+            test::TestRequest::post()
+                .uri("/api/theater/encrypt")
+                .insert_header(header::ContentType::json())
+                .set_payload(body)
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, post(body(256))).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(&app, post(body(257))).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let error: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(error["success"], false);
+        assert_eq!(error["error"]["code"], "PAYLOAD_TOO_LARGE");
+    }
+
+    #[actix_web::test]
+    async fn metrics_count_encryptions() {
+        let state = test_state_with(test_theater().with_billing(), |_| {});
+        state.theater.lock().await.credit_points(1, 500);
+        let app = test::init_service(App::new().app_data(state).configure(configure)).await;
+        for _ in 0..2 {
+            let req = test::TestRequest::post()
+                .uri("/api/theater/encrypt")
+                .set_json(serde_json::json!({ "user_id": 1, "data": "hi", "level": "premium" }))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        }
+
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let body = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+
+        assert!(body.contains("gongle_encryptions_total{level=\"premium\"} 2"), "{}", body);
+        assert!(body.contains("gongle_points_spent_total 1000"), "{}", body);
+        assert!(body.contains("gongle_points_awarded_total 1000"), "{}", body);
+        assert!(body.contains("gongle_crypto_seconds_count 2"), "{}", body);
+    }
+
+    #[actix_web::test]
+    async fn estimate_prices_a_level_without_spending() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+
+        let req = test::TestRequest::get().uri("/api/theater/estimate/eldritch").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"]["base_delay_ms"], 6666);
+        assert_eq!(body["data"]["cost"], 66666);
+        assert_eq!(body["data"]["expected_delay_ms"], 0);
+
+        let req = test::TestRequest::get().uri("/api/theater/estimate/ludicrous").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let error: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(error["error"]["code"], "INVALID_LEVEL");
+    }
+
+    #[actix_web::test]
+    async fn dry_run_encrypts_nothing() {
+        let state = test_state();
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/theater/encrypt")
+            .set_json(serde_json::json!({ "user_id": 1, "data": "hi", "level": "alien", "dry_run": true }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        assert_eq!(body["data"]["ciphertext"], "");
+        assert_eq!(body["data"]["achievement_id"], "first_alien");
+        assert!(!state.metrics.render().contains("gongle_encryptions_total{"));
+    }
+
+    #[actix_web::test]
+    async fn collection_counts_every_loot_box() {
+        let state = test_state();
+        state.theater.lock().await.credit_points(12, 3 * LOOT_BOX_COST);
+        let app = test::init_service(App::new().app_data(state).configure(configure)).await;
+        for _ in 0..3 {
+            let req = test::TestRequest::post()
+                .uri("/api/theater/lootbox")
+                .set_json(serde_json::json!({ "user_id": 12 }))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        }
+
+        let req = test::TestRequest::get().uri("/api/theater/collection/12").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let groups = body["data"].as_array().unwrap();
+        let total: u64 = groups.iter().map(|group| group["count"].as_u64().unwrap()).sum();
+        assert_eq!(total, 3);
+
+        let req = test::TestRequest::get().uri("/api/theater/collection/13").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"], serde_json::json!([]));
+    }
+
+    #[actix_web::test]
+    async fn loot_boxes_are_paid_for() {
+        let state = test_state();
+        state.theater.lock().await.credit_points(12, LOOT_BOX_COST + 5);
+        let app = test::init_service(App::new().app_data(state.clone()).configure(configure)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/theater/lootbox")
+            .set_json(serde_json::json!({ "user_id": 12 }))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let bonus = body["data"]["bonus"].as_u64().unwrap() as u32;
+        assert_eq!(state.theater.lock().await.balance(12), 5 + bonus);
+
+        let req = test::TestRequest::post()
+            .uri("/api/theater/lootbox")
+            .set_json(serde_json::json!({ "user_id": 13 }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYMENT_REQUIRED);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "INSUFFICIENT_POINTS");
+        assert!(state.theater.lock().await.collection(13).is_empty());
+    }
+
+    #[actix_web::test]
+    async fn leaderboard_is_sorted_and_clamped() {
+        let state = test_state();
+        {
+            let mut theater = state.theater.lock().await;
+            for user_id in 1..=120 {
+                theater.credit_points(user_id, user_id as u32 * 10);
+            }
+        }
+        let app = test::init_service(App::new().app_data(state).configure(configure)).await;
+
+        let req = test::TestRequest::get().uri("/api/theater/leaderboard?limit=500").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let board = body["data"].as_array().unwrap();
+        assert_eq!(board.len(), 100);
+        assert_eq!(board[0]["user_id"], 120);
+        assert!(board.windows(2).all(|pair| pair[0]["points"].as_u64() > pair[1]["points"].as_u64()));
+
+        let req = test::TestRequest::get().uri("/api/theater/leaderboard").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["data"].as_array().unwrap().len(), DEFAULT_LEADERBOARD_LIMIT);
+    }
+
+    #[actix_web::test]
+    async fn min_level_rejects_weaker_encryption() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let encrypt = |level: &str| {
+            test::TestRequest::post()
+                .uri("/api/theater/encrypt")
+                .set_json(serde_json::json!({ "user_id": 1, "data": "x", "level": level, "min_level": "paranoid" }))
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, encrypt("premium")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let error: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(error["error"]["code"], "LEVEL_TOO_LOW");
+        assert_eq!(error["error"]["details"], serde_json::json!({ "level": "premium", "min": "paranoid" }));
+
+        assert_eq!(test::call_service(&app, encrypt("paranoid")).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, encrypt("alien")).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn economy_lists_every_price() {
+        let app = test::init_service(App::new().app_data(test_state()).configure(configure)).await;
+        let req = test::TestRequest::get().uri("/api/theater/economy").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let table: EconomyTable = serde_json::from_value(body["data"].clone()).unwrap();
+
+        let levels: Vec<(String, u32, u32)> = table
+            .levels
+            .iter()
+            .map(|l| (format!("{:?}", l.level), l.cost, l.points))
+            .collect();
+        assert_eq!(
+            levels,
+            vec![
+                ("Basic".to_string(), 100, 100),
+                ("Premium".to_string(), 500, 500),
+                ("Paranoid".to_string(), 1000, 1000),
+                ("Tinfoil".to_string(), 5000, 2500),
+                ("Quantum".to_string(), 10000, 5000),
+                ("Alien".to_string(), 25000, 7500),
+                ("Eldritch".to_string(), 66666, 66666),
+            ]
+        );
+
+        let prices = |entries: &[crate::web_theatre::PriceEntry]| {
+            entries
+                .iter()
+                .map(|e| (e.kind.clone(), e.cost))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            prices(&table.funerals),
+            vec![
+                ("viking".to_string(), 10000),
+                ("space".to_string(), 7500),
+                ("quantum".to_string(), 15000),
+                ("eldritch".to_string(), 66666),
+            ]
+        );
+        assert_eq!(
+            prices(&table.shreds),
+            vec![
+                ("standard".to_string(), 500),
+                ("military".to_string(), 2000),
+                ("nuclear".to_string(), 5000),
+                ("blackhole".to_string(), 10000),
+            ]
+        );
+        assert_eq!(table.loot_box_cost, 1000);
+    }
+}
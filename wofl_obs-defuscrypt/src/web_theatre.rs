@@ -1,38 +1,761 @@
 // web_theater.rs - Integration module for Gongle
+use crate::armor::to_armor;
+use crate::cipher::{cipher_for, ChaCha20Cipher, TheaterCipher, CHACHA20_POLY1305_ID};
+use crate::nonce::{self, counter_nonce, KeyId, NonceStore, NonceStrategy};
+use crate::theater_config::{LevelConfig, TheaterConfig};
 use anyhow::{Context, Result};
 use chacha20poly1305::{
-    aead::{Aead, KeyInit},
-    ChaCha20Poly1305, Nonce,
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
 };
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey, SECRET_KEY_LENGTH};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
 use pbkdf2::{
     password_hash::{PasswordHasher, SaltString},
     Pbkdf2,
 };
-use rand::{rngs::OsRng, RngCore, Rng};
+use rand::{rngs::OsRng, seq::SliceRandom, CryptoRng, Rng, RngCore};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    fs,
-    io::{Read, Write},
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    net::{IpAddr, SocketAddr},
     path::Path,
+    str::FromStr,
+    sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
 };
-use zeroize::Zeroize;
+use thiserror::Error;
+use zeroize::{Zeroize, Zeroizing};
 
-/// Theatrical encryption levels with increasingly ridiculous names
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Magic bytes at the start of every theater ciphertext
+const FORMAT_MAGIC: &[u8; 4] = b"GNGL";
+// Version of the ciphertext format written by basic_encrypt
+const FORMAT_VERSION: u8 = 3;
+// Length of the magic + version + level + cipher header in bytes; from
+// version 3 the KDF and its parameters follow, and the header and salt are
+// authenticated along with the caller's associated data
+const HEADER_LENGTH: usize = 7;
+// Format version 1 had no cipher byte and always used ChaCha20-Poly1305
+const V1_HEADER_LENGTH: usize = 6;
+// KDF ids recorded in the ciphertext header
+const KDF_PBKDF2: u8 = 0;
+const KDF_ARGON2ID: u8 = 1;
+// Magic bytes at the start of a streamed file ciphertext
+const STREAM_MAGIC: &[u8; 4] = b"GNGS";
+// Version of the streamed file format written by encrypt_file; version 1
+// didn't record its KDF and always used the configured PBKDF2 rounds
+const STREAM_FORMAT_VERSION: u8 = 2;
+// Plaintext bytes per streamed chunk
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+// Poly1305 tag appended to every chunk
+const STREAM_TAG_LENGTH: usize = 16;
+// Random nonce prefix; the rest of each nonce is a chunk counter and a last-chunk flag
+const STREAM_NONCE_PREFIX_LENGTH: usize = 7;
+// Length of the salt in bytes
+const SALT_LENGTH: usize = 32;
+// Length of the nonce in bytes
+const NONCE_LENGTH: usize = 12;
+// Default number of PBKDF2 rounds for key derivation
+const DEFAULT_PBKDF2_ROUNDS: u32 = 600_000;
+// Bounds for KDF auto-tuning, so a bad timer can't pick something absurd
+const MIN_TUNED_ROUNDS: u32 = 1_000;
+const MAX_TUNED_ROUNDS: u32 = 10_000_000;
+// Most work a ciphertext header may ask of the KDF, so a crafted blob can't
+// tie up the server for minutes or exhaust its memory
+const MAX_PBKDF2_ROUNDS: u32 = 10_000_000;
+const MAX_ARGON2_MEM_KIB: u32 = 1 << 20;
+const MAX_ARGON2_ITERS: u32 = 64;
+const MAX_ARGON2_PARALLELISM: u32 = 16;
+// Largest plaintext a single encryption accepts unless configured otherwise
+const DEFAULT_MAX_INPUT_BYTES: usize = 16 * 1024 * 1024;
+/// Most racers a single race accepts unless configured otherwise
+pub const DEFAULT_MAX_RACE_PARTICIPANTS: usize = 16;
+/// Largest data_size a race is run over unless configured otherwise
+pub const DEFAULT_MAX_RACE_BYTES: usize = 1024 * 1024;
+/// Where funeral livestreams are hosted unless configured otherwise
+pub const DEFAULT_BASE_URL: &str = "https://gongle.com";
+// Items encrypted between cooperative yields in a batch
+const BATCH_YIELD_INTERVAL: usize = 4;
+// Lines appended as padding by the Paranoid level
+const PARANOID_PHRASES: [&str; 7] = [
+    "THE GOVERNMENT IS READING THIS",
+    "BIRDS AREN'T REAL",
+    "THEY'RE IN THE WALLS",
+    "TRUST NO ONE",
+    "THE MOON LANDING WAS STAGED ON MARS",
+    "5G CAUSES RAIN",
+    "ILLUMINATI CONFIRMED",
+];
+// Prefix added when a Quantum encryption collapses into its encrypted state
+const QUANTUM_COLLAPSED_TAG: &[u8] = b"QUANTUM:";
+// Flag in the header's level byte: the Quantum prefix was added and must be stripped
+const FLAG_QUANTUM_PREFIXED: u8 = 0x80;
+// Wrapper theatrical compression added before it really compressed; still read
+const COMPRESSED_PREFIX: &[u8] = b"COMPRESSED[";
+const COMPRESSED_SUFFIX: &[u8] = b"]DEFINITELY_SMALLER_NOW";
+// Bits of entropy per byte above which input already looks encrypted
+const ALREADY_ENCRYPTED_ENTROPY: f64 = 7.9;
+// Marks a theatrically compressed layer that is raw deflate underneath
+const DEFLATED_PREFIX: &[u8] = b"DEFLATED[";
+// Text cursed with zalgo and stored after Eldritch data
+const ELDRITCH_INCANTATION: &str = "Ph'nglui mglw'nafh Cthulhu R'lyeh wgah'nagl fhtagn";
+// Most combining marks the curse puts on each character unless configured otherwise
+const DEFAULT_ZALGO_INTENSITY: u8 = 3;
+// Combining marks sprinkled over Eldritch data
+const ZALGO_CHARS: [char; 8] = ['\u{308}', '\u{30e}', '\u{307}', '\u{304}', '\u{306}', '\u{310}', '\u{30c}', '\u{344}'];
+// Funerals a user may schedule per UTC day unless configured otherwise
+const DEFAULT_DAILY_FUNERAL_QUOTA: u32 = 10;
+// Sliding window the per-user encryption rate limit counts over
+const ENCRYPT_RATE_WINDOW: std::time::Duration = std::time::Duration::from_secs(60);
+// Seconds in a UTC day
+const SECONDS_PER_DAY: u64 = 86_400;
+// Number of AI opponents filled in for a quick race
+const QUICK_RACE_OPPONENTS: usize = 2;
+// Key setup every racer pays before its first byte, so small races run slower per byte
+const RACE_WARMUP_MS: u64 = 50;
+// Bytes raced per point of a quick race win, above the QUICK_RACE_PRIZE floor
+const RACE_BYTES_PER_POINT: usize = 1024;
+// How often a dramatic pause reports its progress to a caller that asked
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+// Pause before the one retry of a failed funeral webhook
+const WEBHOOK_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+// How long a funeral webhook may take to answer before it counts as failed
+const WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// How long shredded data or an encryption can still be restored unless configured otherwise
+pub const DEFAULT_RESTORE_WINDOW: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+// Encryptions kept per user for export; older ones fall off the front
+const MAX_KEPT_ENCRYPTIONS: usize = 100;
+// Oldest scheduled_time a stored funeral may have and still be replayed
+const MAX_FUNERAL_AGE: std::time::Duration = std::time::Duration::from_secs(30 * 86400);
+
+/// Theater-specific errors
+#[derive(Error, Debug)]
+pub enum TheaterError {
+    #[error("Key derivation failed")]
+    KeyDerivation,
+
+    #[error("Encryption failed")]
+    Encrypt,
+
+    #[error("Nonce already used under this key")]
+    NonceReuse,
+
+    #[error("Decryption failed")]
+    Decrypt(#[source] AuthFailed),
+
+    #[error("Not a theater ciphertext (bad magic bytes)")]
+    BadMagic,
+
+    #[error("Malformed armored ciphertext: {0}")]
+    BadArmor(&'static str),
+
+    #[error("Unsupported ciphertext format version: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Unsupported cipher in ciphertext: {0}")]
+    UnsupportedCipher(u8),
+
+    #[error("Invalid key derivation settings: {0}")]
+    InvalidKdf(String),
+
+    #[error("Unknown encryption level '{0}', expected one of: {}", EncryptionLevel::names())]
+    InvalidLevel(String),
+
+    #[error("Level {level} is below the required minimum of {min}")]
+    LevelTooLow { level: EncryptionLevel, min: EncryptionLevel },
+
+    #[error("No encryption level could decrypt this ciphertext")]
+    Undecryptable,
+
+    #[error("Ciphertext is sealed at level {found}, not {expected}")]
+    LevelMismatch { expected: EncryptionLevel, found: EncryptionLevel },
+
+    #[error("Input of {size} bytes exceeds the {limit}-byte limit")]
+    InputTooLarge { size: usize, limit: usize },
+
+    #[error("Insufficient points: need {need}, have {have}")]
+    InsufficientPoints { need: u32, have: u32 },
+
+    #[error("Rate limit of {limit} encryptions per minute reached, retry in {retry_after_secs}s")]
+    RateLimited { limit: u32, retry_after_secs: u64 },
+
+    #[error("Daily funeral quota of {limit} reached, resets at unix time {resets_at}")]
+    QuotaExceeded { limit: u32, resets_at: u64 },
+
+    #[error("Funeral parameter {field} = {value} is out of range, allowed: {allowed}")]
+    InvalidFuneralParam { field: &'static str, value: String, allowed: String },
+
+    #[error("No funeral ceremony is registered as '{0}'")]
+    UnknownCeremony(String),
+
+    #[error("No pending funeral with ceremony id '{0}'")]
+    NotFound(String),
+
+    #[error("Funeral '{0}' has already been held")]
+    AlreadyExecuted(String),
+
+    #[error("Nothing shredded as '{0}' can be restored")]
+    NothingToRestore(String),
+
+    #[error("The window to restore '{0}' has closed")]
+    RestoreWindowExpired(String),
+
+    #[error("Session token rejected: {0}")]
+    InvalidSession(&'static str),
+
+    #[error("Session belongs to user {session}, not user {claimed}")]
+    SessionMismatch { session: u64, claimed: u64 },
+
+    #[error("The theater holds nothing for user {0}")]
+    UnknownUser(u64),
+
+    #[error("A race needs at least one participant")]
+    NoParticipants,
+
+    #[error("Race has {count} participants, the limit is {limit}")]
+    TooManyParticipants { count: usize, limit: usize },
+
+    #[error("More than one participant is named '{0}'")]
+    DuplicateParticipant(String),
+
+    #[error("Race over {size} bytes exceeds the {limit}-byte limit")]
+    RaceTooLarge { size: usize, limit: usize },
+}
+
+impl TheaterError {
+    /// Stable machine-readable code for API clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            TheaterError::KeyDerivation => "KEY_DERIVATION_FAILED",
+            TheaterError::Encrypt => "ENCRYPTION_FAILED",
+            TheaterError::NonceReuse => "NONCE_REUSE",
+            TheaterError::Decrypt(_) => "DECRYPTION_FAILED",
+            TheaterError::BadMagic => "BAD_MAGIC",
+            TheaterError::BadArmor(_) => "BAD_ARMOR",
+            TheaterError::UnsupportedVersion(_) => "UNSUPPORTED_VERSION",
+            TheaterError::UnsupportedCipher(_) => "UNSUPPORTED_CIPHER",
+            TheaterError::InvalidKdf(_) => "INVALID_KDF",
+            TheaterError::InvalidLevel(_) => "INVALID_LEVEL",
+            TheaterError::LevelTooLow { .. } => "LEVEL_TOO_LOW",
+            TheaterError::Undecryptable => "UNDECRYPTABLE",
+            TheaterError::LevelMismatch { .. } => "LEVEL_MISMATCH",
+            TheaterError::InputTooLarge { .. } => "INPUT_TOO_LARGE",
+            TheaterError::InsufficientPoints { .. } => "INSUFFICIENT_POINTS",
+            TheaterError::RateLimited { .. } => "RATE_LIMITED",
+            TheaterError::QuotaExceeded { .. } => "QUOTA_EXCEEDED",
+            TheaterError::InvalidFuneralParam { .. } => "INVALID_FUNERAL_PARAM",
+            TheaterError::UnknownCeremony(_) => "UNKNOWN_CEREMONY",
+            TheaterError::NotFound(_) => "NOT_FOUND",
+            TheaterError::AlreadyExecuted(_) => "ALREADY_EXECUTED",
+            TheaterError::NothingToRestore(_) => "NOTHING_TO_RESTORE",
+            TheaterError::RestoreWindowExpired(_) => "RESTORE_WINDOW_EXPIRED",
+            TheaterError::UnknownUser(_) => "UNKNOWN_USER",
+            TheaterError::InvalidSession(_) => "INVALID_SESSION",
+            TheaterError::SessionMismatch { .. } => "SESSION_MISMATCH",
+            TheaterError::NoParticipants => "NO_PARTICIPANTS",
+            TheaterError::TooManyParticipants { .. } => "TOO_MANY_PARTICIPANTS",
+            TheaterError::DuplicateParticipant(_) => "DUPLICATE_PARTICIPANT",
+            TheaterError::RaceTooLarge { .. } => "RACE_TOO_LARGE",
+        }
+    }
+
+    /// Structured fields behind the message, for clients that want the numbers
+    fn details(&self) -> serde_json::Value {
+        match self {
+            TheaterError::UnsupportedVersion(version) => serde_json::json!({ "version": version }),
+            TheaterError::UnsupportedCipher(cipher) => serde_json::json!({ "cipher": cipher }),
+            TheaterError::InvalidLevel(level) => serde_json::json!({ "level": level }),
+            TheaterError::LevelTooLow { level, min } => {
+                serde_json::json!({ "level": level.as_str(), "min": min.as_str() })
+            },
+            TheaterError::InputTooLarge { size, limit } | TheaterError::RaceTooLarge { size, limit } => {
+                serde_json::json!({ "size": size, "limit": limit })
+            },
+            TheaterError::InsufficientPoints { need, have } => {
+                serde_json::json!({ "need": need, "have": have })
+            },
+            TheaterError::RateLimited { limit, retry_after_secs } => {
+                serde_json::json!({ "limit": limit, "retry_after_secs": retry_after_secs })
+            },
+            TheaterError::QuotaExceeded { limit, resets_at } => {
+                serde_json::json!({ "limit": limit, "resets_at": resets_at })
+            },
+            TheaterError::InvalidFuneralParam { field, value, allowed } => {
+                serde_json::json!({ "field": field, "value": value, "allowed": allowed })
+            },
+            TheaterError::NotFound(ceremony_id) | TheaterError::AlreadyExecuted(ceremony_id) => {
+                serde_json::json!({ "ceremony_id": ceremony_id })
+            },
+            TheaterError::TooManyParticipants { count, limit } => {
+                serde_json::json!({ "count": count, "limit": limit })
+            },
+            TheaterError::DuplicateParticipant(name) => serde_json::json!({ "name": name }),
+            TheaterError::UnknownCeremony(ceremony) => serde_json::json!({ "ceremony": ceremony }),
+            TheaterError::UnknownUser(user_id) => serde_json::json!({ "user_id": user_id }),
+            TheaterError::NothingToRestore(data_id) | TheaterError::RestoreWindowExpired(data_id) => {
+                serde_json::json!({ "data_id": data_id })
+            },
+            TheaterError::InvalidSession(reason) => serde_json::json!({ "reason": reason }),
+            TheaterError::SessionMismatch { session, claimed } => {
+                serde_json::json!({ "session_user_id": session, "claimed_user_id": claimed })
+            },
+            TheaterError::LevelMismatch { expected, found } => {
+                serde_json::json!({ "expected": expected.as_str(), "found": found.as_str() })
+            },
+            _ => serde_json::json!({}),
+        }
+    }
+}
+
+impl Serialize for TheaterError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("TheaterError", 3)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.serialize_field("details", &self.details())?;
+        state.end()
+    }
+}
+
+/// The ciphertext did not authenticate: wrong password, wrong owner or tampered data
+#[derive(Error, Debug)]
+#[error("authentication failed")]
+pub struct AuthFailed;
+
+/// Theatrical encryption levels with increasingly ridiculous names, ordered
+/// from Basic up to Eldritch
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum EncryptionLevel {
     Basic,      // ROT13 (just kidding, still ChaCha20)
     Premium,    // Same encryption but we tell them it's better
     Paranoid,   // Encrypt it twice for no reason
-    Tinfoil,    // Encrypt, compress, encrypt again
+    Tinfoil,    // Compress, compress again, encrypt
     Quantum,    // Adds quantum entanglement (random delays)
     Alien,      // Uses "alien technology" (XOR with 42)
     Eldritch,   // Unknowable encryption (adds zalgo text)
 }
 
-/// Funeral types for data destruction ceremonies
+impl EncryptionLevel {
+    /// Every level, from cheapest to most eldritch
+    pub const ALL: [EncryptionLevel; 7] = [
+        EncryptionLevel::Basic,
+        EncryptionLevel::Premium,
+        EncryptionLevel::Paranoid,
+        EncryptionLevel::Tinfoil,
+        EncryptionLevel::Quantum,
+        EncryptionLevel::Alien,
+        EncryptionLevel::Eldritch,
+    ];
+
+    /// (cost, points) for each level, in `ALL` order; only `TheaterConfig::default()`
+    /// reads these, everything else prices levels from the theater's config
+    pub(crate) const ECONOMY: [(u32, u32); 7] = [
+        (100, 100),
+        (500, 500),
+        (1000, 1000),
+        (5000, 2500),
+        (10000, 5000),
+        (25000, 7500),
+        (66666, 66666),
+    ];
+
+    /// Comma-separated names of every level, for error messages
+    fn names() -> String {
+        let names: Vec<&str> = EncryptionLevel::ALL.iter().map(|l| l.as_str()).collect();
+        names.join(", ")
+    }
+
+    /// Byte identifying this level in a ciphertext header
+    fn to_byte(&self) -> u8 {
+        EncryptionLevel::ALL.iter().position(|l| l == self).unwrap_or_default() as u8
+    }
+
+    /// Inverse of `to_byte`
+    fn from_byte(byte: u8) -> Option<EncryptionLevel> {
+        EncryptionLevel::ALL.get(byte as usize).cloned()
+    }
+
+    /// Lowercase name used by the frontend and the HTTP API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EncryptionLevel::Basic => "basic",
+            EncryptionLevel::Premium => "premium",
+            EncryptionLevel::Paranoid => "paranoid",
+            EncryptionLevel::Tinfoil => "tinfoil",
+            EncryptionLevel::Quantum => "quantum",
+            EncryptionLevel::Alien => "alien",
+            EncryptionLevel::Eldritch => "eldritch",
+        }
+    }
+
+    /// Whether this level is `min` or stronger
+    pub fn at_least(&self, min: EncryptionLevel) -> bool {
+        *self >= min
+    }
+
+    /// Dramatic pause before a drama factor is applied
+    pub fn base_delay_ms(&self) -> u64 {
+        match self {
+            EncryptionLevel::Basic => 100,
+            EncryptionLevel::Premium => 500,
+            EncryptionLevel::Paranoid => 1000,
+            EncryptionLevel::Tinfoil => 2000,
+            EncryptionLevel::Quantum => 3000,
+            EncryptionLevel::Alien => 4000,
+            EncryptionLevel::Eldritch => 6666,
+        }
+    }
+
+    /// Flavor text every encryption at this level reports
+    pub fn flavor_text(&self) -> &'static [&'static str] {
+        match self {
+            EncryptionLevel::Basic => &["Applied ROT13 (just kidding)", "Added blockchain dust"],
+            EncryptionLevel::Premium => &["Double-encrypted for safety", "Blessed by cyber-monks"],
+            EncryptionLevel::Paranoid => &[
+                "Wrapped in digital tin foil",
+                "Hidden from government satellites",
+                "5G-proof coating applied",
+            ],
+            EncryptionLevel::Tinfoil => &[
+                "Compressed with anxiety",
+                "Encrypted with conspiracy theories",
+                "Chemtrail-resistant layer added",
+            ],
+            EncryptionLevel::Quantum => &[
+                "Quantum entangled with parallel universe",
+                "Schrödinger's encryption applied",
+                "Observed by quantum cats",
+            ],
+            EncryptionLevel::Alien => &[
+                "Applied Area 51 technology",
+                "Translated to alien language",
+                "UFO cloaking activated",
+            ],
+            EncryptionLevel::Eldritch => &[
+                "C̸͎̈ť̶̰h̷̺̎u̸̮̇l̴̰̈h̴̬̆ṳ̶̈ ̷͇̈́f̸̱̈h̶̺̄t̶̜̔ä̶́ͅg̷̱̈ñ̶̬",
+                "Reality.exe has stopped responding",
+                "S̵̱̈́a̷̤̐n̶̜̈́i̷̦̇t̸̰̄y̷̺̌ ̸̜̇c̸̣̈h̶̰̄ë̶́ͅc̷̱̈k̸̜̇ ̷̤̈f̶̰̄ä̶́ͅi̷̦̇ḷ̸̈ë̶́ͅď̷̺",
+            ],
+        }
+    }
+}
+
+impl fmt::Display for EncryptionLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for EncryptionLevel {
+    type Err = TheaterError;
+
+    fn from_str(s: &str) -> Result<Self, TheaterError> {
+        EncryptionLevel::ALL
+            .iter()
+            .find(|level| level.as_str() == s)
+            .cloned()
+            .ok_or_else(|| TheaterError::InvalidLevel(s.to_string()))
+    }
+}
+
+/// Everything the UI and CLI show about an encryption level
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelInfo {
+    pub level: EncryptionLevel,
+    pub name: String,
+    pub emoji: char,
+    pub cost: u32,
+    pub points: u32,
+}
+
+/// Describe a level, including its display name and emoji badge, at the
+/// prices `config` charges
+pub fn describe_level(level: &EncryptionLevel, config: &TheaterConfig) -> LevelInfo {
+    let (name, emoji) = match level {
+        EncryptionLevel::Basic => ("Basic", '🔓'),
+        EncryptionLevel::Premium => ("Premium", '💎'),
+        EncryptionLevel::Paranoid => ("Paranoid", '👁'),
+        EncryptionLevel::Tinfoil => ("Tinfoil Supreme", '🎩'),
+        EncryptionLevel::Quantum => ("Quantum", '⚛'),
+        EncryptionLevel::Alien => ("Alien Technology", '👽'),
+        EncryptionLevel::Eldritch => ("Eldritch Horror", '🐙'),
+    };
+
+    LevelInfo {
+        level: level.clone(),
+        name: name.to_string(),
+        emoji,
+        cost: config.level(level).cost,
+        points: config.level(level).points,
+    }
+}
+
+/// Points charged per funeral type, keyed by the name the frontend sends
+pub const FUNERAL_COSTS: [(&str, u32); 4] = [
+    ("viking", 10000),
+    ("space", 7500),
+    ("quantum", 15000),
+    ("eldritch", 66666),
+];
+
+/// Points charged per shredding type
+pub const SHRED_COSTS: [(&str, u32); 4] = [
+    ("standard", 500),
+    ("military", 2000),
+    ("nuclear", 5000),
+    ("blackhole", 10000),
+];
+
+/// Most entries a leaderboard will return
+pub const MAX_LEADERBOARD_SIZE: usize = 100;
+
+/// Points charged to open a loot box
+pub const LOOT_BOX_COST: u32 = 1000;
+
+/// Fewest points awarded to a user who wins a quick race
+pub const QUICK_RACE_PRIZE: u32 = 1000;
+
+/// Most points a quick race can award, however much data it ran over
+pub const MAX_QUICK_RACE_PRIZE: u32 = 10_000;
+
+/// Points for winning a quick race over `data_size` bytes: one per KiB, between
+/// the flat prize and `MAX_QUICK_RACE_PRIZE`
+pub fn race_prize(data_size: usize) -> u32 {
+    let earned = u32::try_from(data_size / RACE_BYTES_PER_POINT).unwrap_or(u32::MAX);
+    earned.clamp(QUICK_RACE_PRIZE, MAX_QUICK_RACE_PRIZE)
+}
+
+/// How rare a looted algorithm is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rarity {
+    Common,
+    Uncommon,
+    Rare,
+    Epic,
+    Legendary,
+    Mythic,
+}
+
+impl Rarity {
+    /// Every rarity, from most to least common
+    pub const ALL: [Rarity; 6] = [
+        Rarity::Common,
+        Rarity::Uncommon,
+        Rarity::Rare,
+        Rarity::Epic,
+        Rarity::Legendary,
+        Rarity::Mythic,
+    ];
+
+    /// Display color for this rarity in the frontend
+    pub fn color(&self) -> &'static str {
+        match self {
+            Rarity::Common => "#808080",
+            Rarity::Uncommon => "#00FF00",
+            Rarity::Rare => "#0080FF",
+            Rarity::Epic => "#B000B0",
+            Rarity::Legendary => "#FF8000",
+            Rarity::Mythic => "#FF0080",
+        }
+    }
+
+    /// Pick a rarity from a uniform roll in `0.0..1.0` (40/30/15/10/4/1 split)
+    fn from_roll(roll: f64) -> Self {
+        if roll < 0.4 {
+            Rarity::Common
+        } else if roll < 0.7 {
+            Rarity::Uncommon
+        } else if roll < 0.85 {
+            Rarity::Rare
+        } else if roll < 0.95 {
+            Rarity::Epic
+        } else if roll < 0.99 {
+            Rarity::Legendary
+        } else {
+            Rarity::Mythic
+        }
+    }
+}
+
+/// Everything a loot box can contain, with its rarity and bonus points
+const LOOT_BOX_ALGORITHMS: [(&str, Rarity, u32); 11] = [
+    ("ROT13 Supreme Edition", Rarity::Common, 100),
+    ("Caesar Cipher Deluxe", Rarity::Common, 150),
+    ("Base64 Premium", Rarity::Common, 200),
+    ("XOR with Password \"password\"", Rarity::Uncommon, 300),
+    ("Pig Latin Encryption", Rarity::Uncommon, 400),
+    ("Reverse String Technology", Rarity::Rare, 500),
+    ("UPPERCASE ONLY MODE", Rarity::Rare, 600),
+    ("Emoji Substitution Cipher 🔐", Rarity::Epic, 1000),
+    ("Blockchain-ish Algorithm", Rarity::Legendary, 2500),
+    ("AI-Powered Nonsense", Rarity::Mythic, 5000),
+    ("Quantum Entangled ROT26", Rarity::Mythic, 10000),
+];
+
+/// What came out of a loot box
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LootBoxResult {
+    pub algorithm: String,
+    pub rarity: Rarity,
+    pub bonus: u32,
+    pub rarity_color: String,
+}
+
+/// One user's standing on the points leaderboard
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub user_id: u64,
+    pub points: u32,
+    /// The level the user has encrypted at most often, if they've encrypted at all
+    pub favorite_level: Option<EncryptionLevel>,
+}
+
+/// The algorithms of one rarity in a user's collection
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionGroup {
+    pub rarity: Rarity,
+    pub count: usize,
+    /// One entry per loot box, so duplicates appear more than once
+    pub algorithms: Vec<String>,
+}
+
+/// Group looted algorithms by rarity, most common first, leaving out empty rarities
+pub fn group_collection(collection: &[LootBoxResult]) -> Vec<CollectionGroup> {
+    Rarity::ALL
+        .iter()
+        .filter_map(|rarity| {
+            let algorithms: Vec<String> = collection
+                .iter()
+                .filter(|loot| loot.rarity == *rarity)
+                .map(|loot| loot.algorithm.clone())
+                .collect();
+            if algorithms.is_empty() {
+                return None;
+            }
+            Some(CollectionGroup { rarity: *rarity, count: algorithms.len(), algorithms })
+        })
+        .collect()
+}
+
+/// Technologies a security certificate may claim to use
+const CERTIFICATE_TECHNOLOGIES: [&str; 7] = [
+    "Alien",
+    "Time-traveling",
+    "Interdimensional",
+    "Blockchain",
+    "AI-powered",
+    "Quantum",
+    "Holographic",
+];
+
+/// Extra protection thrown in with every certificate
+const CERTIFICATE_BONUSES: [&str; 7] = [
+    "A rubber duck",
+    "Good vibes",
+    "Thoughts and prayers",
+    "A lucky penny",
+    "Mercury in retrograde",
+    "Essential oils",
+    "Crystal healing",
+];
+
+/// Threat level names and colors, from severity 1 up to 10
+const THREAT_LEVELS: [(&str, &str); 10] = [
+    ("RAINBOW UNICORN", "#FF69B4"),
+    ("DOUBLE RAINBOW", "#FF1493"),
+    ("NEON PINK", "#FF00FF"),
+    ("GLITTER BOMB", "#FFD700"),
+    ("JAZZ HANDS", "#00CED1"),
+    ("DISCO INFERNO", "#FF4500"),
+    ("PLAID ALERT", "#8B4513"),
+    ("PAISLEY PANIC", "#9370DB"),
+    ("COSMIC HORROR", "#4B0082"),
+    ("BEIGE NIGHTMARE", "#F5F5DC"),
+];
+
+/// What to do about each threat level, from severity 1 up to 10
+const THREAT_RECOMMENDATIONS: [&str; 10] = [
+    "No action needed. Pet a unicorn.",
+    "Consider wearing sunglasses indoors.",
+    "Apply glitter-resistant coating.",
+    "Jazz hands defense protocol activated.",
+    "Disco ball deflection shields up.",
+    "Switch to plaid camouflage.",
+    "Paisley pattern scrambler engaged.",
+    "Cosmic horror insurance recommended.",
+    "Reality anchor deployment suggested.",
+    "PANIC! Then have some beige tea.",
+];
+
+/// How worried a user should be about their unprotected data
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThreatLevel {
+    pub level: String,
+    pub color: String,
+    /// 1 (nothing exposed) to 10 (everything exposed)
+    pub severity: u8,
+    pub recommended_action: String,
+}
+
+/// An official-looking security certificate for a user's encrypted data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Certificate {
+    pub user_name: String,
+    pub layers: u32,
+    pub technology: String,
+    pub prayers: u32,
+    pub bonus_protection: String,
+    pub security_score: u32,
+    pub encrypted_items: u64,
+    pub certificate_id: String,
+    #[serde(with = "unix_millis")]
+    pub issued_date: SystemTime,
+    pub expiry_date: String,
+    pub signed_by: String,
+    pub quantum_signature: String,
+    /// Ed25519 signature over the rest of the certificate, base64-encoded;
+    /// see `verify_certificate`
+    pub signature: String,
+}
+
+impl Certificate {
+    /// The bytes that get signed: the certificate as JSON with sorted keys
+    /// and no `signature` field
+    fn signed_bytes(&self) -> Result<Vec<u8>> {
+        let mut value = serde_json::to_value(self)?;
+        if let Some(fields) = value.as_object_mut() {
+            fields.remove("signature");
+        }
+        Ok(serde_json::to_vec(&value)?)
+    }
+}
+
+/// Whether `certificate` carries a valid signature from the holder of `public_key`
+pub fn verify_certificate(certificate: &Certificate, public_key: &[u8; 32]) -> bool {
+    let Ok(message) = certificate.signed_bytes() else {
+        return false;
+    };
+    let Some(signature) = base64_text::decode(&certificate.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+    else {
+        return false;
+    };
+    let Ok(public_key) = VerifyingKey::from_bytes(public_key) else {
+        return false;
+    };
+    public_key.verify_strict(&message, &signature).is_ok()
+}
+
+/// Funeral types for data destruction ceremonies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
 pub enum FuneralType {
     Viking {
         longboat_size: u32,
@@ -51,452 +774,5650 @@ pub enum FuneralType {
         dimensions_breached: u32,
         sanity_cost: i32,
     },
+    /// A ceremony looked up by key in the theater's registry
+    Custom {
+        ceremony: String,
+    },
 }
 
-/// Web API response for encryption operations
-#[derive(Debug, Serialize, Deserialize)]
-pub struct EncryptionResult {
-    pub success: bool,
-    pub message: String,
-    pub data_id: String,
-    pub encryption_time_ms: u64,
-    pub theatrical_elements: Vec<String>,
-    pub points_earned: u32,
-    pub achievement_unlocked: Option<String>,
-}
-
-/// Data protection theater manager
-pub struct DataTheater {
-    /// Path to the actual encryption binary
-    encryption_binary: String,
-    /// Theatrical delay multiplier
-    drama_factor: f32,
-    /// Achievement tracker
-    achievements: HashMap<String, bool>,
-    /// Random number generator for theatrical elements
-    rng: OsRng,
-}
-
-impl DataTheater {
-    pub fn new(encryption_binary: String) -> Self {
-        Self {
-            encryption_binary,
-            drama_factor: 1.0,
-            achievements: HashMap::new(),
-            rng: OsRng,
+impl FuneralType {
+    /// Name of this funeral type as used by the frontend
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FuneralType::Viking { .. } => "viking",
+            FuneralType::Space { .. } => "space",
+            FuneralType::Quantum { .. } => "quantum",
+            FuneralType::Eldritch { .. } => "eldritch",
+            FuneralType::Custom { .. } => "custom",
         }
     }
 
-    /// Perform theatrical encryption with increasing levels of absurdity
-    pub async fn encrypt_with_drama(
-        &mut self,
-        user_id: u64,
-        data: &str,
-        level: EncryptionLevel,
-    ) -> Result<EncryptionResult> {
-        let start = SystemTime::now();
-        let mut theatrical_elements = Vec::new();
-
-        // Add theatrical delays based on level
-        let base_delay = match &level {
-            EncryptionLevel::Basic => 100,
-            EncryptionLevel::Premium => 500,
-            EncryptionLevel::Paranoid => 1000,
-            EncryptionLevel::Tinfoil => 2000,
-            EncryptionLevel::Quantum => 3000,
-            EncryptionLevel::Alien => 4000,
-            EncryptionLevel::Eldritch => 6666,
+    /// The stock ceremony for a built-in funeral name
+    pub fn stock(name: &str) -> Option<FuneralType> {
+        let stock = match name {
+            "viking" => FuneralType::Viking {
+                longboat_size: 50,
+                burning_arrows: 100,
+            },
+            "space" => FuneralType::Space {
+                trajectory: "Mars".to_string(),
+                escape_velocity: 11.2,
+            },
+            "quantum" => FuneralType::Quantum {
+                superposition: true,
+                observer_count: 42,
+            },
+            "eldritch" => FuneralType::Eldritch {
+                tentacles: 888,
+                dimensions_breached: 13,
+                sanity_cost: -9999,
+            },
+            _ => return None,
         };
+        Some(stock)
+    }
 
-        // Dramatic pause
-        tokio::time::sleep(tokio::time::Duration::from_millis(
-            (base_delay as f32 * self.drama_factor) as u64
-        )).await;
+    /// Points charged to hold this funeral
+    pub fn cost(&self) -> u32 {
+        FUNERAL_COSTS
+            .iter()
+            .find(|(kind, _)| *kind == self.kind())
+            .map(|(_, cost)| *cost)
+            .unwrap_or_default()
+    }
 
-        // Generate encryption key based on "security level"
-        let password = self.generate_theatrical_password(user_id, &level);
-        
-        // Perform actual encryption (but with theatrical modifications)
-        let encrypted_data = match level {
-            EncryptionLevel::Basic => {
-                theatrical_elements.push("Applied ROT13 (just kidding)".to_string());
-                theatrical_elements.push("Added blockchain dust".to_string());
-                self.basic_encrypt(data, &password)?
+    /// Reject ceremony parameters the theater can't stage
+    pub fn validate(&self) -> Result<(), TheaterError> {
+        match self {
+            FuneralType::Viking { longboat_size, burning_arrows } => {
+                check_funeral_param("longboat_size", *longboat_size, 1..=1_000)?;
+                check_funeral_param("burning_arrows", *burning_arrows, 0..=10_000)
             },
-            EncryptionLevel::Premium => {
-                theatrical_elements.push("Double-encrypted for safety".to_string());
-                theatrical_elements.push("Blessed by cyber-monks".to_string());
-                let first = self.basic_encrypt(data, &password)?;
-                self.basic_encrypt(&base64::encode(&first), &password)?
-            },
-            EncryptionLevel::Paranoid => {
-                theatrical_elements.push("Wrapped in digital tin foil".to_string());
-                theatrical_elements.push("Hidden from government satellites".to_string());
-                theatrical_elements.push("5G-proof coating applied".to_string());
-                
-                // Add random padding
-                let padded = format!("{}\n{}", data, self.generate_paranoid_padding());
-                self.basic_encrypt(&padded, &password)?
+            FuneralType::Space { trajectory, escape_velocity } => {
+                if trajectory.trim().is_empty() || trajectory.chars().count() > 64 {
+                    return Err(TheaterError::InvalidFuneralParam {
+                        field: "trajectory",
+                        value: format!("{:?}", trajectory),
+                        allowed: "1 to 64 characters".to_string(),
+                    });
+                }
+                // Nothing leaves faster than light, not even deleted data
+                check_funeral_param("escape_velocity", *escape_velocity, 0.0..=299_792.458)
             },
-            EncryptionLevel::Tinfoil => {
-                theatrical_elements.push("Compressed with anxiety".to_string());
-                theatrical_elements.push("Encrypted with conspiracy theories".to_string());
-                theatrical_elements.push("Chemtrail-resistant layer added".to_string());
-                
-                // Compress, encrypt, compress again (pointlessly)
-                let compressed = self.theatrical_compress(data);
-                let encrypted = self.basic_encrypt(&compressed, &password)?;
-                self.theatrical_compress(&base64::encode(&encrypted)).into_bytes()
+            FuneralType::Quantum { observer_count, .. } => {
+                check_funeral_param("observer_count", *observer_count, 0..=1_000_000)
             },
-            EncryptionLevel::Quantum => {
-                theatrical_elements.push("Quantum entangled with parallel universe".to_string());
-                theatrical_elements.push("Schrödinger's encryption applied".to_string());
-                theatrical_elements.push("Observed by quantum cats".to_string());
-                
-                // Add quantum "superposition"
-                if self.rng.gen_bool(0.5) {
-                    theatrical_elements.push("Data is encrypted AND decrypted!".to_string());
-                    self.basic_encrypt(data, &password)?
-                } else {
-                    theatrical_elements.push("Data collapsed into encrypted state".to_string());
-                    self.basic_encrypt(&format!("QUANTUM:{}", data), &password)?
-                }
+            FuneralType::Eldritch { tentacles, dimensions_breached, sanity_cost } => {
+                check_funeral_param("tentacles", *tentacles, 0..=10_000)?;
+                check_funeral_param("dimensions_breached", *dimensions_breached, 0..=1_000)?;
+                check_funeral_param("sanity_cost", *sanity_cost, -1_000_000..=0)
             },
-            EncryptionLevel::Alien => {
-                theatrical_elements.push("Applied Area 51 technology".to_string());
-                theatrical_elements.push("Translated to alien language".to_string());
-                theatrical_elements.push("UFO cloaking activated".to_string());
-                
-                // XOR with 42 (the answer to everything)
-                let alien_data = data.bytes()
-                    .map(|b| b ^ 42)
-                    .collect::<Vec<u8>>();
-                self.basic_encrypt(&base64::encode(&alien_data), &password)?
-            },
-            EncryptionLevel::Eldritch => {
-                theatrical_elements.push("C̸͎̈ť̶̰h̷̺̎u̸̮̇l̴̰̈h̴̬̆ṳ̶̈ ̷͇̈́f̸̱̈h̶̺̄t̶̜̔ä̶́ͅg̷̱̈ñ̶̬".to_string());
-                theatrical_elements.push("Reality.exe has stopped responding".to_string());
-                theatrical_elements.push("S̵̱̈́a̷̤̐n̶̜̈́i̷̦̇t̸̰̄y̷̺̌ ̸̜̇c̸̣̈h̶̰̄ë̶́ͅc̷̱̈k̸̜̇ ̷̤̈f̶̰̄ä̶́ͅi̷̦̇ḷ̸̈ë̶́ͅď̷̺".to_string());
-                
-                // Add zalgo text
-                let zalgo_data = self.add_zalgo_text(data);
-                self.basic_encrypt(&zalgo_data, &password)?
+            FuneralType::Custom { ceremony } => {
+                if ceremony.is_empty() {
+                    return Err(TheaterError::InvalidFuneralParam {
+                        field: "ceremony",
+                        value: "\"\"".to_string(),
+                        allowed: "a registered ceremony name".to_string(),
+                    });
+                }
+                Ok(())
             },
-        };
-
-        // Calculate points based on theatrical complexity
-        let points_earned = match level {
-            EncryptionLevel::Basic => 100,
-            EncryptionLevel::Premium => 500,
-            EncryptionLevel::Paranoid => 1000,
-            EncryptionLevel::Tinfoil => 2500,
-            EncryptionLevel::Quantum => 5000,
-            EncryptionLevel::Alien => 7500,
-            EncryptionLevel::Eldritch => 66666,
-        };
-
-        // Check for achievements
-        let achievement = self.check_achievements(user_id, &level);
+        }
+    }
+}
 
-        let elapsed = start.elapsed()?.as_millis() as u64;
-        
-        Ok(EncryptionResult {
-            success: true,
-            message: format!("Data encrypted with {:?} level security!", level),
-            data_id: format!("GONGLE-{}-{}", user_id, self.rng.gen::<u32>()),
-            encryption_time_ms: elapsed,
-            theatrical_elements,
-            points_earned,
-            achievement_unlocked: achievement,
-        })
+/// A kind of funeral the theater can stage. The built-in `FuneralType`s are
+/// ceremonies; others can be added with `DataTheater::register_ceremony`.
+pub trait FuneralCeremony: Send + Sync {
+    /// Words read over `data_count` items of data
+    fn epitaph(&self, data_count: usize) -> String;
+    /// Overwrite passes the data gets; drawn once, when the funeral is planned
+    fn shred_passes(&self, rng: &mut dyn RngCore) -> u32;
+    /// Whether `passes` is a count `shred_passes` could have drawn
+    fn accepts_passes(&self, _passes: u32) -> bool {
+        true
     }
+    fn special_effects(&self) -> Vec<String>;
+}
 
-    /// Schedule a data funeral with maximum drama
-    pub async fn schedule_funeral(
-        &mut self,
-        user_id: u64,
-        data_ids: Vec<String>,
-        funeral_type: FuneralType,
-    ) -> Result<FuneralSchedule> {
-        let ceremony_id = format!("FUNERAL-{}-{}", user_id, self.rng.gen::<u32>());
-        
-        let (epitaph, shred_passes, special_effects) = match &funeral_type {
-            FuneralType::Viking { longboat_size, burning_arrows } => (
-                format!("Here lies {} bytes of data. They sailed to digital Valhalla on a {}ft longboat, pierced by {} flaming arrows.", 
-                    data_ids.len() * 1024, longboat_size, burning_arrows),
-                35,
-                vec!["🔥", "⚔️", "🛡️", "⛵"],
-            ),
-            FuneralType::Space { trajectory, escape_velocity } => (
-                format!("Launched into the {} at {}km/s. Ground Control to Major Data: your circuit's dead, there's something wrong.", 
-                    trajectory, escape_velocity),
-                self.rng.gen_range(1..100),
-                vec!["🚀", "🌟", "🌌", "👨‍🚀"],
-            ),
-            FuneralType::Quantum { superposition, observer_count } => (
-                format!("This data {} in a superposition of deleted and not deleted, observed by {} quantum scientists.",
-                    if *superposition { "exists" } else { "doesn't exist" },
-                    observer_count),
-                if self.rng.gen_bool(0.5) { 0 } else { 999 },
-                vec!["🎲", "📊", "🔬", "❓"],
-            ),
-            FuneralType::Eldritch { tentacles, dimensions_breached, sanity_cost } => (
-                format!("D̸a̷t̶a̷ ̸c̶o̷n̶s̷u̸m̷e̶d̸ ̷b̶y̷ {} ̸t̶e̷n̶t̷a̸c̷l̶e̷s̸ ̷a̶c̷r̶o̷s̸s̷ {} ̷d̸i̶m̷e̶n̷s̸i̶o̷n̸s̷.̸ ̷S̶a̷n̸i̶t̷y̸ ̷c̶o̷s̸t̷:̸ {}",
-                    tentacles, dimensions_breached, sanity_cost),
-                666,
-                vec!["🐙", "🌀", "👁️", "🕸️"],
-            ),
-        };
+impl FuneralCeremony for FuneralType {
+    fn epitaph(&self, data_count: usize) -> String {
+        describe_funeral(self, data_count).epitaph
+    }
 
-        // Create memorial certificate
-        let memorial = FuneralSchedule {
-            ceremony_id,
-            user_id,
-            data_ids,
-            funeral_type,
-            scheduled_time: SystemTime::now() + std::time::Duration::from_secs(86400), // 24 hours
-            epitaph,
-            shred_passes,
-            special_effects,
-            livestream_url: format!("https://gongle.com/funerals/live/{}", self.rng.gen::<u32>()),
-            guest_list: self.generate_funeral_guests(),
-        };
+    /// Built-in pass counts are decided here and checked by `accepts_passes`
+    /// just below, which `FuneralSchedule::validate` relies on; keep the two in step
+    fn shred_passes(&self, rng: &mut dyn RngCore) -> u32 {
+        match self {
+            FuneralType::Viking { .. } => 35,
+            FuneralType::Space { .. } => rng.gen_range(1..100),
+            FuneralType::Quantum { .. } => if rng.gen_bool(0.5) { 0 } else { 999 },
+            FuneralType::Eldritch { .. } => 666,
+            // Resolved through the registry before anything is shredded
+            FuneralType::Custom { .. } => 0,
+        }
+    }
 
-        Ok(memorial)
+    fn accepts_passes(&self, passes: u32) -> bool {
+        match self {
+            FuneralType::Viking { .. } => passes == 35,
+            FuneralType::Space { .. } => (1..100).contains(&passes),
+            FuneralType::Quantum { .. } => passes == 0 || passes == 999,
+            FuneralType::Eldritch { .. } => passes == 666,
+            // Registered ceremonies pick their own passes
+            FuneralType::Custom { .. } => true,
+        }
     }
 
-    /// Basic encryption using the actual ChaCha20 implementation
-    fn basic_encrypt(&self, data: &str, password: &str) -> Result<Vec<u8>> {
-        // Generate salt
-        let mut salt = vec![0u8; 32];
-        self.rng.fill_bytes(&mut salt);
-        
-        // Derive key
-        let salt_string = SaltString::encode_b64(&salt)
-            .map_err(|_| anyhow::anyhow!("Failed to encode salt"))?;
-        
-        let key = derive_key(password, &salt)?;
-        
-        // Encrypt
-        let cipher = ChaCha20Poly1305::new(&key.into());
-        let mut nonce_bytes = [0u8; 12];
-        self.rng.fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
-        
-        let encrypted = cipher
-            .encrypt(nonce, data.as_bytes())
-            .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
-        
-        // Combine salt + nonce + encrypted data
-        let mut result = Vec::new();
-        result.extend_from_slice(&salt);
-        result.extend_from_slice(&nonce_bytes);
-        result.extend_from_slice(&encrypted);
-        
-        Ok(result)
+    fn special_effects(&self) -> Vec<String> {
+        describe_funeral(self, 0).special_effects
     }
+}
 
-    /// Generate theatrical password based on user and level
-    fn generate_theatrical_password(&self, user_id: u64, level: &EncryptionLevel) -> String {
-        match level {
-            EncryptionLevel::Basic => format!("user_{}_password123", user_id),
-            EncryptionLevel::Premium => format!("user_{}_premiumpassword!", user_id),
-            EncryptionLevel::Paranoid => format!("user_{}_they_are_watching", user_id),
-            EncryptionLevel::Tinfoil => format!("user_{}_5g_cant_penetrate_this", user_id),
-            EncryptionLevel::Quantum => format!("user_{}_schrodingers_password", user_id),
-            EncryptionLevel::Alien => format!("user_{}_area51_clearance", user_id),
-            EncryptionLevel::Eldritch => format!("user_{}_ph_nglui_mglw_nafh", user_id),
-        }
+/// Fail with `InvalidFuneralParam` unless `value` lies within `allowed`
+fn check_funeral_param<T: PartialOrd + fmt::Display>(
+    field: &'static str,
+    value: T,
+    allowed: std::ops::RangeInclusive<T>,
+) -> Result<(), TheaterError> {
+    if allowed.contains(&value) {
+        return Ok(());
     }
+    Err(TheaterError::InvalidFuneralParam {
+        field,
+        value: value.to_string(),
+        allowed: format!("{}..={}", allowed.start(), allowed.end()),
+    })
+}
 
-    /// Generate paranoid padding
-    fn generate_paranoid_padding(&mut self) -> String {
-        let paranoid_phrases = [
-            "THE GOVERNMENT IS READING THIS",
-            "BIRDS AREN'T REAL",
-            "THEY'RE IN THE WALLS",
-            "TRUST NO ONE",
-            "THE MOON LANDING WAS STAGED ON MARS",
-            "5G CAUSES RAIN",
-            "ILLUMINATI CONFIRMED",
-        ];
-        
-        let count = self.rng.gen_range(5..20);
-        (0..count)
-            .map(|_| paranoid_phrases[self.rng.gen_range(0..paranoid_phrases.len())])
-            .collect::<Vec<_>>()
-            .join("\n")
-    }
+/// Cost and reward for a single encryption level
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelEconomy {
+    pub level: EncryptionLevel,
+    pub cost: u32,
+    pub points: u32,
+}
 
-    /// Theatrical compression (doesn't actually compress)
-    fn theatrical_compress(&self, data: &str) -> String {
-        format!("COMPRESSED[{}]DEFINITELY_SMALLER_NOW", data)
-    }
+/// Price of a named funeral or shredding type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceEntry {
+    pub kind: String,
+    pub cost: u32,
+}
 
-    /// Add zalgo text for eldritch effect
-    fn add_zalgo_text(&mut self, text: &str) -> String {
-        let zalgo_chars = ['̈', '̎', '̇', '̄', '̆', '̐', '̌', '̈́'];
-        
-        text.chars()
-            .map(|c| {
-                let zalgo_count = self.rng.gen_range(1..4);
-                let mut result = String::from(c);
-                for _ in 0..zalgo_count {
-                    result.push(zalgo_chars[self.rng.gen_range(0..zalgo_chars.len())]);
-                }
-                result
+/// Every price and reward in the theater, so clients don't hardcode their own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EconomyTable {
+    pub levels: Vec<LevelEconomy>,
+    pub funerals: Vec<PriceEntry>,
+    pub shreds: Vec<PriceEntry>,
+    pub loot_box_cost: u32,
+}
+
+/// Build the economy table from a theater config and the canonical Rust tables
+pub fn economy_table(config: &TheaterConfig) -> EconomyTable {
+    let prices = |table: &[(&str, u32)]| {
+        table
+            .iter()
+            .map(|(kind, cost)| PriceEntry {
+                kind: kind.to_string(),
+                cost: *cost,
             })
             .collect()
+    };
+
+    EconomyTable {
+        levels: EncryptionLevel::ALL
+            .iter()
+            .map(|level| LevelEconomy {
+                level: level.clone(),
+                cost: config.level(level).cost,
+                points: config.level(level).points,
+            })
+            .collect(),
+        funerals: prices(&FUNERAL_COSTS),
+        shreds: prices(&SHRED_COSTS),
+        loot_box_cost: LOOT_BOX_COST,
     }
+}
 
-    /// Check for achievements
-    fn check_achievements(&mut self, user_id: u64, level: &EncryptionLevel) -> Option<String> {
-        let achievement_key = format!("{:?}_first", level);
-        
-        if !self.achievements.contains_key(&achievement_key) {
-            self.achievements.insert(achievement_key.clone(), true);
-            
-            Some(match level {
-                EncryptionLevel::Basic => "Baby's First Encryption!",
-                EncryptionLevel::Premium => "Premium Member!",
-                EncryptionLevel::Paranoid => "They're Watching!",
-                EncryptionLevel::Tinfoil => "Conspiracy Theorist!",
-                EncryptionLevel::Quantum => "Quantum Entangled!",
-                EncryptionLevel::Alien => "Area 51 Clearance!",
-                EncryptionLevel::Eldritch => "Ṃ̷̈́ä̶̤́d̸̰̈ṅ̷̺ë̶́ͅṣ̸̈š̷̱ ̸̜̇Ë̶̤́m̸̰̈ḃ̷̦ṛ̸̈ä̶́ͅč̷̺ë̸̱̇d̷̤̈!",
-            }.to_string())
-        } else {
-            None
-        }
+/// What a funeral will look like, before anyone pays for it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FuneralPreview {
+    pub epitaph: String,
+    pub special_effects: Vec<String>,
+    pub cost: u32,
+}
+
+/// Fill in the epitaph and effects for a funeral over `data_count` items
+pub fn describe_funeral(funeral_type: &FuneralType, data_count: usize) -> FuneralPreview {
+    let (epitaph, special_effects) = match funeral_type {
+        FuneralType::Viking { longboat_size, burning_arrows } => (
+            format!("Here lies {} bytes of data. They sailed to digital Valhalla on a {}ft longboat, pierced by {} flaming arrows.", 
+                data_count * 1024, longboat_size, burning_arrows),
+            vec!["🔥", "⚔️", "🛡️", "⛵"],
+        ),
+        FuneralType::Space { trajectory, escape_velocity } => (
+            format!("Launched into the {} at {}km/s. Ground Control to Major Data: your circuit's dead, there's something wrong.", 
+                trajectory, escape_velocity),
+            vec!["🚀", "🌟", "🌌", "👨‍🚀"],
+        ),
+        FuneralType::Quantum { superposition, observer_count } => (
+            format!("This data {} in a superposition of deleted and not deleted, observed by {} quantum scientists.",
+                if *superposition { "exists" } else { "doesn't exist" },
+                observer_count),
+            vec!["🎲", "📊", "🔬", "❓"],
+        ),
+        FuneralType::Eldritch { tentacles, dimensions_breached, sanity_cost } => (
+            format!("D̸a̷t̶a̷ ̸c̶o̷n̶s̷u̸m̷e̶d̸ ̷b̶y̷ {} ̸t̶e̷n̶t̷a̸c̷l̶e̷s̸ ̷a̶c̷r̶o̷s̸s̷ {} ̷d̸i̶m̷e̶n̷s̸i̶o̷n̸s̷.̸ ̷S̶a̷n̸i̶t̷y̸ ̷c̶o̷s̸t̷:̸ {}",
+                tentacles, dimensions_breached, sanity_cost),
+            vec!["🐙", "🌀", "👁️", "🕸️"],
+        ),
+        FuneralType::Custom { ceremony } => (
+            format!("Here lie {} items of data, laid to rest in a {} ceremony.", data_count, ceremony),
+            Vec::new(),
+        ),
+    };
+
+    FuneralPreview {
+        epitaph,
+        special_effects: special_effects.into_iter().map(String::from).collect(),
+        cost: funeral_type.cost(),
     }
+}
 
-    /// Generate funeral guest list
-    fn generate_funeral_guests(&mut self) -> Vec<String> {
-        let guests = [
-            "Mark Zuckerberg (via metaverse)",
-            "The Ghost of Your Privacy",
-            "Three Russian Hackers",
-            "Your FBI Agent",
-            "Cambridge Analytica (uninvited)",
-            "That Nigerian Prince",
-            "Cookie Monster (for the cookies)",
-            "The Blockchain Itself",
-            "Satoshi Nakamoto (maybe)",
-            "Your Mom (disappointed)",
-        ];
-        
-        let count = self.rng.gen_range(3..7);
-        guests.choose_multiple(&mut self.rng, count)
-            .map(|s| s.to_string())
-            .collect()
+/// Stable achievement identifiers, independent of the (possibly zalgo) display name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AchievementId {
+    FirstBasic,
+    FirstPremium,
+    FirstParanoid,
+    FirstTinfoil,
+    FirstQuantum,
+    FirstAlien,
+    FirstEldritch,
+}
+
+/// Web API response for encryption operations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionResult {
+    pub success: bool,
+    pub message: String,
+    pub data_id: String,
+    /// Wall time for the whole call, drama included
+    pub encryption_time_ms: u64,
+    /// Time spent in the dramatic pause
+    pub theatrical_time_ms: u64,
+    /// Time spent actually encrypting
+    pub real_crypto_time_ms: u64,
+    pub theatrical_elements: Vec<String>,
+    /// Points charged for this level; a billed theater deducts it
+    pub cost: u32,
+    /// Points awarded for this level; the net balance change of a purchase is
+    /// `points_earned - cost`
+    pub points_earned: u32,
+    /// Whether `cost` was actually deducted and `points_earned` credited; never
+    /// on an unbilled theater or a dry run
+    #[serde(default)]
+    pub charged: bool,
+    pub achievement_unlocked: Option<String>,
+    pub achievement_id: Option<AchievementId>,
+    /// Shannon entropy of the plaintext; close to 8 means it was random-looking already
+    pub input_entropy_bits_per_byte: f64,
+    /// `compressed_bytes / original length`, for levels that compress and
+    /// non-empty input
+    pub compression_ratio: Option<f32>,
+    /// Size of the data after compression, before encryption, for levels that compress
+    pub compressed_bytes: Option<usize>,
+    /// The encrypted bytes, base64-encoded in JSON
+    #[serde(with = "base64_bytes")]
+    pub ciphertext: Vec<u8>,
+}
+
+/// What an encryption at some level would cost and how long it would take
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Estimate {
+    pub level: EncryptionLevel,
+    pub cost: u32,
+    pub points_earned: u32,
+    /// The level's pause before the drama factor is applied
+    pub base_delay_ms: u64,
+    /// The pause this theater would actually take
+    pub expected_delay_ms: u64,
+    pub theatrical_elements: Vec<String>,
+}
+
+/// The one base64 alphabet the theater uses, so every encode has a matching decode
+pub(crate) mod base64_text {
+    use base64::{engine::general_purpose::STANDARD, DecodeError, Engine};
+
+    pub fn encode(bytes: impl AsRef<[u8]>) -> String {
+        STANDARD.encode(bytes)
+    }
+
+    pub fn decode(encoded: impl AsRef<[u8]>) -> Result<Vec<u8>, DecodeError> {
+        STANDARD.decode(encoded)
     }
 }
 
-/// Funeral schedule details
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FuneralSchedule {
-    pub ceremony_id: String,
+/// Serialize byte buffers as base64 strings
+mod base64_bytes {
+    use super::base64_text;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64_text::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64_text::decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serde adapter storing a `SystemTime` as milliseconds since the unix epoch
+mod unix_millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| serde::ser::Error::custom("time is before the unix epoch"))?
+            .as_millis();
+        serializer.serialize_u64(u64::try_from(millis).map_err(serde::ser::Error::custom)?)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let millis = u64::deserialize(deserializer)?;
+        Ok(UNIX_EPOCH + Duration::from_millis(millis))
+    }
+}
+
+/// Whether an audited operation went through
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AuditOutcome {
+    Success,
+    /// The operation was blocked, with the reason shown to support
+    Failed(String),
+}
+
+/// One entry in the points audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
     pub user_id: u64,
-    pub data_ids: Vec<String>,
-    pub funeral_type: FuneralType,
-    pub scheduled_time: SystemTime,
-    pub epitaph: String,
-    pub shred_passes: u32,
-    pub special_effects: Vec<String>,
-    pub livestream_url: String,
-    pub guest_list: Vec<String>,
+    pub operation: String,
+    pub points_delta: i64,
+    pub timestamp: SystemTime,
+    pub outcome: AuditOutcome,
 }
 
-/// Encryption race participant
+/// An encryption kept so its owner can export it later
+struct KeptEncryption {
+    data_id: String,
+    level: EncryptionLevel,
+    encrypted_at: SystemTime,
+    ciphertext: Vec<u8>,
+}
+
+/// The plaintext of a recent encryption, wiped when dropped
+struct RestorableEncryption {
+    plaintext: Zeroizing<Vec<u8>>,
+    encrypted_at: SystemTime,
+}
+
+/// One kept encryption in a `UserExport`
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct RaceParticipant {
+pub struct ExportedItem {
+    pub data_id: String,
+    pub level: EncryptionLevel,
+    #[serde(with = "unix_millis")]
+    pub encrypted_at: SystemTime,
+    /// The ciphertext as armored text, ready to paste back into decrypt
+    pub armored_ciphertext: String,
+}
+
+/// An achievement in a `UserExport`, under both its stable id and display name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedAchievement {
+    pub id: AchievementId,
     pub name: String,
-    pub encryption_speed: f64,
-    pub vehicle: String,
-    pub trash_talk: String,
 }
 
-/// Run an encryption race
-pub async fn encryption_race(
-    participants: Vec<RaceParticipant>,
-    data_size: usize,
-) -> Result<RaceResults> {
-    let mut results = Vec::new();
-    let mut rng = OsRng;
-    
-    for participant in participants {
-        // Random performance modifier
-        let performance = participant.encryption_speed * rng.gen_range(0.8..1.2);
-        let time = (data_size as f64 / performance) * 1000.0;
-        
-        results.push(RaceResult {
-            name: participant.name,
-            time_ms: time as u64,
-            vehicle: participant.vehicle,
-            victory_cry: generate_victory_cry(&mut rng),
-        });
+/// Everything the theater holds for a user, for a data export request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserExport {
+    pub user_id: u64,
+    #[serde(with = "unix_millis")]
+    pub exported_at: SystemTime,
+    pub points: u32,
+    /// The user's most recent encryptions, oldest first
+    pub encrypted_items: Vec<ExportedItem>,
+    pub collection: Vec<LootBoxResult>,
+    pub achievements: Vec<ExportedAchievement>,
+    /// Funerals still waiting to be held, filled in from the `FuneralScheduler`
+    pub funerals: Vec<FuneralSchedule>,
+}
+
+/// What `purge_user` erased for a deletion request
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PurgeReport {
+    pub achievements: usize,
+    /// Balance the user held when purged
+    pub points: u32,
+    pub encrypted_items: usize,
+    pub audit_entries: usize,
+    pub loot_box_items: usize,
+    pub cached_keys: usize,
+    /// Funerals called off, filled in by `FuneralScheduler::purge_user`
+    pub pending_funerals: usize,
+    /// Stored data items and tombstones dropped, filled in by `FuneralScheduler::purge_user`
+    pub data_items: usize,
+}
+
+/// Source of the current time, swappable so tests can travel through days
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real wall clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
     }
-    
-    results.sort_by_key(|r| r.time_ms);
-    
-    Ok(RaceResults {
-        winner: results[0].name.clone(),
-        results,
-        prize: "A golden encryption key (decorative only)".to_string(),
-    })
 }
 
-/// Race results
-#[derive(Debug, Serialize, Deserialize)]
-pub struct RaceResults {
-    pub winner: String,
-    pub results: Vec<RaceResult>,
-    pub prize: String,
+/// A clock that only moves when told to, so tests can step past deadlines without sleeping
+pub struct MockClock(std::sync::Mutex<SystemTime>);
+
+impl MockClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self(std::sync::Mutex::new(now))
+    }
+
+    /// Move the clock forward by `by`
+    pub fn advance(&self, by: std::time::Duration) {
+        *self.0.lock().unwrap() += by;
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct RaceResult {
-    pub name: String,
-    pub time_ms: u64,
-    pub vehicle: String,
-    pub victory_cry: String,
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
 }
 
-fn generate_victory_cry(rng: &mut OsRng) -> String {
-    let cries = [
-        "ENCRYPTED TO THE MOON!",
-        "EAT MY CIPHER DUST!",
-        "CHACHA20 GO BRRRRR!",
-        "WITNESS MY ENTROPY!",
-        "QUANTUM SUPREMACY ACHIEVED!",
-        "I AM THE KEY MASTER!",
-    ];
-    cries[rng.gen_range(0..cries.len())].to_string()
+/// Randomness source for salts, nonces and theatrics; `OsRng` outside of tests
+pub trait TheaterRng: RngCore + CryptoRng + Send + Sync {}
+
+impl<R: RngCore + CryptoRng + Send + Sync> TheaterRng for R {}
+
+/// Data protection theater manager
+pub struct DataTheater {
+    /// Theatrical delay multiplier
+    drama_factor: f32,
+    /// Prices, delays and flavor text per level
+    config: TheaterConfig,
+    /// Most combining marks the Eldritch curse puts on each character
+    zalgo_intensity: u8,
+    /// Largest plaintext a single encryption accepts
+    max_input_bytes: usize,
+    /// How many racers, and how much data, a single race accepts
+    race_limits: RaceLimits,
+    /// Public root of the site, without a trailing slash; livestream links hang off it
+    base_url: String,
+    /// Whether to pause dramatically and narrate; the crypto is the same either way
+    theatrics_enabled: bool,
+    /// Levels each user has already unlocked the first-time achievement for
+    achievements: HashMap<u64, HashSet<EncryptionLevel>>,
+    /// Random number generator for salts, nonces and theatrical elements
+    rng: Box<dyn TheaterRng>,
+    /// AEAD new ciphertexts are sealed with; decryption follows the header
+    cipher: Arc<dyn TheaterCipher>,
+    /// PBKDF2 rounds used when deriving encryption keys
+    kdf: Kdf,
+    /// Points balance per user
+    balances: HashMap<u64, u32>,
+    /// Every points-spending operation, successful or not
+    audit_log: Vec<AuditEntry>,
+    /// Where "now" comes from
+    clock: Arc<dyn Clock>,
+    /// Funerals each user may schedule per UTC day
+    daily_funeral_quota: u32,
+    /// Funerals scheduled per user as (UTC day number, count)
+    funeral_counts: HashMap<u64, (u64, u32)>,
+    /// Ceremonies `FuneralType::Custom` can name, by key
+    ceremonies: HashMap<String, Box<dyn FuneralCeremony>>,
+    /// Encryptions, and separately decryptions, each user may start per minute;
+    /// unlimited when unset
+    encrypt_rate_limit: Option<u32>,
+    /// Whether encryptions charge their level's cost and credit its points
+    billing: bool,
+    /// When each user's encryptions in the current window started, oldest first
+    recent_encryptions: HashMap<u64, VecDeque<SystemTime>>,
+    /// When each user's decryptions in the current window started, under the same limit
+    recent_decryptions: HashMap<u64, VecDeque<SystemTime>>,
+    /// Each user's most recent encryptions, oldest first, for export
+    kept_encryptions: HashMap<u64, VecDeque<KeptEncryption>>,
+    /// How long after an encryption `restore` can still reverse it
+    restore_window: std::time::Duration,
+    /// Plaintexts of encryptions still inside the restore window, by (user_id, data_id)
+    restorable: HashMap<(u64, String), RestorableEncryption>,
+    /// Keys derived for an in-flight batch, by (user_id, shared salt)
+    key_cache: HashMap<(u64, [u8; SALT_LENGTH]), DerivedKey>,
+    /// Successful encryptions per user and level
+    level_counts: HashMap<u64, HashMap<EncryptionLevel, u32>>,
+    /// Algorithms each user has pulled from loot boxes, oldest first
+    collections: HashMap<u64, Vec<LootBoxResult>>,
+    /// How nonces for new ciphertexts are picked
+    nonce_strategy: NonceStrategy,
+    /// Messages sealed so far under each key, for counter nonces
+    nonce_counters: HashMap<KeyId, u64>,
+    /// Nonces already used, checked before every seal when set
+    nonce_store: Option<Box<dyn NonceStore>>,
+    /// Ed25519 key certificates are signed with; wiped when dropped
+    signing_key: SigningKey,
+    /// Make encryptions fail once this many more have been planned, to exercise error paths
+    #[cfg(test)]
+    fail_encryption: Option<usize>,
 }
 
-/// Derive key from password (reusing from the main crypto module)
-fn derive_key(password: &str, salt: &[u8]) -> Result<[u8; 32]> {
-    let mut key = [0u8; 32];
-    
-    let salt_string = SaltString::encode_b64(salt)
-        .map_err(|_| anyhow::anyhow!("Salt encoding failed"))?;
-    
-    let hash = Pbkdf2
-        .hash_password_customized(
-            password.as_bytes(),
-            None,
-            None,
-            pbkdf2::Params {
-                rounds: 600_000,
-                output_length: 32,
-            },
-            &salt_string,
-        )
-        .map_err(|_| anyhow::anyhow!("Key derivation failed"))?;
-    
-    let hash_unwrapped = hash.hash.unwrap();
-    let hash_value = hash_unwrapped.as_bytes();
-    key.copy_from_slice(&hash_value[0..32]);
-    
-    Ok(key)
-}
\ No newline at end of file
+impl Default for DataTheater {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DataTheater {
+    pub fn new() -> Self {
+        let mut seed = Zeroizing::new([0u8; SECRET_KEY_LENGTH]);
+        OsRng.fill_bytes(seed.as_mut());
+        let signing_key = SigningKey::from_bytes(&seed);
+        Self {
+            drama_factor: 1.0,
+            config: TheaterConfig::default(),
+            zalgo_intensity: DEFAULT_ZALGO_INTENSITY,
+            max_input_bytes: DEFAULT_MAX_INPUT_BYTES,
+            race_limits: RaceLimits::default(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            theatrics_enabled: true,
+            achievements: HashMap::new(),
+            rng: Box::new(OsRng),
+            cipher: Arc::new(ChaCha20Cipher),
+            kdf: Kdf::Pbkdf2 { rounds: DEFAULT_PBKDF2_ROUNDS },
+            balances: HashMap::new(),
+            audit_log: Vec::new(),
+            clock: Arc::new(SystemClock),
+            daily_funeral_quota: DEFAULT_DAILY_FUNERAL_QUOTA,
+            funeral_counts: HashMap::new(),
+            ceremonies: ["viking", "space", "quantum", "eldritch"]
+                .into_iter()
+                .filter_map(|name| {
+                    let stock = FuneralType::stock(name)?;
+                    Some((name.to_string(), Box::new(stock) as Box<dyn FuneralCeremony>))
+                })
+                .collect(),
+            encrypt_rate_limit: None,
+            billing: false,
+            recent_encryptions: HashMap::new(),
+            recent_decryptions: HashMap::new(),
+            kept_encryptions: HashMap::new(),
+            restore_window: DEFAULT_RESTORE_WINDOW,
+            restorable: HashMap::new(),
+            key_cache: HashMap::new(),
+            level_counts: HashMap::new(),
+            collections: HashMap::new(),
+            nonce_strategy: NonceStrategy::default(),
+            nonce_counters: HashMap::new(),
+            nonce_store: None,
+            signing_key,
+            #[cfg(test)]
+            fail_encryption: None,
+        }
+    }
+
+    /// A theater priced and narrated by the TOML config at `path`, or by the
+    /// built-in defaults if there's no file there
+    pub fn from_config(path: &Path) -> Result<Self> {
+        Self::new().with_config(TheaterConfig::load(path)?)
+    }
+
+    /// Replace the per-level prices, delays and flavor text
+    pub fn with_config(mut self, config: TheaterConfig) -> Result<Self> {
+        config.validate()?;
+        self.config = config;
+        Ok(self)
+    }
+
+    /// The per-level prices, delays and flavor text in use
+    pub fn config(&self) -> &TheaterConfig {
+        &self.config
+    }
+
+    /// Put up to `intensity` combining marks on each character of the Eldritch
+    /// curse; 0 leaves it plain
+    pub fn with_zalgo_intensity(mut self, intensity: u8) -> Self {
+        self.zalgo_intensity = intensity;
+        self
+    }
+
+    /// Refuse to encrypt any single input larger than `limit` bytes
+    pub fn with_max_input_bytes(mut self, limit: usize) -> Self {
+        self.max_input_bytes = limit;
+        self
+    }
+
+    /// Refuse races with more than `limit` participants
+    pub fn with_max_race_participants(mut self, limit: usize) -> Self {
+        self.race_limits.participants = limit;
+        self
+    }
+
+    /// Refuse races over more than `limit` bytes of data
+    pub fn with_max_race_bytes(mut self, limit: usize) -> Self {
+        self.race_limits.data_size = limit;
+        self
+    }
+
+    /// How many participants, and how much data, a race may have
+    pub fn race_limits(&self) -> RaceLimits {
+        self.race_limits
+    }
+
+    /// Build livestream links under `base_url`, e.g. "https://gongle.example/theater",
+    /// for anyone not hosting at gongle.com
+    pub fn with_base_url(mut self, base_url: &str) -> Result<Self> {
+        let url = reqwest::Url::parse(base_url).with_context(|| format!("Invalid base URL {:?}", base_url))?;
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            anyhow::bail!("Base URL {:?} must be an http:// or https:// URL with a host", base_url);
+        }
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        Ok(self)
+    }
+
+    /// Scale every dramatic pause; see `set_drama_factor` for what's accepted
+    pub fn with_drama_factor(mut self, drama_factor: f32) -> Result<Self> {
+        self.set_drama_factor(drama_factor)?;
+        Ok(self)
+    }
+
+    /// Scale every dramatic pause by a finite, positive factor.
+    ///
+    /// Use `without_theatrics` to skip the pauses altogether.
+    pub fn set_drama_factor(&mut self, factor: f32) -> Result<()> {
+        if !factor.is_finite() || factor <= 0.0 {
+            anyhow::bail!("Drama factor must be finite and positive, got {}", factor);
+        }
+        self.drama_factor = factor;
+        Ok(())
+    }
+
+    /// Sober mode for pipelines: no dramatic pauses and no theatrical elements
+    pub fn without_theatrics(mut self) -> Self {
+        self.theatrics_enabled = false;
+        self
+    }
+
+    /// Start from the achievements saved at `path` (none if it doesn't exist yet)
+    pub fn with_achievements_from(mut self, path: &Path) -> Result<Self> {
+        self.achievements = load_achievements(path)?;
+        Ok(self)
+    }
+
+    /// Write every user's unlocked achievements to `path` as JSON
+    pub fn save_achievements(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec_pretty(&self.achievements)?;
+        fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Use a different RNG, e.g. a seeded one for reproducible tests
+    pub fn with_rng(mut self, rng: impl TheaterRng + 'static) -> Self {
+        self.rng = Box::new(rng);
+        self
+    }
+
+    /// Seal new ciphertexts with a different cipher
+    pub fn with_cipher(mut self, cipher: impl TheaterCipher + 'static) -> Self {
+        self.cipher = Arc::new(cipher);
+        self
+    }
+
+    /// Refuse to seal under a (key, nonce) pair `store` has seen before
+    pub fn with_nonce_store(mut self, store: impl NonceStore + 'static) -> Self {
+        self.nonce_store = Some(Box::new(store));
+        self
+    }
+
+    /// Pick nonces randomly or from a per-key counter; see `crate::nonce`
+    pub fn with_nonce_strategy(mut self, strategy: NonceStrategy) -> Self {
+        self.nonce_strategy = strategy;
+        self
+    }
+
+    /// Use a different clock, e.g. a manual one in tests
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Let encryptions be reversed for `window` instead of the default five
+    /// minutes; zero keeps no plaintexts at all
+    pub fn with_restore_window(mut self, window: std::time::Duration) -> Self {
+        self.restore_window = window;
+        self
+    }
+
+    /// Sign certificates with the key saved at `path`, generating and saving
+    /// a new one if it doesn't exist yet
+    pub fn with_signing_key_from(mut self, path: &Path) -> Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => {
+                let bytes = Zeroizing::new(bytes);
+                let seed = <[u8; SECRET_KEY_LENGTH]>::try_from(bytes.as_slice()).map(Zeroizing::new).map_err(|_| {
+                    anyhow::anyhow!(
+                        "Signing key in {} is {} bytes, expected {}",
+                        path.display(),
+                        bytes.len(),
+                        SECRET_KEY_LENGTH
+                    )
+                })?;
+                self.signing_key = SigningKey::from_bytes(&seed);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut seed = Zeroizing::new([0u8; SECRET_KEY_LENGTH]);
+                self.rng.fill_bytes(seed.as_mut());
+                let mut options = OpenOptions::new();
+                options.write(true).create_new(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                options
+                    .open(path)
+                    .and_then(|mut file| file.write_all(seed.as_ref()))
+                    .with_context(|| format!("Failed to write signing key to {}", path.display()))?;
+                self.signing_key = SigningKey::from_bytes(&seed);
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+        Ok(self)
+    }
+
+    /// Public key that verifies this theater's certificates
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    /// Change how many funerals each user may schedule per UTC day
+    pub fn with_daily_funeral_quota(mut self, quota: u32) -> Self {
+        self.daily_funeral_quota = quota;
+        self
+    }
+
+    /// Let each user start at most `per_minute` encryptions, and as many
+    /// decryptions through `decrypt_shared`, in any sliding minute
+    pub fn with_encrypt_rate_limit(mut self, per_minute: u32) -> Self {
+        self.encrypt_rate_limit = Some(per_minute);
+        self
+    }
+
+    /// Charge every encryption its level's cost once it succeeds and credit its
+    /// points, turning away users who can't pay
+    pub fn with_billing(mut self) -> Self {
+        self.billing = true;
+        self
+    }
+
+    /// Make `ceremony` available to funerals as `FuneralType::Custom { ceremony: key }`,
+    /// replacing any ceremony already registered under `key`
+    pub fn register_ceremony(&mut self, key: impl Into<String>, ceremony: Box<dyn FuneralCeremony>) {
+        self.ceremonies.insert(key.into(), ceremony);
+    }
+
+    /// Use a custom number of PBKDF2 rounds (1 to 10 million)
+    pub fn with_pbkdf2_rounds(self, rounds: u32) -> Result<Self> {
+        self.with_kdf(Kdf::Pbkdf2 { rounds })
+    }
+
+    /// Derive keys for new ciphertexts with `kdf`; decryption follows the header
+    pub fn with_kdf(mut self, kdf: Kdf) -> Result<Self> {
+        kdf.validate()?;
+        self.kdf = kdf;
+        Ok(self)
+    }
+
+    /// PBKDF2 rounds for formats that don't record their KDF: the configured
+    /// rounds, or the default when another KDF is configured
+    fn pbkdf2_rounds(&self) -> u32 {
+        match self.kdf {
+            Kdf::Pbkdf2 { rounds } => rounds,
+            Kdf::Argon2id { .. } => DEFAULT_PBKDF2_ROUNDS,
+        }
+    }
+
+    /// Perform theatrical encryption with increasing levels of absurdity.
+    /// Empty `data` is encrypted like any other: every level produces a real
+    /// ciphertext that decrypts back to nothing. `progress`, if given, hears the
+    /// fraction of the dramatic pause that has passed every 200ms, ending at 1.0.
+    #[tracing::instrument(skip(self, data, progress), fields(bytes = data.len()))]
+    pub async fn encrypt_with_drama(
+        &mut self,
+        user_id: u64,
+        data: &[u8],
+        level: EncryptionLevel,
+        progress: Option<&(dyn Fn(f32) + Send + Sync)>,
+    ) -> Result<EncryptionResult> {
+        let checked = self
+            .check_input_size(data.len())
+            .and_then(|()| self.check_balance(user_id, &level, 1))
+            .and_then(|()| self.claim_encrypt_slot(user_id));
+        if let Err(e) = checked {
+            let result = Err(e.into());
+            self.audit_failed_encryption(user_id, &level, &result);
+            trace_outcome("encryption", &result);
+            return result;
+        }
+        let start = tokio::time::Instant::now();
+        let timing = Timing { start, theatrical: self.dramatic_pause(&level, progress).await };
+        let result = self.perform_encryption(user_id, data, level.clone(), timing, &Salting::Fresh, None).await;
+        self.audit_failed_encryption(user_id, &level, &result);
+        trace_outcome("encryption", &result);
+        result
+    }
+
+    /// `encrypt_with_drama` on a theater shared between requests. The lock is
+    /// only held to draw randomness, claim nonces and score the result; the
+    /// dramatic pause, key derivation and sealing run without it, so concurrent
+    /// encryptions overlap instead of queueing behind each other.
+    #[tracing::instrument(skip(theater, data), fields(bytes = data.len()))]
+    pub async fn encrypt_shared(
+        theater: &tokio::sync::Mutex<DataTheater>,
+        user_id: u64,
+        data: &[u8],
+        level: EncryptionLevel,
+    ) -> Result<EncryptionResult> {
+        let result = Self::encrypt_unlocked(theater, user_id, data, level.clone()).await;
+        if result.is_err() {
+            theater.lock().await.audit_failed_encryption(user_id, &level, &result);
+        }
+        trace_outcome("encryption", &result);
+        result
+    }
+
+    async fn encrypt_unlocked(
+        theater: &tokio::sync::Mutex<DataTheater>,
+        user_id: u64,
+        data: &[u8],
+        level: EncryptionLevel,
+    ) -> Result<EncryptionResult> {
+        let start = tokio::time::Instant::now();
+        let (plan, delay_ms) = {
+            let mut theater = theater.lock().await;
+            theater.check_input_size(data.len())?;
+            theater.check_balance(user_id, &level, 1)?;
+            theater.claim_encrypt_slot(user_id)?;
+            let plan = theater.plan_encryption(user_id, &level, &Salting::Fresh, None)?;
+            (plan, theater.expected_delay_ms(&level))
+        };
+        let timing = Timing { start, theatrical: pause_for(delay_ms, None).await };
+
+        let crypto_started = std::time::Instant::now();
+        let keys = plan.derive_keys().await?;
+        let nonces = theater.lock().await.claim_nonces(&plan, &keys)?;
+        let sealed = plan.seal(data, &keys, &nonces)?;
+        let crypto_time = crypto_started.elapsed();
+
+        Ok(theater.lock().await.finish_encryption(plan, data, sealed, crypto_time, timing)?)
+    }
+
+    /// The result `encrypt_with_drama` would give, minus the ciphertext: no crypto,
+    /// no dramatic pause, and no achievements unlocked
+    pub fn dry_run_encryption(&mut self, user_id: u64, level: EncryptionLevel) -> EncryptionResult {
+        let estimate = self.estimate(&level);
+        let (achievement_id, achievement) = self.peek_achievement(user_id, &level).unzip();
+
+        EncryptionResult {
+            success: true,
+            message: format!("Dry run: data would be encrypted with {:?} level security!", level),
+            data_id: format!("GONGLE-{}-{}", user_id, self.rng.gen::<u32>()),
+            encryption_time_ms: 0,
+            theatrical_time_ms: 0,
+            real_crypto_time_ms: 0,
+            theatrical_elements: estimate.theatrical_elements,
+            cost: estimate.cost,
+            points_earned: estimate.points_earned,
+            charged: false,
+            achievement_unlocked: achievement,
+            achievement_id,
+            input_entropy_bits_per_byte: 0.0,
+            compression_ratio: None,
+            compressed_bytes: None,
+            ciphertext: Vec::new(),
+        }
+    }
+
+    /// Encrypt many items behind a single dramatic pause.
+    ///
+    /// Every item in the batch shares one random salt, so PBKDF2 runs once per
+    /// batch instead of once per item. Nonces stay random per item, so the AEAD
+    /// is still sound, but the trade-off is real: the items share a key (leaking
+    /// one key exposes the whole batch) and the identical salt shows they were
+    /// encrypted together. Single-item calls keep a fresh salt each time.
+    ///
+    /// The batch yields back to the runtime every few items instead of
+    /// monopolizing the worker thread.
+    pub async fn encrypt_batch(
+        &mut self,
+        user_id: u64,
+        items: &[String],
+        level: EncryptionLevel,
+    ) -> Result<Vec<EncryptionResult>> {
+        self.run_batch(user_id, items, level, None).await
+    }
+
+    /// `encrypt_batch` under a caller-chosen password instead of the theatrical one
+    pub async fn batch_encrypt(
+        &mut self,
+        user_id: u64,
+        items: Vec<String>,
+        level: EncryptionLevel,
+        password: &str,
+    ) -> Result<Vec<EncryptionResult>> {
+        self.run_batch(user_id, &items, level, Some(password)).await
+    }
+
+    async fn run_batch(
+        &mut self,
+        user_id: u64,
+        items: &[String],
+        level: EncryptionLevel,
+        password: Option<&str>,
+    ) -> Result<Vec<EncryptionResult>> {
+        for item in items {
+            self.check_input_size(item.len())?;
+        }
+        self.check_balance(user_id, &level, items.len())?;
+        self.claim_encrypt_slots(user_id, items.len())?;
+        let start = tokio::time::Instant::now();
+        let timing = Timing { start, theatrical: self.dramatic_pause(&level, None).await };
+
+        let (salt, _) = self.fresh_salt_and_nonce();
+        let salting = Salting::Batch { user_id, salt };
+        let results = self
+            .encrypt_items(user_id, items, &level, timing, &salting, password)
+            .await;
+        self.key_cache.remove(&(user_id, salt));
+
+        results
+    }
+
+    async fn encrypt_items(
+        &mut self,
+        user_id: u64,
+        items: &[String],
+        level: &EncryptionLevel,
+        timing: Timing,
+        salting: &Salting,
+        password: Option<&str>,
+    ) -> Result<Vec<EncryptionResult>> {
+        let mut sealed = Vec::with_capacity(items.len());
+        for chunk in items.chunks(BATCH_YIELD_INTERVAL) {
+            for item in chunk {
+                let (plan, item_sealed, crypto_time) =
+                    self.seal_encryption(user_id, item.as_bytes(), level.clone(), salting, password).await?;
+                sealed.push((plan, item.as_bytes(), item_sealed, crypto_time));
+            }
+            tokio::task::yield_now().await;
+        }
+
+        // Charge for the whole batch only once every item has sealed, so a failure part way costs nothing
+        self.charge_encryptions(user_id, level, sealed.len())?;
+        Ok(sealed
+            .into_iter()
+            .map(|(plan, data, sealed, crypto_time)| {
+                self.score_encryption(plan, data, sealed, crypto_time, timing)
+            })
+            .collect())
+    }
+
+    /// `encrypt_batch`, or `batch_encrypt` given a password, on a theater shared
+    /// between requests. Like `encrypt_shared`, the lock is only held to plan
+    /// the items, claim their nonces and charge and score them; the dramatic
+    /// pause, the batch's one key derivation and the sealing run without it.
+    #[tracing::instrument(skip(theater, items, password), fields(items = items.len()))]
+    pub async fn encrypt_batch_shared(
+        theater: &tokio::sync::Mutex<DataTheater>,
+        user_id: u64,
+        items: &[String],
+        level: EncryptionLevel,
+        password: Option<&str>,
+    ) -> Result<Vec<EncryptionResult>> {
+        let result = Self::encrypt_batch_unlocked(theater, user_id, items, level.clone(), password).await;
+        if result.is_err() {
+            theater.lock().await.audit_failed_encryption(user_id, &level, &result);
+        }
+        trace_outcome("batch encryption", &result);
+        result
+    }
+
+    async fn encrypt_batch_unlocked(
+        theater: &tokio::sync::Mutex<DataTheater>,
+        user_id: u64,
+        items: &[String],
+        level: EncryptionLevel,
+        password: Option<&str>,
+    ) -> Result<Vec<EncryptionResult>> {
+        let start = tokio::time::Instant::now();
+        let (plans, delay_ms) = {
+            let mut theater = theater.lock().await;
+            for item in items {
+                theater.check_input_size(item.len())?;
+            }
+            theater.check_balance(user_id, &level, items.len())?;
+            theater.claim_encrypt_slots(user_id, items.len())?;
+            let (salt, _) = theater.fresh_salt_and_nonce();
+            let salting = Salting::Batch { user_id, salt };
+            let plans = items
+                .iter()
+                .map(|_| theater.plan_encryption(user_id, &level, &salting, password))
+                .collect::<Result<Vec<_>, _>>()?;
+            (plans, theater.expected_delay_ms(&level))
+        };
+        let timing = Timing { start, theatrical: pause_for(delay_ms, None).await };
+
+        // Every plan shares the batch's salt and password, so one derivation keys them all
+        let Some(first) = plans.first() else {
+            return Ok(Vec::new());
+        };
+        let derive_started = std::time::Instant::now();
+        let key = derive_key_offloaded(&first.password, first.layers[0].0, first.kdf).await?;
+        let derive_time = derive_started.elapsed();
+        let keys = |plan: &EncryptionPlan| vec![key.clone(); plan.layers.len()];
+        let nonces = {
+            let mut theater = theater.lock().await;
+            plans
+                .iter()
+                .map(|plan| theater.claim_nonces(plan, &keys(plan)))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+
+        // The first item's crypto time includes the derivation, as in `encrypt_batch`
+        let mut sealed = Vec::with_capacity(items.len());
+        for (index, (plan, nonces)) in plans.iter().zip(&nonces).enumerate() {
+            let seal_started = std::time::Instant::now();
+            let item_sealed = plan.seal(items[index].as_bytes(), &keys(plan), nonces)?;
+            let crypto_time = seal_started.elapsed() + if index == 0 { derive_time } else { Default::default() };
+            sealed.push((item_sealed, crypto_time));
+            if (index + 1) % BATCH_YIELD_INTERVAL == 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        // Charge for the whole batch only once every item has sealed, so a failure part way costs nothing
+        let mut theater = theater.lock().await;
+        theater.charge_encryptions(user_id, &level, plans.len())?;
+        Ok(plans
+            .into_iter()
+            .zip(sealed)
+            .zip(items)
+            .map(|((plan, (sealed, crypto_time)), item)| {
+                theater.score_encryption(plan, item.as_bytes(), sealed, crypto_time, timing)
+            })
+            .collect())
+    }
+
+    /// On a billed theater, turn away a user who can't pay for `count`
+    /// encryptions at `level` before any work is done
+    fn check_balance(&self, user_id: u64, level: &EncryptionLevel, count: usize) -> Result<(), TheaterError> {
+        if !self.billing {
+            return Ok(());
+        }
+        let need = self.config.level(level).cost.saturating_mul(u32::try_from(count).unwrap_or(u32::MAX));
+        let have = self.balance(user_id);
+        if have < need {
+            return Err(TheaterError::InsufficientPoints { need, have });
+        }
+        Ok(())
+    }
+
+    /// Reject inputs that would make the level transforms balloon
+    fn check_input_size(&self, size: usize) -> Result<(), TheaterError> {
+        if size > self.max_input_bytes {
+            return Err(TheaterError::InputTooLarge { size, limit: self.max_input_bytes });
+        }
+        Ok(())
+    }
+
+    /// Sleep for the level's theatrical delay, returning how long it took
+    async fn dramatic_pause(
+        &self,
+        level: &EncryptionLevel,
+        progress: Option<&(dyn Fn(f32) + Send + Sync)>,
+    ) -> std::time::Duration {
+        if !self.theatrics_enabled {
+            return std::time::Duration::ZERO;
+        }
+        pause_for(self.expected_delay_ms(level), progress).await
+    }
+
+    /// Length of the level's dramatic pause, drama factor included
+    fn expected_delay_ms(&self, level: &EncryptionLevel) -> u64 {
+        if !self.theatrics_enabled {
+            return 0;
+        }
+        (self.config.level(level).base_delay_ms as f32 * self.drama_factor) as u64
+    }
+
+    /// Cost, points and pause of an encryption at `level`, without encrypting
+    /// anything or touching a balance
+    pub fn estimate(&self, level: &EncryptionLevel) -> Estimate {
+        let LevelConfig { cost, points, base_delay_ms, elements } = self.config.level(level);
+        let theatrical_elements = if self.theatrics_enabled { elements.clone() } else { Vec::new() };
+
+        Estimate {
+            level: level.clone(),
+            cost: *cost,
+            points_earned: *points,
+            base_delay_ms: *base_delay_ms,
+            expected_delay_ms: self.expected_delay_ms(level),
+            theatrical_elements,
+        }
+    }
+
+    /// The non-theatrical half of an encryption: transforms, crypto and scoring
+    async fn perform_encryption(
+        &mut self,
+        user_id: u64,
+        data: &[u8],
+        level: EncryptionLevel,
+        timing: Timing,
+        salting: &Salting,
+        password: Option<&str>,
+    ) -> Result<EncryptionResult> {
+        let (plan, sealed, crypto_time) = self.seal_encryption(user_id, data, level, salting, password).await?;
+        Ok(self.finish_encryption(plan, data, sealed, crypto_time, timing)?)
+    }
+
+    /// Plan and seal one encryption, without charging or scoring it yet
+    async fn seal_encryption(
+        &mut self,
+        user_id: u64,
+        data: &[u8],
+        level: EncryptionLevel,
+        salting: &Salting,
+        password: Option<&str>,
+    ) -> Result<(EncryptionPlan, Sealed, std::time::Duration)> {
+        let plan = self.plan_encryption(user_id, &level, salting, password)?;
+
+        let crypto_started = std::time::Instant::now();
+        let keys = match salting {
+            Salting::Fresh => plan.derive_keys().await?,
+            Salting::Batch { user_id, salt } => {
+                let key = self.derive_key_cached(*user_id, &plan.password, *salt).await?;
+                vec![key; plan.layers.len()]
+            },
+        };
+        let nonces = self.claim_nonces(&plan, &keys)?;
+        let sealed = plan.seal(data, &keys, &nonces)?;
+        Ok((plan, sealed, crypto_started.elapsed()))
+    }
+
+    /// Draw everything an encryption needs from the theater: its password, the
+    /// level's random flourishes and a salt and nonce per sealed layer
+    fn plan_encryption(
+        &mut self,
+        user_id: u64,
+        level: &EncryptionLevel,
+        salting: &Salting,
+        password: Option<&str>,
+    ) -> Result<EncryptionPlan, TheaterError> {
+        #[cfg(test)]
+        if let Some(remaining) = self.fail_encryption.as_mut() {
+            if *remaining == 0 {
+                return Err(TheaterError::Encrypt);
+            }
+            *remaining -= 1;
+        }
+
+        let mut theatrical_elements = self.config.level(level).elements.clone();
+        // Generate encryption key based on "security level", unless the caller brought one
+        let password = Zeroizing::new(match password {
+            Some(password) => password.to_string(),
+            None => self.generate_theatrical_password(user_id, level),
+        });
+        let transform = match level {
+            EncryptionLevel::Basic => Transform::None,
+            EncryptionLevel::Premium => Transform::SealTwice,
+            // Add random padding
+            EncryptionLevel::Paranoid => Transform::Trailer(self.generate_paranoid_padding()),
+            EncryptionLevel::Tinfoil => Transform::Compress,
+            // Add quantum "superposition"
+            EncryptionLevel::Quantum if self.rng.gen_bool(0.5) => {
+                theatrical_elements.push("Data is encrypted AND decrypted!".to_string());
+                Transform::None
+            },
+            EncryptionLevel::Quantum => {
+                theatrical_elements.push("Data collapsed into encrypted state".to_string());
+                Transform::Collapse
+            },
+            EncryptionLevel::Alien => Transform::Alien,
+            // Add zalgo text after the data, where it can't corrupt binary input
+            EncryptionLevel::Eldritch => Transform::Trailer(self.add_zalgo_text(ELDRITCH_INCANTATION)),
+        };
+        let layer_count = if matches!(transform, Transform::SealTwice) { 2 } else { 1 };
+        let layers = (0..layer_count)
+            .map(|_| {
+                let (fresh_salt, nonce) = self.fresh_salt_and_nonce();
+                match salting {
+                    Salting::Fresh => (fresh_salt, nonce),
+                    Salting::Batch { salt, .. } => (*salt, nonce),
+                }
+            })
+            .collect();
+
+        Ok(EncryptionPlan {
+            user_id,
+            level: level.clone(),
+            password,
+            cipher: self.cipher.clone(),
+            kdf: self.kdf,
+            transform,
+            layers,
+            theatrical_elements,
+        })
+    }
+
+    /// The nonce each of a plan's layers is sealed with, claimed against the
+    /// counters and nonce store
+    fn claim_nonces(
+        &mut self,
+        plan: &EncryptionPlan,
+        keys: &[DerivedKey],
+    ) -> Result<Vec<[u8; NONCE_LENGTH]>, TheaterError> {
+        keys.iter()
+            .zip(&plan.layers)
+            .map(|(key, (_, random))| self.claim_nonce(key, *random))
+            .collect()
+    }
+
+    /// Charge a sealed encryption on a billed theater and score it
+    fn finish_encryption(
+        &mut self,
+        plan: EncryptionPlan,
+        data: &[u8],
+        sealed: Sealed,
+        crypto_time: std::time::Duration,
+        timing: Timing,
+    ) -> Result<EncryptionResult, TheaterError> {
+        self.charge_encryptions(plan.user_id, &plan.level, 1)?;
+        Ok(self.score_encryption(plan, data, sealed, crypto_time, timing))
+    }
+
+    /// On a billed theater, charge `count` sealed encryptions at `level` all at
+    /// once and credit their points, auditing each; nothing is charged if the
+    /// balance no longer covers them all
+    fn charge_encryptions(&mut self, user_id: u64, level: &EncryptionLevel, count: usize) -> Result<(), TheaterError> {
+        if !self.billing {
+            return Ok(());
+        }
+        let LevelConfig { cost, points, .. } = *self.config.level(level);
+        let times = u32::try_from(count).unwrap_or(u32::MAX);
+        // The balance may have moved since the up-front check while the lock was released
+        self.debit_points(user_id, cost.saturating_mul(times))?;
+        self.credit_points(user_id, points.saturating_mul(times));
+        let points_delta = points as i64 - cost as i64;
+        for _ in 0..count {
+            self.record_audit(user_id, format!("encrypt:{:?}", level), points_delta, AuditOutcome::Success);
+        }
+        Ok(())
+    }
+
+    /// Score a charged encryption: achievements, counts, a kept copy for export
+    /// and, while the restore window is open, the plaintext to restore it from
+    fn score_encryption(
+        &mut self,
+        plan: EncryptionPlan,
+        data: &[u8],
+        sealed: Sealed,
+        crypto_time: std::time::Duration,
+        timing: Timing,
+    ) -> EncryptionResult {
+        let EncryptionPlan { user_id, level, mut theatrical_elements, .. } = plan;
+        // Calculate points based on theatrical complexity
+        let cost = self.config.level(&level).cost;
+        let points_earned = self.config.level(&level).points;
+
+        if sealed.input_entropy_bits_per_byte > ALREADY_ENCRYPTED_ENTROPY {
+            theatrical_elements.push("This already looks encrypted, you fool.".to_string());
+        }
+        if !self.theatrics_enabled {
+            theatrical_elements.clear();
+        }
+
+        // Check for achievements
+        let (achievement_id, achievement) = self.check_achievements(user_id, &level).unzip();
+        *self.level_counts.entry(user_id).or_default().entry(level.clone()).or_default() += 1;
+
+        let data_id = format!("GONGLE-{}-{}", user_id, self.rng.gen::<u32>());
+        let kept = self.kept_encryptions.entry(user_id).or_default();
+        if kept.len() == MAX_KEPT_ENCRYPTIONS {
+            kept.pop_front();
+        }
+        kept.push_back(KeptEncryption {
+            data_id: data_id.clone(),
+            level: level.clone(),
+            encrypted_at: self.clock.now(),
+            ciphertext: sealed.ciphertext.clone(),
+        });
+        self.prune_restorable();
+        if !self.restore_window.is_zero() {
+            let restorable =
+                RestorableEncryption { plaintext: Zeroizing::new(data.to_vec()), encrypted_at: self.clock.now() };
+            self.restorable.insert((user_id, data_id.clone()), restorable);
+        }
+
+        let elapsed = timing.start.elapsed().as_millis() as u64;
+
+        EncryptionResult {
+            success: true,
+            message: format!("Data encrypted with {:?} level security!", level),
+            data_id,
+            encryption_time_ms: elapsed,
+            theatrical_time_ms: timing.theatrical.as_millis() as u64,
+            real_crypto_time_ms: crypto_time.as_millis() as u64,
+            theatrical_elements,
+            cost,
+            points_earned,
+            charged: self.billing,
+            achievement_unlocked: achievement,
+            achievement_id,
+            input_entropy_bits_per_byte: sealed.input_entropy_bits_per_byte,
+            compression_ratio: sealed
+                .compressed_bytes
+                .filter(|_| !data.is_empty())
+                .map(|compressed| compressed as f32 / data.len() as f32),
+            compressed_bytes: sealed.compressed_bytes,
+            ciphertext: sealed.ciphertext,
+        }
+    }
+
+    /// Find the PBKDF2 round count whose derivation time is closest to `target`
+    /// on this machine, optionally adopting it for future encryptions
+    pub fn tune_kdf_rounds(&mut self, target: std::time::Duration, apply: bool) -> Result<u32> {
+        let rounds = tune_rounds(target, |rounds| {
+            let started = std::time::Instant::now();
+            derive_key("kdf-tuning", &[0u8; 32], rounds)?;
+            Ok(started.elapsed())
+        })?;
+
+        if apply {
+            self.kdf = Kdf::Pbkdf2 { rounds };
+        }
+        Ok(rounds)
+    }
+
+    /// Charge `LOOT_BOX_COST` and open a loot box, crediting the algorithm's
+    /// bonus points to the user. A user who can't pay gets nothing.
+    pub fn open_loot_box(&mut self, user_id: u64) -> Result<LootBoxResult, TheaterError> {
+        if let Err(error) = self.debit_points(user_id, LOOT_BOX_COST) {
+            self.record_audit(user_id, "lootbox".to_string(), 0, AuditOutcome::Failed(error.to_string()));
+            return Err(error);
+        }
+
+        let rarity = Rarity::from_roll(self.rng.gen());
+        let candidates: Vec<_> = LOOT_BOX_ALGORITHMS
+            .iter()
+            .filter(|(_, r, _)| *r == rarity)
+            .collect();
+        let (algorithm, _, bonus) = candidates[self.rng.gen_range(0..candidates.len())];
+
+        self.credit_points(user_id, *bonus);
+        self.record_audit(
+            user_id,
+            "lootbox".to_string(),
+            *bonus as i64 - LOOT_BOX_COST as i64,
+            AuditOutcome::Success,
+        );
+
+        let loot = LootBoxResult {
+            algorithm: algorithm.to_string(),
+            rarity,
+            bonus: *bonus,
+            rarity_color: rarity.color().to_string(),
+        };
+        self.collections.entry(user_id).or_default().push(loot.clone());
+        Ok(loot)
+    }
+
+    /// Every algorithm a user has pulled from a loot box, oldest first
+    pub fn collection(&self, user_id: u64) -> Vec<LootBoxResult> {
+        self.collections.get(&user_id).cloned().unwrap_or_default()
+    }
+
+    /// Issue a security certificate vouching for the user's encrypted data
+    pub fn generate_certificate(&mut self, user_id: u64, user_email: &str, encrypted_count: u64) -> Certificate {
+        let issued_date = self.clock.now();
+        let issued_secs = issued_date.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        let mut certificate = Certificate {
+            user_name: user_email.to_string(),
+            layers: self.rng.gen_range(3..=13),
+            technology: CERTIFICATE_TECHNOLOGIES.choose(&mut self.rng).unwrap().to_string(),
+            prayers: self.rng.gen_range(1..=10),
+            bonus_protection: CERTIFICATE_BONUSES.choose(&mut self.rng).unwrap().to_string(),
+            security_score: self.rng.gen_range(900..=999),
+            encrypted_items: encrypted_count,
+            certificate_id: format!("CERT-{}-{}", user_id, issued_secs),
+            issued_date,
+            expiry_date: "When the sun explodes".to_string(),
+            signed_by: "Dr. Totally Real Security Expert".to_string(),
+            quantum_signature: self.generate_quantum_signature(),
+            signature: String::new(),
+        };
+        let message = certificate.signed_bytes().expect("certificates serialize to JSON");
+        certificate.signature = base64_text::encode(self.signing_key.sign(&message).to_bytes());
+        certificate
+    }
+
+    /// Threat level for a user's data: the larger the unencrypted share, the
+    /// higher the severity. Nothing stored at all counts as nothing exposed.
+    pub fn threat_level(&self, _user_id: u64, encrypted_count: u64, unencrypted_count: u64) -> ThreatLevel {
+        let total = u128::from(encrypted_count) + u128::from(unencrypted_count);
+        let exposed = u128::from(unencrypted_count);
+        // Round 9 * exposed / total to the nearest step above the minimum
+        let steps = if total == 0 { 0 } else { (18 * exposed + total) / (2 * total) };
+        let index = steps as usize;
+        let (level, color) = THREAT_LEVELS[index];
+
+        ThreatLevel {
+            level: level.to_string(),
+            color: color.to_string(),
+            severity: index as u8 + 1,
+            recommended_action: THREAT_RECOMMENDATIONS[index].to_string(),
+        }
+    }
+
+    /// Eight groups of four hex digits, verified by nobody
+    fn generate_quantum_signature(&mut self) -> String {
+        let groups: Vec<String> = (0..8)
+            .map(|_| format!("{:04X}", self.rng.gen::<u16>()))
+            .collect();
+        format!("{} (QUANTUM VERIFIED)", groups.join("-"))
+    }
+
+    /// Current points balance for a user
+    pub fn balance(&self, user_id: u64) -> u32 {
+        self.balances.get(&user_id).copied().unwrap_or_default()
+    }
+
+    /// Add points to a user's balance, saturating at `u32::MAX` rather than
+    /// wrapping a hoarder back to zero
+    pub fn credit_points(&mut self, user_id: u64, amount: u32) {
+        let balance = self.balances.entry(user_id).or_default();
+        *balance = balance.saturating_add(amount);
+    }
+
+    /// Take points from a user's balance, leaving it untouched if they can't
+    /// cover `amount`
+    pub fn debit_points(&mut self, user_id: u64, amount: u32) -> Result<(), TheaterError> {
+        let have = self.balance(user_id);
+        let left = have
+            .checked_sub(amount)
+            .ok_or(TheaterError::InsufficientPoints { need: amount, have })?;
+        self.balances.insert(user_id, left);
+        Ok(())
+    }
+
+    /// The `top_n` users with the most points, capped at `MAX_LEADERBOARD_SIZE`;
+    /// ties go to the lower user id
+    pub fn leaderboard(&self, top_n: usize) -> Vec<LeaderboardEntry> {
+        let mut entries: Vec<LeaderboardEntry> = self
+            .balances
+            .iter()
+            .map(|(&user_id, &points)| LeaderboardEntry {
+                user_id,
+                points,
+                favorite_level: self.favorite_level(user_id),
+            })
+            .collect();
+        entries.sort_by(|a, b| b.points.cmp(&a.points).then(a.user_id.cmp(&b.user_id)));
+        entries.truncate(top_n.min(MAX_LEADERBOARD_SIZE));
+        entries
+    }
+
+    /// The level a user encrypts at most, preferring the pricier level on a tie
+    fn favorite_level(&self, user_id: u64) -> Option<EncryptionLevel> {
+        let counts = self.level_counts.get(&user_id)?;
+        // `max_by_key` keeps the last of equal maxima, and `ALL` runs cheapest first
+        EncryptionLevel::ALL
+            .iter()
+            .max_by_key(|level| counts.get(*level).copied().unwrap_or_default())
+            .filter(|level| counts.contains_key(*level))
+            .cloned()
+    }
+
+    /// All audited operations, oldest first
+    pub fn audit_log(&self) -> &[AuditEntry] {
+        &self.audit_log
+    }
+
+    /// `encrypt_with_drama` billed whether or not the theater is: the level's
+    /// cost is charged and its points awarded once the encryption succeeds, with
+    /// the net change in the audit log. A failed encryption costs nothing.
+    pub async fn purchase_encryption(
+        &mut self,
+        user_id: u64,
+        data: &[u8],
+        level: EncryptionLevel,
+    ) -> Result<EncryptionResult> {
+        let billing = std::mem::replace(&mut self.billing, true);
+        let result = self.encrypt_with_drama(user_id, data, level, None).await;
+        self.billing = billing;
+        result
+    }
+
+    /// Everything the theater holds for a user, with ciphertexts armored.
+    /// Pending funerals live in the `FuneralScheduler`; see `pending_for`.
+    pub fn export_user(&self, user_id: u64) -> UserExport {
+        let unlocked = self.achievements.get(&user_id);
+        UserExport {
+            user_id,
+            exported_at: self.clock.now(),
+            points: self.balance(user_id),
+            encrypted_items: self
+                .kept_encryptions
+                .get(&user_id)
+                .into_iter()
+                .flatten()
+                .map(|kept| ExportedItem {
+                    data_id: kept.data_id.clone(),
+                    level: kept.level.clone(),
+                    encrypted_at: kept.encrypted_at,
+                    armored_ciphertext: to_armor(&kept.ciphertext),
+                })
+                .collect(),
+            collection: self.collection(user_id),
+            achievements: EncryptionLevel::ALL
+                .iter()
+                .filter(|level| unlocked.is_some_and(|levels| levels.contains(*level)))
+                .map(|level| {
+                    let (id, name) = achievement_for(level);
+                    ExportedAchievement { id, name }
+                })
+                .collect(),
+            funerals: Vec::new(),
+        }
+    }
+
+    /// Erase everything the theater knows about a user: balance, achievements,
+    /// audit entries, loot, cached keys and usage counters. Pending funerals
+    /// live in the `FuneralScheduler` and are purged there.
+    pub fn purge_user(&mut self, user_id: u64) -> Result<PurgeReport> {
+        let audit_before = self.audit_log.len();
+        self.audit_log.retain(|entry| entry.user_id != user_id);
+        let cached_before = self.key_cache.len();
+        self.key_cache.retain(|(owner, _), _| *owner != user_id);
+        self.restorable.retain(|(owner, _), _| *owner != user_id);
+
+        let achievements = self.achievements.remove(&user_id);
+        let points = self.balances.remove(&user_id);
+        let kept = self.kept_encryptions.remove(&user_id);
+        let collection = self.collections.remove(&user_id);
+        let counters = [
+            self.level_counts.remove(&user_id).is_some(),
+            self.recent_encryptions.remove(&user_id).is_some(),
+            self.recent_decryptions.remove(&user_id).is_some(),
+            self.funeral_counts.remove(&user_id).is_some(),
+        ];
+
+        let report = PurgeReport {
+            achievements: achievements.as_ref().map_or(0, HashSet::len),
+            points: points.unwrap_or_default(),
+            encrypted_items: kept.as_ref().map_or(0, VecDeque::len),
+            audit_entries: audit_before - self.audit_log.len(),
+            loot_box_items: collection.as_ref().map_or(0, Vec::len),
+            cached_keys: cached_before - self.key_cache.len(),
+            ..PurgeReport::default()
+        };
+        let known = achievements.is_some()
+            || points.is_some()
+            || kept.is_some()
+            || collection.is_some()
+            || counters.contains(&true);
+        if !known && report == PurgeReport::default() {
+            return Err(TheaterError::UnknownUser(user_id).into());
+        }
+        Ok(report)
+    }
+
+    /// Reverse one of `user_id`'s encryptions while the restore window is open:
+    /// its kept ciphertext is dropped and the plaintext handed back. The points
+    /// it cost stay spent.
+    pub fn restore(&mut self, user_id: u64, data_id: &str) -> Result<Vec<u8>, TheaterError> {
+        self.prune_restorable();
+        let Some(mut restorable) = self.restorable.remove(&(user_id, data_id.to_string())) else {
+            // The plaintext is gone once the window closes, but the kept ciphertext may still be there
+            let kept = self.kept_encryptions.get(&user_id);
+            if kept.is_some_and(|kept| kept.iter().any(|item| item.data_id == data_id)) {
+                return Err(TheaterError::RestoreWindowExpired(data_id.to_string()));
+            }
+            return Err(TheaterError::NothingToRestore(data_id.to_string()));
+        };
+        if let Some(kept) = self.kept_encryptions.get_mut(&user_id) {
+            kept.retain(|item| item.data_id != data_id);
+        }
+        Ok(std::mem::take(&mut *restorable.plaintext))
+    }
+
+    /// Drop, and so wipe, every plaintext whose restore window has closed. Only
+    /// encrypting and restoring prune, so an idle theater keeps them until then.
+    fn prune_restorable(&mut self) {
+        let now = self.clock.now();
+        let window = self.restore_window;
+        self.restorable.retain(|_, restorable| {
+            now.duration_since(restorable.encrypted_at).is_ok_and(|elapsed| elapsed < window)
+        });
+    }
+
+    fn record_audit(&mut self, user_id: u64, operation: String, points_delta: i64, outcome: AuditOutcome) {
+        self.audit_log.push(AuditEntry {
+            user_id,
+            operation,
+            points_delta,
+            timestamp: self.clock.now(),
+            outcome,
+        });
+    }
+
+    /// Audit a billed encryption that went wrong; it was never charged
+    fn audit_failed_encryption<T>(&mut self, user_id: u64, level: &EncryptionLevel, result: &Result<T>) {
+        if let (true, Err(e)) = (self.billing, result) {
+            self.record_audit(user_id, format!("encrypt:{:?}", level), 0, AuditOutcome::Failed(e.to_string()));
+        }
+    }
+
+    /// Schedule a data funeral with maximum drama; `webhook_url`, if given, is
+    /// POSTed the schedule once the funeral has been held
+    #[tracing::instrument(skip(self, data_ids, webhook_url), fields(items = data_ids.len()))]
+    pub async fn schedule_funeral(
+        &mut self,
+        user_id: u64,
+        data_ids: Vec<String>,
+        funeral_type: FuneralType,
+        webhook_url: Option<String>,
+    ) -> Result<FuneralSchedule> {
+        let result = self.plan_funeral(user_id, data_ids, funeral_type, webhook_url);
+        trace_outcome("funeral scheduling", &result);
+        result
+    }
+
+    /// Validate, charge the quota for and lay out a funeral
+    fn plan_funeral(
+        &mut self,
+        user_id: u64,
+        data_ids: Vec<String>,
+        funeral_type: FuneralType,
+        webhook_url: Option<String>,
+    ) -> Result<FuneralSchedule> {
+        funeral_type.validate()?;
+        // Unlike empty data, an empty funeral has nothing to shred
+        if data_ids.is_empty() {
+            return Err(TheaterError::InvalidFuneralParam {
+                field: "data_ids",
+                value: "[]".to_string(),
+                allowed: "at least one data id".to_string(),
+            }
+            .into());
+        }
+        let is_http = |url: &str| url.starts_with("http://") || url.starts_with("https://");
+        if let Some(url) = webhook_url.as_deref().filter(|url| !is_http(url)) {
+            return Err(TheaterError::InvalidFuneralParam {
+                field: "webhook_url",
+                value: url.to_string(),
+                allowed: "an http:// or https:// URL".to_string(),
+            }
+            .into());
+        }
+        let ceremony: &dyn FuneralCeremony = match &funeral_type {
+            FuneralType::Custom { ceremony } => self
+                .ceremonies
+                .get(ceremony)
+                .ok_or_else(|| TheaterError::UnknownCeremony(ceremony.clone()))?
+                .as_ref(),
+            built_in => built_in,
+        };
+        let epitaph = ceremony.epitaph(data_ids.len());
+        let special_effects = ceremony.special_effects();
+        let shred_passes = ceremony.shred_passes(&mut self.rng);
+
+        let now = self.clock.now();
+        self.claim_funeral_slot(user_id, now)?;
+
+        let ceremony_id = format!("FUNERAL-{}-{}", user_id, self.rng.gen::<u32>());
+
+        // Create memorial certificate
+        let memorial = FuneralSchedule {
+            ceremony_id,
+            user_id,
+            data_ids,
+            funeral_type,
+            scheduled_time: now + std::time::Duration::from_secs(86400), // 24 hours
+            epitaph,
+            shred_passes,
+            special_effects,
+            livestream_url: format!("{}/funerals/live/{}", self.base_url, self.rng.gen::<u32>()),
+            guest_list: self.generate_funeral_guests(),
+            webhook_url,
+        };
+
+        Ok(memorial)
+    }
+
+    /// Count a funeral against the user's quota for the current UTC day
+    fn claim_funeral_slot(&mut self, user_id: u64, now: SystemTime) -> Result<(), TheaterError> {
+        let today = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / SECONDS_PER_DAY;
+        let (day, count) = self.funeral_counts.entry(user_id).or_insert((today, 0));
+        if *day != today {
+            *day = today;
+            *count = 0;
+        }
+
+        if *count >= self.daily_funeral_quota {
+            return Err(TheaterError::QuotaExceeded {
+                limit: self.daily_funeral_quota,
+                resets_at: (today + 1) * SECONDS_PER_DAY,
+            });
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    /// Count an encryption against the user's rate limit for the last minute
+    fn claim_encrypt_slot(&mut self, user_id: u64) -> Result<(), TheaterError> {
+        self.claim_encrypt_slots(user_id, 1)
+    }
+
+    /// Count `count` encryptions against the rate limit at once: a batch gets
+    /// every slot it needs or none of them
+    fn claim_encrypt_slots(&mut self, user_id: u64, count: usize) -> Result<(), TheaterError> {
+        let now = self.clock.now();
+        claim_rate_slots(self.encrypt_rate_limit, self.recent_encryptions.entry(user_id).or_default(), now, count)
+    }
+
+    /// Count a decryption against the user's rate limit for the last minute;
+    /// decryptions get as many slots as encryptions, counted separately
+    fn claim_decrypt_slot(&mut self, user_id: u64) -> Result<(), TheaterError> {
+        let now = self.clock.now();
+        claim_rate_slots(self.encrypt_rate_limit, self.recent_decryptions.entry(user_id).or_default(), now, 1)
+    }
+
+    /// Basic encryption using the actual ChaCha20 implementation
+    fn basic_encrypt(
+        &mut self,
+        data: &[u8],
+        password: &str,
+        aad: &[u8],
+        level: &EncryptionLevel,
+    ) -> Result<Vec<u8>, TheaterError> {
+        let (salt, nonce) = self.fresh_salt_and_nonce();
+        let key = self.kdf.derive(password, &salt)?;
+        let nonce = self.claim_nonce(&key, nonce)?;
+        let prefix = ciphertext_prefix(level.to_byte(), self.cipher.as_ref(), &self.kdf, &salt);
+        seal(self.cipher.as_ref(), &key, prefix, &nonce, aad, data)
+    }
+
+    /// Derive a key once per (user_id, salt) and reuse it for the rest of a batch.
+    /// Only `encrypt_batch` reuses salts, and it evicts its entry when done.
+    async fn derive_key_cached(
+        &mut self,
+        user_id: u64,
+        password: &str,
+        salt: [u8; SALT_LENGTH],
+    ) -> Result<DerivedKey, TheaterError> {
+        if let Some(key) = self.key_cache.get(&(user_id, salt)) {
+            return Ok(key.clone());
+        }
+        let key = derive_key_offloaded(password, salt, self.kdf).await?;
+        self.key_cache.insert((user_id, salt), key.clone());
+        Ok(key)
+    }
+
+    /// The nonce to seal the next message under `key` with, given a random
+    /// candidate, after checking it against the nonce store
+    fn claim_nonce(&mut self, key: &DerivedKey, random: [u8; NONCE_LENGTH]) -> Result<[u8; NONCE_LENGTH], TheaterError> {
+        if self.nonce_strategy == NonceStrategy::Random && self.nonce_store.is_none() {
+            return Ok(random);
+        }
+
+        let key_id = nonce::key_id(&key.0);
+        let nonce = match self.nonce_strategy {
+            NonceStrategy::Random => random,
+            NonceStrategy::Counter => {
+                let counter = self.nonce_counters.entry(key_id).or_default();
+                let nonce = counter_nonce(*counter);
+                *counter += 1;
+                nonce
+            },
+        };
+        if let Some(store) = self.nonce_store.as_mut() {
+            store.record(&key_id, &nonce)?;
+        }
+        Ok(nonce)
+    }
+
+    /// Random salt and nonce for a new ciphertext
+    fn fresh_salt_and_nonce(&mut self) -> ([u8; SALT_LENGTH], [u8; NONCE_LENGTH]) {
+        let mut salt = [0u8; SALT_LENGTH];
+        self.rng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; NONCE_LENGTH];
+        self.rng.fill_bytes(&mut nonce);
+        (salt, nonce)
+    }
+
+    /// Reverse `basic_encrypt`: check the header, split salt + nonce + ciphertext
+    /// and decrypt, authenticating against the same associated data
+    fn basic_decrypt(&self, blob: &[u8], password: &str, aad: &[u8]) -> Result<Vec<u8>, TheaterError> {
+        open_layer(blob, password, aad, Kdf::Pbkdf2 { rounds: self.pbkdf2_rounds() })
+    }
+
+    /// Decrypt a ciphertext from `perform_encryption`, reading the level from its
+    /// header; without a password the level's theatrical password is used
+    pub fn decrypt_auto(
+        &mut self,
+        user_id: u64,
+        ciphertext: &[u8],
+        password: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        self.plan_decryption(user_id, ciphertext, password)?.open(ciphertext)
+    }
+
+    /// `decrypt_auto` on a theater shared between requests, counted against the
+    /// user's rate limit. The lock is only held to read the header and pick the
+    /// password; the key derivations, as costly as the header asks, run on the
+    /// blocking pool without it.
+    pub async fn decrypt_shared(
+        theater: &tokio::sync::Mutex<DataTheater>,
+        user_id: u64,
+        ciphertext: Vec<u8>,
+        password: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let plan = {
+            let mut theater = theater.lock().await;
+            theater.claim_decrypt_slot(user_id)?;
+            theater.plan_decryption(user_id, &ciphertext, password)?
+        };
+        tokio::task::spawn_blocking(move || plan.open(&ciphertext)).await?
+    }
+
+    /// Read what decrypting `ciphertext` needs from its header and the theater
+    fn plan_decryption(
+        &self,
+        user_id: u64,
+        ciphertext: &[u8],
+        password: Option<&str>,
+    ) -> Result<DecryptionPlan, TheaterError> {
+        let Header { level, flags, .. } = read_header(ciphertext)?;
+        let password = match password {
+            Some(password) => Zeroizing::new(password.to_string()),
+            None => Zeroizing::new(self.generate_theatrical_password(user_id, &level)),
+        };
+        Ok(DecryptionPlan {
+            level,
+            flags,
+            password,
+            aad: user_id.to_le_bytes(),
+            legacy_kdf: Kdf::Pbkdf2 { rounds: self.pbkdf2_rounds() },
+            // Twice the input cap leaves room for deflate's overhead on
+            // incompressible data but stops a crafted layer inflating without bound
+            inflate_limit: self.max_input_bytes.saturating_mul(2),
+        })
+    }
+
+    /// Decrypt a ciphertext without knowing its level: a readable header is
+    /// trusted, otherwise each level is tried cheapest first and the first to
+    /// authenticate and reverse cleanly wins. Bare `salt || nonce || ciphertext`
+    /// blobs from before the header existed are read as ChaCha20-Poly1305 under
+    /// PBKDF2. Trying a level is a matter of writing it into the header; from
+    /// version 3 the header is authenticated, so only the level the data was
+    /// really sealed at can decrypt.
+    pub fn decrypt_try_all(
+        &mut self,
+        user_id: u64,
+        ciphertext: &[u8],
+        password: Option<&str>,
+    ) -> Result<(EncryptionLevel, Vec<u8>)> {
+        let candidates: Vec<(EncryptionLevel, Vec<u8>)> = match read_header(ciphertext) {
+            Ok(header) => vec![(header.level, ciphertext.to_vec())],
+            // The level byte is damaged but the rest of the header may be fine
+            Err(TheaterError::InvalidLevel(_)) => EncryptionLevel::ALL
+                .iter()
+                .map(|level| {
+                    let mut blob = ciphertext.to_vec();
+                    blob[5] = level.to_byte() | (blob[5] & FLAG_QUANTUM_PREFIXED);
+                    (level.clone(), blob)
+                })
+                .collect(),
+            Err(TheaterError::BadMagic) => EncryptionLevel::ALL
+                .iter()
+                .map(|level| {
+                    let header = [FORMAT_MAGIC.as_slice(), &[1, level.to_byte()]].concat();
+                    (level.clone(), [header.as_slice(), ciphertext].concat())
+                })
+                .collect(),
+            Err(e) => return Err(e.into()),
+        };
+
+        for (level, blob) in candidates {
+            match self.decrypt_auto(user_id, &blob, password) {
+                Ok(data) => return Ok((level, data)),
+                Err(e) => tracing::debug!(level = %level, error = %format!("{:#}", e), "level did not decrypt"),
+            }
+        }
+        Err(TheaterError::Undecryptable.into())
+    }
+
+    /// Re-protect a ciphertext under a new password. Each layer is opened with
+    /// the old password and resealed under the new one with a fresh salt and
+    /// nonce, keeping its level and flags; whatever the level put inside the
+    /// layer (padding, curses) comes through untouched. A wrong old password
+    /// fails before anything is sealed. The key derivations run on tokio's
+    /// blocking pool.
+    pub async fn rotate_key(
+        &mut self,
+        user_id: u64,
+        ciphertext: &[u8],
+        old_password: &str,
+        new_password: &str,
+        level: EncryptionLevel,
+    ) -> Result<Vec<u8>> {
+        let Header { level: found, flags, .. } = read_header(ciphertext)?;
+        if found != level {
+            return Err(TheaterError::LevelMismatch { expected: level, found }.into());
+        }
+        let aad = user_id.to_le_bytes();
+        let legacy_kdf = Kdf::Pbkdf2 { rounds: self.pbkdf2_rounds() };
+
+        let mut layer = Zeroizing::new(open_layer_offloaded(ciphertext, old_password, &aad, legacy_kdf).await?);
+        if level == EncryptionLevel::Premium {
+            // The outer layer seals the base64 text of a second ciphertext
+            let inner = base64_text::decode(&*layer).context("Premium inner layer is not valid base64")?;
+            layer = Zeroizing::new(open_layer_offloaded(&inner, old_password, &aad, legacy_kdf).await?);
+        }
+
+        let plan = self.plan_reseal(user_id, level, flags, new_password);
+        let keys = plan.derive_keys().await?;
+        let nonces = self.claim_nonces(&plan, &keys)?;
+        Ok(plan.seal(&layer, &keys, &nonces)?.ciphertext)
+    }
+
+    /// Plan resealing the contents of a ciphertext's layers as they are under
+    /// `password`, with the level's layer count and the header's `flags`
+    fn plan_reseal(&mut self, user_id: u64, level: EncryptionLevel, flags: u8, password: &str) -> EncryptionPlan {
+        let transform = match level {
+            EncryptionLevel::Premium => Transform::SealTwice,
+            _ => Transform::Reseal { flags },
+        };
+        let layer_count = if matches!(transform, Transform::SealTwice) { 2 } else { 1 };
+        let layers = (0..layer_count).map(|_| self.fresh_salt_and_nonce()).collect();
+        EncryptionPlan {
+            user_id,
+            level,
+            password: Zeroizing::new(password.to_string()),
+            cipher: self.cipher.clone(),
+            kdf: self.kdf,
+            transform,
+            layers,
+            theatrical_elements: Vec::new(),
+        }
+    }
+
+    /// Append an independently decryptable, length-framed record to `file`
+    pub fn append_encrypted(&mut self, file: &Path, data: &[u8], password: &str) -> Result<()> {
+        let blob = self.basic_encrypt(data, password, &[], &EncryptionLevel::Basic)?;
+        let frame_len = u32::try_from(blob.len())
+            .map_err(|_| anyhow::anyhow!("Record too large to frame"))?;
+
+        let mut out = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)
+            .with_context(|| format!("Failed to open {} for appending", file.display()))?;
+        let mut frame = Vec::with_capacity(4 + blob.len());
+        frame.extend_from_slice(&frame_len.to_be_bytes());
+        frame.extend_from_slice(&blob);
+        out.write_all(&frame)?;
+
+        Ok(())
+    }
+
+    /// Decrypt every record written by `append_encrypted`, in order
+    pub fn decrypt_concatenated(&self, file: &Path, password: &str) -> Result<Vec<Vec<u8>>> {
+        let contents = fs::read(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+
+        let mut records = Vec::new();
+        let mut rest = contents.as_slice();
+        while !rest.is_empty() {
+            if rest.len() < 4 {
+                anyhow::bail!("Truncated record header");
+            }
+            let (len_bytes, tail) = rest.split_at(4);
+            let len = u32::from_be_bytes(len_bytes.try_into()?) as usize;
+            if tail.len() < len {
+                anyhow::bail!("Truncated record body");
+            }
+            let (blob, tail) = tail.split_at(len);
+            records.push(self.basic_decrypt(blob, password, &[])?);
+            rest = tail;
+        }
+
+        Ok(records)
+    }
+
+    /// Encrypt a file of any size in fixed-size chunks, so memory stays bounded.
+    ///
+    /// Each chunk is sealed separately under a nonce built from a random prefix,
+    /// the chunk counter and a last-chunk flag, with the file header as associated
+    /// data, so chunks can't be reordered, dropped or truncated undetected.
+    pub async fn encrypt_file(
+        &mut self,
+        in_path: &Path,
+        out_path: &Path,
+        level: EncryptionLevel,
+        password: &str,
+    ) -> Result<()> {
+        self.dramatic_pause(&level, None).await;
+
+        let (salt, _) = self.fresh_salt_and_nonce();
+        let mut nonce_prefix = [0u8; STREAM_NONCE_PREFIX_LENGTH];
+        self.rng.fill_bytes(&mut nonce_prefix);
+
+        let mut header = Vec::new();
+        header.extend_from_slice(STREAM_MAGIC);
+        header.push(STREAM_FORMAT_VERSION);
+        header.push(level.to_byte());
+        self.kdf.write(&mut header);
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&nonce_prefix);
+
+        let key = derive_key_offloaded(password, salt, self.kdf).await?;
+        let (in_path, out_path) = (in_path.to_path_buf(), out_path.to_path_buf());
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let input = File::open(&in_path)
+                .with_context(|| format!("Failed to open {}", in_path.display()))?;
+            with_output_file(&out_path, |output| {
+                output.write_all(&header)?;
+                seal_stream(input, output, &key, &nonce_prefix, &header)
+            })
+        })
+        .await?
+    }
+
+    /// Decrypt a file written by `encrypt_file`, returning the level it was
+    /// encrypted at. The key is derived the way the header says; only version 1
+    /// files fall back to the configured PBKDF2 rounds. A partially written
+    /// output is removed on failure.
+    pub async fn decrypt_file(
+        &self,
+        in_path: &Path,
+        out_path: &Path,
+        password: &str,
+    ) -> Result<EncryptionLevel> {
+        let mut input = File::open(in_path)
+            .with_context(|| format!("Failed to open {}", in_path.display()))?;
+        let legacy_kdf = Kdf::Pbkdf2 { rounds: self.pbkdf2_rounds() };
+        let StreamHeader { bytes: header, level, kdf, salt, nonce_prefix } =
+            read_stream_header(&mut input, legacy_kdf)?;
+
+        let key = derive_key_offloaded(password, salt, kdf).await?;
+        let out_path = out_path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            with_output_file(&out_path, |output| {
+                open_stream(input, output, &key, &nonce_prefix, &header)
+            })
+        })
+        .await??;
+
+        Ok(level)
+    }
+
+    /// Generate theatrical password based on user and level
+    fn generate_theatrical_password(&self, user_id: u64, level: &EncryptionLevel) -> String {
+        match level {
+            EncryptionLevel::Basic => format!("user_{}_password123", user_id),
+            EncryptionLevel::Premium => format!("user_{}_premiumpassword!", user_id),
+            EncryptionLevel::Paranoid => format!("user_{}_they_are_watching", user_id),
+            EncryptionLevel::Tinfoil => format!("user_{}_5g_cant_penetrate_this", user_id),
+            EncryptionLevel::Quantum => format!("user_{}_schrodingers_password", user_id),
+            EncryptionLevel::Alien => format!("user_{}_area51_clearance", user_id),
+            EncryptionLevel::Eldritch => format!("user_{}_ph_nglui_mglw_nafh", user_id),
+        }
+    }
+
+    /// Generate paranoid padding
+    fn generate_paranoid_padding(&mut self) -> String {
+        let count = self.rng.gen_range(5..20);
+        (0..count)
+            .map(|_| PARANOID_PHRASES[self.rng.gen_range(0..PARANOID_PHRASES.len())])
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Add zalgo text for eldritch effect
+    fn add_zalgo_text(&mut self, text: &str) -> String {
+        text.chars()
+            .map(|c| {
+                let zalgo_count = match self.zalgo_intensity {
+                    0 => 0,
+                    intensity => self.rng.gen_range(1..=intensity),
+                };
+                let mut result = String::from(c);
+                for _ in 0..zalgo_count {
+                    result.push(ZALGO_CHARS[self.rng.gen_range(0..ZALGO_CHARS.len())]);
+                }
+                result
+            })
+            .collect()
+    }
+
+    /// Check for achievements
+    fn check_achievements(
+        &mut self,
+        user_id: u64,
+        level: &EncryptionLevel,
+    ) -> Option<(AchievementId, String)> {
+        if self.achievements.entry(user_id).or_default().insert(level.clone()) {
+            Some(achievement_for(level))
+        } else {
+            None
+        }
+    }
+
+    /// The achievement encrypting at `level` would unlock, without unlocking it
+    fn peek_achievement(&self, user_id: u64, level: &EncryptionLevel) -> Option<(AchievementId, String)> {
+        let unlocked = self.achievements.get(&user_id).is_some_and(|levels| levels.contains(level));
+        (!unlocked).then(|| achievement_for(level))
+    }
+
+    /// Race a user's racer against generated opponents, crediting the prize on a win
+    pub async fn quick_race(
+        &mut self,
+        user_id: u64,
+        racer: RaceParticipant,
+        data_size: usize,
+    ) -> Result<QuickRaceResults> {
+        let racer_name = racer.name.clone();
+        let (participants, mut rng) = self.line_up_quick_race(racer);
+        let race = encryption_race(participants, data_size, self.race_limits, &mut rng).await?;
+        Ok(self.award_quick_race(user_id, &racer_name, data_size, race))
+    }
+
+    /// `quick_race` on a shared theater. The lock is only held to line up the
+    /// opponents and to pay out; the race itself, which can run for as long
+    /// as the data takes, runs without it.
+    pub async fn quick_race_shared(
+        theater: &tokio::sync::Mutex<DataTheater>,
+        user_id: u64,
+        racer: RaceParticipant,
+        data_size: usize,
+    ) -> Result<QuickRaceResults> {
+        let racer_name = racer.name.clone();
+        let (participants, limits, mut rng) = {
+            let mut theater = theater.lock().await;
+            let (participants, rng) = theater.line_up_quick_race(racer);
+            (participants, theater.race_limits, rng)
+        };
+        let race = encryption_race(participants, data_size, limits, &mut rng).await?;
+        Ok(theater.lock().await.award_quick_race(user_id, &racer_name, data_size, race))
+    }
+
+    /// The user's racer and its generated opponents, plus randomness for the
+    /// race drawn from the theater's, so seeded theaters race the same way
+    fn line_up_quick_race(&mut self, racer: RaceParticipant) -> (Vec<RaceParticipant>, rand::rngs::StdRng) {
+        let mut participants = vec![racer];
+        participants.extend(self.generate_opponents(QUICK_RACE_OPPONENTS));
+        let rng = rand::SeedableRng::from_rng(&mut self.rng).expect("theater RNGs don't fail");
+        (participants, rng)
+    }
+
+    /// Credit the prize if `racer_name` won
+    fn award_quick_race(
+        &mut self,
+        user_id: u64,
+        racer_name: &str,
+        data_size: usize,
+        race: RaceResults,
+    ) -> QuickRaceResults {
+        let points_awarded = if race.winner == racer_name {
+            let prize = race_prize(data_size);
+            self.credit_points(user_id, prize);
+            prize
+        } else {
+            0
+        };
+
+        QuickRaceResults { race, points_awarded }
+    }
+
+    /// Generate AI opponents so a race can be filled out without the client
+    pub fn generate_opponents(&mut self, count: usize) -> Vec<RaceParticipant> {
+        let racers = [
+            ("CryptoBot3000", "🚗"),
+            ("QuantumRacer", "🚙"),
+            ("BlockchainBurner", "🏍️"),
+            ("EnigmaExpress", "🚂"),
+            ("RSA Roadster", "🏎️"),
+            ("NonceSense", "🛵"),
+            ("Hashimoto Drift", "🚕"),
+            ("The Salted Hash", "🚜"),
+        ];
+        let trash_talk = [
+            "Your entropy is showing.",
+            "I've seen faster ROT13.",
+            "My keys are longer than your attention span.",
+            "Catch me if you can brute-force me.",
+            "You call that a cipher suite?",
+            "I pad my blocks with your tears.",
+        ];
+
+        let mut lineup: Vec<_> = racers.iter().collect();
+        lineup.shuffle(&mut self.rng);
+
+        (0..count)
+            .map(|i| {
+                let (name, vehicle) = lineup[i % lineup.len()];
+                let lap = i / lineup.len();
+                RaceParticipant {
+                    name: if lap == 0 {
+                        name.to_string()
+                    } else {
+                        format!("{} Mk{}", name, lap + 1)
+                    },
+                    encryption_speed: self.rng.gen_range(0.7..1.4),
+                    vehicle: vehicle.to_string(),
+                    trash_talk: trash_talk[self.rng.gen_range(0..trash_talk.len())].to_string(),
+                }
+            })
+            .collect()
+    }
+
+    /// Generate funeral guest list
+    fn generate_funeral_guests(&mut self) -> Vec<String> {
+        let guests = [
+            "Mark Zuckerberg (via metaverse)",
+            "The Ghost of Your Privacy",
+            "Three Russian Hackers",
+            "Your FBI Agent",
+            "Cambridge Analytica (uninvited)",
+            "That Nigerian Prince",
+            "Cookie Monster (for the cookies)",
+            "The Blockchain Itself",
+            "Satoshi Nakamoto (maybe)",
+            "Your Mom (disappointed)",
+        ];
+        
+        let count = self.rng.gen_range(3..7);
+        guests.choose_multiple(&mut self.rng, count)
+            .map(|s| s.to_string())
+            .collect()
+    }
+}
+
+/// Funeral schedule details
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FuneralSchedule {
+    pub ceremony_id: String,
+    pub user_id: u64,
+    pub data_ids: Vec<String>,
+    pub funeral_type: FuneralType,
+    #[serde(with = "unix_millis")]
+    pub scheduled_time: SystemTime,
+    pub epitaph: String,
+    pub shred_passes: u32,
+    pub special_effects: Vec<String>,
+    pub livestream_url: String,
+    pub guest_list: Vec<String>,
+    /// Told when the funeral has been held
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+}
+
+impl FuneralSchedule {
+    /// Check the cross-field invariants `schedule_funeral` guarantees
+    pub fn validate(&self) -> Result<()> {
+        self.validate_at(SystemClock.now())
+    }
+
+    /// `validate`, judging how stale scheduled_time is as of `now`
+    pub fn validate_at(&self, now: SystemTime) -> Result<()> {
+        let prefix = format!("FUNERAL-{}-", self.user_id);
+        if !self.ceremony_id.starts_with(&prefix) {
+            anyhow::bail!(
+                "ceremony_id {} does not belong to user {}",
+                self.ceremony_id,
+                self.user_id
+            );
+        }
+
+        if !self.funeral_type.accepts_passes(self.shred_passes) {
+            anyhow::bail!(
+                "shred_passes {} is not valid for a {} funeral",
+                self.shred_passes,
+                self.funeral_type.kind()
+            );
+        }
+
+        if let Ok(age) = now.duration_since(self.scheduled_time) {
+            if age > MAX_FUNERAL_AGE {
+                anyhow::bail!(
+                    "scheduled_time is {} days in the past",
+                    age.as_secs() / 86400
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Rebuild a funeral from its stored JSON, rejecting inconsistent records
+pub fn replay_funeral(json: &str) -> Result<FuneralSchedule> {
+    let schedule: FuneralSchedule = serde_json::from_str(json)?;
+    schedule.validate()?;
+    Ok(schedule)
+}
+
+/// A data item held by the theater, alive until its funeral runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoredData {
+    Alive(Vec<u8>),
+    Tombstone { ceremony_id: String, buried_at: SystemTime },
+}
+
+/// What a funeral's webhook is POSTed once the ceremony has been held
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FuneralCompleted {
+    #[serde(flatten)]
+    pub schedule: FuneralSchedule,
+    #[serde(with = "unix_millis")]
+    pub completed_at: SystemTime,
+}
+
+/// How far a held funeral's webhook has got
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum WebhookStatus {
+    Pending,
+    Delivered { attempts: u32 },
+    Failed { attempts: u32, error: String },
+}
+
+/// What a shredded item held, kept until the restore window closes
+struct ShreddedData {
+    bytes: Zeroizing<Vec<u8>>,
+    shredded_at: SystemTime,
+}
+
+/// Pending funerals and the data they will shred once their time comes
+pub struct FuneralScheduler {
+    pending: Vec<(tokio::time::Instant, FuneralSchedule)>,
+    /// Ceremony ids of funerals that have already been held
+    executed: HashSet<String>,
+    /// Data items by (owner, data_id); a funeral only touches its own user's items
+    data: HashMap<(u64, String), StoredData>,
+    /// Delivery of each held funeral's webhook, by ceremony id
+    webhooks: HashMap<String, WebhookStatus>,
+    /// Items shredded within the restore window, by (owner, data_id)
+    shredded: HashMap<(u64, String), ShreddedData>,
+    /// How long after its funeral shredded data can still be restored
+    restore_window: std::time::Duration,
+    /// Whether webhooks may reach loopback, private and link-local addresses
+    private_webhooks: bool,
+    clock: Arc<dyn Clock>,
+    wakeup: Arc<tokio::sync::Notify>,
+}
+
+impl Default for FuneralScheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FuneralScheduler {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+            executed: HashSet::new(),
+            data: HashMap::new(),
+            webhooks: HashMap::new(),
+            shredded: HashMap::new(),
+            restore_window: DEFAULT_RESTORE_WINDOW,
+            private_webhooks: false,
+            clock: Arc::new(SystemClock),
+            wakeup: Arc::new(tokio::sync::Notify::new()),
+        }
+    }
+
+    /// Use a different clock to measure how far away each funeral is
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Keep shredded data restorable for `window` instead of the default five minutes;
+    /// zero shreds for good straight away
+    pub fn with_restore_window(mut self, window: std::time::Duration) -> Self {
+        self.restore_window = window;
+        self
+    }
+
+    /// Let webhooks reach loopback, private and link-local addresses, e.g. a
+    /// receiver on the same machine in tests; never do this on a public server
+    pub fn with_private_webhooks(mut self) -> Self {
+        self.private_webhooks = true;
+        self
+    }
+
+    /// Hold one of `user_id`'s data items until one of their funerals shreds it
+    pub fn store(&mut self, user_id: u64, data_id: impl Into<String>, bytes: Vec<u8>) {
+        self.prune_shredded();
+        self.data.insert((user_id, data_id.into()), StoredData::Alive(bytes));
+    }
+
+    /// Current state of one of `user_id`'s data items, if the theater has ever seen it
+    pub fn get(&self, user_id: u64, data_id: &str) -> Option<&StoredData> {
+        self.data.get(&(user_id, data_id.to_string()))
+    }
+
+    /// Queue a funeral to run at its scheduled_time
+    pub fn enqueue(&mut self, schedule: FuneralSchedule) {
+        let wait = schedule
+            .scheduled_time
+            .duration_since(self.clock.now())
+            .unwrap_or_default();
+        self.pending.push((tokio::time::Instant::now() + wait, schedule));
+        self.wakeup.notify_one();
+    }
+
+    /// Number of funerals still waiting for their scheduled_time
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// A user's funerals still waiting for their scheduled_time
+    pub fn pending_for(&self, user_id: u64) -> Vec<FuneralSchedule> {
+        self.pending
+            .iter()
+            .map(|(_, schedule)| schedule)
+            .filter(|schedule| schedule.user_id == user_id)
+            .cloned()
+            .collect()
+    }
+
+    /// How delivery of a held funeral's webhook went, if it had one
+    pub fn webhook_status(&self, ceremony_id: &str) -> Option<&WebhookStatus> {
+        self.webhooks.get(ceremony_id)
+    }
+
+    /// Call off a pending funeral so its data survives
+    pub fn cancel_funeral(&mut self, ceremony_id: &str) -> Result<(), TheaterError> {
+        if self.executed.contains(ceremony_id) {
+            return Err(TheaterError::AlreadyExecuted(ceremony_id.to_string()));
+        }
+        let index = self
+            .pending
+            .iter()
+            .position(|(_, schedule)| schedule.ceremony_id == ceremony_id)
+            .ok_or_else(|| TheaterError::NotFound(ceremony_id.to_string()))?;
+        self.pending.remove(index);
+        Ok(())
+    }
+
+    /// Bring back an item one of `user_id`'s funerals shredded, if the restore
+    /// window is still open
+    pub fn restore(&mut self, user_id: u64, data_id: &str) -> Result<(), TheaterError> {
+        self.prune_shredded();
+        let key = (user_id, data_id.to_string());
+        let Some(mut shredded) = self.shredded.remove(&key) else {
+            // The bytes are gone once the window closes, but the tombstone is still there
+            return match self.data.get(&key) {
+                Some(StoredData::Tombstone { .. }) => Err(TheaterError::RestoreWindowExpired(data_id.to_string())),
+                _ => Err(TheaterError::NothingToRestore(data_id.to_string())),
+            };
+        };
+        self.data.insert(key, StoredData::Alive(std::mem::take(&mut *shredded.bytes)));
+        Ok(())
+    }
+
+    /// Whether data shredded at `shredded_at` may still be restored
+    fn restorable(&self, shredded_at: SystemTime) -> bool {
+        self.clock
+            .now()
+            .duration_since(shredded_at)
+            .is_ok_and(|elapsed| elapsed < self.restore_window)
+    }
+
+    /// Drop, and so wipe, the bytes of every item whose restore window has closed
+    fn prune_shredded(&mut self) {
+        let shredded = std::mem::take(&mut self.shredded);
+        self.shredded = shredded
+            .into_iter()
+            .filter(|(_, shredded)| self.restorable(shredded.shredded_at))
+            .collect();
+    }
+
+    /// When the worker next has something to do: hold a funeral or wipe
+    /// shredded bytes whose restore window has closed
+    fn next_wakeup(&self) -> Option<tokio::time::Instant> {
+        let now = self.clock.now();
+        let expiries = self.shredded.values().map(|shredded| {
+            let closes = shredded.shredded_at + self.restore_window;
+            tokio::time::Instant::now() + closes.duration_since(now).unwrap_or_default()
+        });
+        self.pending.iter().map(|(due, _)| *due).chain(expiries).min()
+    }
+
+    /// Call off a user's pending funerals and drop the data they were holding,
+    /// along with the tombstones and webhook records of funerals already held
+    pub fn purge_user(&mut self, user_id: u64) -> PurgeReport {
+        let prefix = format!("FUNERAL-{}-", user_id);
+        let (purged, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|(_, schedule)| schedule.user_id == user_id);
+        self.pending = pending;
+
+        let data_before = self.data.len();
+        self.data.retain(|(owner, _), stored| {
+            if *owner != user_id {
+                return true;
+            }
+            if let StoredData::Alive(bytes) = stored {
+                bytes.zeroize();
+            }
+            false
+        });
+        let data_items = data_before - self.data.len();
+        self.executed.retain(|ceremony_id| !ceremony_id.starts_with(&prefix));
+        self.webhooks.retain(|ceremony_id, _| !ceremony_id.starts_with(&prefix));
+        self.shredded.retain(|(owner, _), _| *owner != user_id);
+
+        PurgeReport { pending_funerals: purged.len(), data_items, ..PurgeReport::default() }
+    }
+
+    /// Run the worker that holds each queued funeral at its scheduled_time
+    /// and notifies its webhook, if any
+    pub fn spawn(state: Arc<tokio::sync::Mutex<FuneralScheduler>>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let (wakeup, next) = {
+                    let mut scheduler = state.lock().await;
+                    let private_webhooks = scheduler.private_webhooks;
+                    for completed in scheduler.hold_due_funerals(tokio::time::Instant::now()) {
+                        tokio::spawn(deliver_webhook(state.clone(), private_webhooks, completed));
+                    }
+                    (scheduler.wakeup.clone(), scheduler.next_wakeup())
+                };
+
+                match next {
+                    Some(due) => tokio::select! {
+                        _ = tokio::time::sleep_until(due) => {},
+                        _ = wakeup.notified() => {},
+                    },
+                    None => wakeup.notified().await,
+                }
+            }
+        })
+    }
+
+    /// Shred the data of every funeral due by `now`, or whose scheduled_time the
+    /// clock has passed, leaving tombstones behind, and return the held funerals
+    /// whose webhooks are still to be told
+    fn hold_due_funerals(&mut self, now: tokio::time::Instant) -> Vec<FuneralCompleted> {
+        self.prune_shredded();
+
+        let clock_now = self.clock.now();
+        let (due, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|(at, schedule)| *at <= now || schedule.scheduled_time <= clock_now);
+        self.pending = pending;
+
+        let mut notify = Vec::new();
+        for (_, schedule) in due {
+            self.executed.insert(schedule.ceremony_id.clone());
+            let buried_at = self.clock.now();
+            for data_id in &schedule.data_ids {
+                // Only items the funeral's own user holds are buried
+                let key = (schedule.user_id, data_id.clone());
+                let Some(stored) = self.data.get_mut(&key) else {
+                    continue;
+                };
+                let tombstone = StoredData::Tombstone { ceremony_id: schedule.ceremony_id.clone(), buried_at };
+                if let StoredData::Alive(bytes) = std::mem::replace(stored, tombstone) {
+                    // Zeroizing wipes the bytes when the entry is dropped: on restore, or
+                    // when `prune_shredded` finds the restore window closed
+                    let shredded = ShreddedData { bytes: Zeroizing::new(bytes), shredded_at: buried_at };
+                    self.shredded.insert(key, shredded);
+                }
+            }
+            if schedule.webhook_url.is_some() {
+                self.webhooks.insert(schedule.ceremony_id.clone(), WebhookStatus::Pending);
+                notify.push(FuneralCompleted { schedule, completed_at: buried_at });
+            }
+        }
+        notify
+    }
+}
+
+/// POST a held funeral to its webhook, retrying once, and record how it went
+async fn deliver_webhook(
+    state: Arc<tokio::sync::Mutex<FuneralScheduler>>,
+    private_webhooks: bool,
+    completed: FuneralCompleted,
+) {
+    let Some(url) = completed.schedule.webhook_url.clone() else {
+        return;
+    };
+    let ceremony_id = completed.schedule.ceremony_id.clone();
+
+    let client = match webhook_client(&url, private_webhooks).await {
+        Ok(client) => client,
+        Err(error) => {
+            tracing::error!(ceremony_id = %ceremony_id, error = %error, "funeral webhook refused");
+            state.lock().await.webhooks.insert(ceremony_id, WebhookStatus::Failed { attempts: 0, error });
+            return;
+        },
+    };
+
+    let mut attempts = 0;
+    let status = loop {
+        attempts += 1;
+        let sent = client.post(&url).json(&completed).send().await.and_then(|resp| resp.error_for_status());
+        match sent {
+            Ok(_) => break WebhookStatus::Delivered { attempts },
+            Err(e) if attempts < 2 => {
+                tracing::warn!(ceremony_id = %ceremony_id, error = %e, "funeral webhook failed, retrying");
+                tokio::time::sleep(WEBHOOK_RETRY_DELAY).await;
+            },
+            Err(e) => break WebhookStatus::Failed { attempts, error: e.to_string() },
+        }
+    };
+
+    match &status {
+        WebhookStatus::Failed { error, .. } => {
+            tracing::error!(ceremony_id = %ceremony_id, error = %error, "funeral webhook gave up")
+        },
+        _ => tracing::info!(ceremony_id = %ceremony_id, attempts, "funeral webhook delivered"),
+    }
+    state.lock().await.webhooks.insert(ceremony_id, status);
+}
+
+/// A client for one webhook delivery: redirects are never followed, and unless
+/// `private_webhooks` is set, the host must resolve only to public addresses
+/// and the client is pinned to them so a second lookup can't point it inward
+async fn webhook_client(url: &str, private_webhooks: bool) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none());
+    let builder = if private_webhooks {
+        builder
+    } else {
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid webhook URL: {e}"))?;
+        let port = parsed.port_or_known_default().ok_or("webhook URL has no port")?;
+        let host = parsed.host_str().ok_or("webhook URL has no host")?;
+        let literal = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>();
+        let (domain, addrs): (Option<&str>, Vec<SocketAddr>) = match literal {
+            Ok(ip) => (None, vec![SocketAddr::new(ip, port)]),
+            Err(_) => {
+                let addrs = tokio::net::lookup_host((host, port))
+                    .await
+                    .map_err(|e| format!("cannot resolve webhook host {host}: {e}"))?;
+                (Some(host), addrs.collect())
+            },
+        };
+        if addrs.is_empty() {
+            return Err("webhook host resolved to no addresses".into());
+        }
+        if let Some(internal) = addrs.iter().find(|addr| !is_public_address(addr.ip())) {
+            return Err(format!("webhook host resolves to non-public address {}", internal.ip()));
+        }
+        match domain {
+            Some(domain) => builder.resolve_to_addrs(domain, &addrs),
+            None => builder,
+        }
+    };
+    builder.build().map_err(|e| format!("cannot build webhook client: {e}"))
+}
+
+/// Whether `ip` is on the public internet, i.e. not loopback, private,
+/// link-local, shared, unspecified or otherwise reserved
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b)))
+        },
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_address(mapped.into()),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            },
+        },
+    }
+}
+
+/// The round count whose time, as measured by `time_rounds`, is closest to `target`
+fn tune_rounds(
+    target: std::time::Duration,
+    mut time_rounds: impl FnMut(u32) -> Result<std::time::Duration>,
+) -> Result<u32> {
+    // Double until we overshoot, then binary search the last interval
+    let mut low = MIN_TUNED_ROUNDS;
+    let mut high = MIN_TUNED_ROUNDS;
+    while high < MAX_TUNED_ROUNDS && time_rounds(high)? < target {
+        low = high;
+        high = high.saturating_mul(2).min(MAX_TUNED_ROUNDS);
+    }
+    while high - low > low / 20 {
+        let mid = low + (high - low) / 2;
+        if time_rounds(mid)? < target {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    let low_error = time_rounds(low)?.abs_diff(target);
+    let high_error = time_rounds(high)?.abs_diff(target);
+    Ok(if low_error <= high_error { low } else { high })
+}
+
+/// Claim `count` of the `limit` slots in a sliding `ENCRYPT_RATE_WINDOW`, all
+/// or none; `recent` holds when each live claim was made, oldest first
+fn claim_rate_slots(
+    limit: Option<u32>,
+    recent: &mut VecDeque<SystemTime>,
+    now: SystemTime,
+    count: usize,
+) -> Result<(), TheaterError> {
+    let Some(limit) = limit else {
+        return Ok(());
+    };
+    while recent.front().is_some_and(|&started| started + ENCRYPT_RATE_WINDOW <= now) {
+        recent.pop_front();
+    }
+
+    if recent.len() + count > limit as usize {
+        // Enough slots are free once the oldest `overflow` claims age out;
+        // a batch bigger than the limit never fits, so it waits out the whole window
+        let overflow = recent.len() + count - limit as usize;
+        let frees_up = recent
+            .get(overflow - 1)
+            .or(recent.back())
+            .map_or(now + ENCRYPT_RATE_WINDOW, |&claimed| claimed + ENCRYPT_RATE_WINDOW);
+        let wait = frees_up.duration_since(now).unwrap_or_default();
+        return Err(TheaterError::RateLimited {
+            limit,
+            retry_after_secs: wait.as_secs() + u64::from(wait.subsec_nanos() > 0),
+        });
+    }
+    recent.extend(std::iter::repeat_n(now, count));
+    Ok(())
+}
+
+/// Encryption race participant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaceParticipant {
+    pub name: String,
+    pub encryption_speed: f64,
+    pub vehicle: String,
+    pub trash_talk: String,
+}
+
+/// Header + salt of a new ciphertext: everything before the nonce.
+/// `level_byte` is the level's byte with any flags already set.
+fn ciphertext_prefix(level_byte: u8, cipher: &dyn TheaterCipher, kdf: &Kdf, salt: &[u8]) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(HEADER_LENGTH + salt.len());
+    prefix.extend_from_slice(FORMAT_MAGIC);
+    prefix.push(FORMAT_VERSION);
+    prefix.push(level_byte);
+    prefix.push(cipher.id());
+    kdf.write(&mut prefix);
+    prefix.extend_from_slice(salt);
+    prefix
+}
+
+/// Encrypt with an already derived key, appending nonce + ciphertext to `prefix`.
+/// The header and salt in `prefix` are authenticated along with `aad`.
+fn seal(
+    cipher: &dyn TheaterCipher,
+    key: &DerivedKey,
+    mut prefix: Vec<u8>,
+    nonce: &[u8; NONCE_LENGTH],
+    aad: &[u8],
+    data: &[u8],
+) -> Result<Vec<u8>, TheaterError> {
+    let encrypted = cipher.encrypt(&key.0, nonce, &[aad, &prefix].concat(), data)?;
+
+    prefix.reserve(nonce.len() + encrypted.len());
+    prefix.extend_from_slice(nonce);
+    prefix.extend_from_slice(&encrypted);
+
+    Ok(prefix)
+}
+
+/// What the header of a theater ciphertext records
+struct Header<'a> {
+    version: u8,
+    level: EncryptionLevel,
+    flags: u8,
+    cipher: u8,
+    /// None for formats from before the KDF was recorded, which used PBKDF2
+    kdf: Option<Kdf>,
+    /// Everything after the header
+    body: &'a [u8],
+}
+
+/// Reverse `seal` on a whole ciphertext: read the header, split salt + nonce +
+/// ciphertext and decrypt, deriving the key with `legacy_kdf` if the header
+/// doesn't name one
+fn open_layer(blob: &[u8], password: &str, aad: &[u8], legacy_kdf: Kdf) -> Result<Vec<u8>, TheaterError> {
+    let layer = SealedLayer::split(blob, legacy_kdf)?;
+    let key = layer.kdf.derive(password, &layer.salt)?;
+    layer.open(&key, aad)
+}
+
+/// `open_layer` with the key derivation run on tokio's blocking pool
+async fn open_layer_offloaded(
+    blob: &[u8],
+    password: &str,
+    aad: &[u8],
+    legacy_kdf: Kdf,
+) -> Result<Vec<u8>, TheaterError> {
+    let layer = SealedLayer::split(blob, legacy_kdf)?;
+    let key = derive_key_offloaded(password, layer.salt, layer.kdf).await?;
+    layer.open(&key, aad)
+}
+
+/// One sealed layer of a ciphertext, split up for opening
+struct SealedLayer<'a> {
+    blob: &'a [u8],
+    version: u8,
+    cipher: u8,
+    kdf: Kdf,
+    salt: [u8; SALT_LENGTH],
+    nonce: [u8; NONCE_LENGTH],
+    encrypted: &'a [u8],
+}
+
+impl<'a> SealedLayer<'a> {
+    /// Read the header and split salt + nonce + ciphertext, taking the KDF to
+    /// be `legacy_kdf` if the header doesn't name one
+    fn split(blob: &'a [u8], legacy_kdf: Kdf) -> Result<Self, TheaterError> {
+        let header = read_header(blob)?;
+        let body = header.body;
+        if body.len() < SALT_LENGTH + NONCE_LENGTH {
+            return Err(TheaterError::Decrypt(AuthFailed));
+        }
+        let (salt, rest) = body.split_at(SALT_LENGTH);
+        let (nonce, encrypted) = rest.split_at(NONCE_LENGTH);
+        Ok(SealedLayer {
+            blob,
+            version: header.version,
+            cipher: header.cipher,
+            kdf: header.kdf.unwrap_or(legacy_kdf),
+            salt: salt.try_into().unwrap(),
+            nonce: nonce.try_into().unwrap(),
+            encrypted,
+        })
+    }
+
+    /// Decrypt with the layer's derived key, authenticating against `aad`
+    fn open(&self, key: &DerivedKey, aad: &[u8]) -> Result<Vec<u8>, TheaterError> {
+        let cipher = cipher_for(self.cipher)?;
+        // Before version 3 only the caller's associated data was authenticated
+        let aad = match self.version {
+            FORMAT_VERSION => {
+                let authenticated = self.blob.len() - self.encrypted.len() - NONCE_LENGTH;
+                [aad, &self.blob[..authenticated]].concat()
+            },
+            _ => aad.to_vec(),
+        };
+        cipher.decrypt(&key.0, &self.nonce, &aad, self.encrypted)
+    }
+}
+
+/// Validate the magic and version of a theater ciphertext and read its header
+fn read_header(blob: &[u8]) -> Result<Header<'_>, TheaterError> {
+    if blob.len() < V1_HEADER_LENGTH || &blob[..4] != FORMAT_MAGIC {
+        return Err(TheaterError::BadMagic);
+    }
+    let (cipher, kdf, body) = match blob[4] {
+        1 => (CHACHA20_POLY1305_ID, None, &blob[V1_HEADER_LENGTH..]),
+        2 | FORMAT_VERSION if blob.len() < HEADER_LENGTH => return Err(TheaterError::BadMagic),
+        2 => (blob[6], None, &blob[HEADER_LENGTH..]),
+        FORMAT_VERSION => {
+            let (kdf, body) = Kdf::read(&blob[HEADER_LENGTH..])?;
+            (blob[6], Some(kdf), body)
+        },
+        version => return Err(TheaterError::UnsupportedVersion(version)),
+    };
+    let flags = blob[5] & FLAG_QUANTUM_PREFIXED;
+    let level = EncryptionLevel::from_byte(blob[5] & !FLAG_QUANTUM_PREFIXED)
+        .ok_or_else(|| TheaterError::InvalidLevel(blob[5].to_string()))?;
+    Ok(Header { version: blob[4], level, flags, cipher, kdf, body })
+}
+
+/// Shannon entropy of `data` in bits per byte, from 0 (one repeated byte) to 8
+/// (every byte value equally often). Empty input has none.
+pub fn estimate_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    let total = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Theatrical compression (really deflate, behind a theatrical prefix)
+fn theatrical_compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(DEFLATED_PREFIX.to_vec(), Compression::best());
+    encoder.write_all(data).expect("writing to a Vec can't fail");
+    encoder.finish().expect("writing to a Vec can't fail")
+}
+
+/// Undo `theatrical_compress`, refusing to inflate past `limit`
+/// bytes. Layers from before compression was real only lose their wrapper.
+fn theatrical_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    if let Some(deflated) = data.strip_prefix(DEFLATED_PREFIX) {
+        let mut inflated = Vec::new();
+        DeflateDecoder::new(deflated)
+            .take(limit as u64 + 1)
+            .read_to_end(&mut inflated)
+            .context("Tinfoil layer is not valid deflate data")?;
+        if inflated.len() > limit {
+            anyhow::bail!("Tinfoil layer inflates past {} bytes", limit);
+        }
+        return Ok(inflated);
+    }
+    data.strip_prefix(COMPRESSED_PREFIX)
+        .and_then(|rest| rest.strip_suffix(COMPRESSED_SUFFIX))
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow::anyhow!("Data was not theatrically compressed"))
+}
+
+/// Length-prefix `data` and append decorative `trailer` bytes after it
+fn wrap_with_trailer(data: &[u8], trailer: &[u8]) -> Result<Vec<u8>> {
+    let len = u32::try_from(data.len()).map_err(|_| anyhow::anyhow!("Data too large to wrap"))?;
+    Ok([&len.to_be_bytes()[..], data, trailer].concat())
+}
+
+/// Recover the data from `wrap_with_trailer`, discarding the trailer
+fn unwrap_trailer(wrapped: &[u8]) -> Result<Vec<u8>> {
+    let (len, rest) = wrapped
+        .split_first_chunk::<4>()
+        .ok_or_else(|| anyhow::anyhow!("Wrapped data is missing its length"))?;
+    rest.get(..u32::from_be_bytes(*len) as usize)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| anyhow::anyhow!("Wrapped data is shorter than its length"))
+}
+
+/// Remove combining marks, recovering the text under a zalgo curse. Covers the
+/// combining blocks `add_zalgo_text` and most zalgo generators draw from, not
+/// every nonspacing mark in Unicode.
+pub fn strip_zalgo(text: &str) -> String {
+    text.chars()
+        .filter(|c| {
+            !matches!(
+                *c,
+                '\u{300}'..='\u{36f}'
+                    | '\u{1ab0}'..='\u{1aff}'
+                    | '\u{1dc0}'..='\u{1dff}'
+                    | '\u{20d0}'..='\u{20ff}'
+                    | '\u{fe20}'..='\u{fe2f}'
+            )
+        })
+        .collect()
+}
+
+/// Stable code for a failed theater call: its `TheaterError` code, or INTERNAL
+pub fn error_code(error: &anyhow::Error) -> &'static str {
+    error.downcast_ref::<TheaterError>().map_or("INTERNAL", TheaterError::code)
+}
+
+/// Emit an info event when `operation` succeeded, or an error event carrying
+/// the `TheaterError` code when it failed
+fn trace_outcome<T>(operation: &str, result: &Result<T>) {
+    match result {
+        Ok(_) => tracing::info!(operation, "succeeded"),
+        Err(e) => tracing::error!(operation, error_code = error_code(e), error = %format!("{:#}", e), "failed"),
+    }
+}
+
+/// Read achievements written by `DataTheater::save_achievements`; a missing
+/// file just means nobody has unlocked anything yet
+pub fn load_achievements(path: &Path) -> Result<HashMap<u64, HashSet<EncryptionLevel>>> {
+    match fs::read(path) {
+        Ok(json) => serde_json::from_slice(&json)
+            .with_context(|| format!("Failed to parse achievements in {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// The header written by `DataTheater::encrypt_file`, parsed
+struct StreamHeader {
+    /// Exactly as read, since every chunk authenticates it
+    bytes: Vec<u8>,
+    level: EncryptionLevel,
+    kdf: Kdf,
+    salt: [u8; SALT_LENGTH],
+    nonce_prefix: [u8; STREAM_NONCE_PREFIX_LENGTH],
+}
+
+/// Read and validate a streamed file header from the front of `input`;
+/// version 1 headers don't record their KDF, so they get `legacy_kdf`
+fn read_stream_header(input: &mut impl Read, legacy_kdf: Kdf) -> Result<StreamHeader, TheaterError> {
+    let mut bytes = Vec::new();
+    let mut take = |count: usize, bytes: &mut Vec<u8>| -> Result<(), TheaterError> {
+        let start = bytes.len();
+        bytes.resize(start + count, 0);
+        input.read_exact(&mut bytes[start..]).map_err(|_| TheaterError::BadMagic)
+    };
+
+    take(STREAM_MAGIC.len() + 2, &mut bytes)?;
+    if &bytes[..4] != STREAM_MAGIC {
+        return Err(TheaterError::BadMagic);
+    }
+    let level = EncryptionLevel::from_byte(bytes[5])
+        .ok_or_else(|| TheaterError::InvalidLevel(bytes[5].to_string()))?;
+    let kdf = match bytes[4] {
+        1 => legacy_kdf,
+        STREAM_FORMAT_VERSION => {
+            take(1, &mut bytes)?;
+            take(Kdf::params_length(bytes[6])?, &mut bytes)?;
+            Kdf::read(&bytes[6..])?.0
+        },
+        version => return Err(TheaterError::UnsupportedVersion(version)),
+    };
+
+    let kdf_end = bytes.len();
+    take(SALT_LENGTH + STREAM_NONCE_PREFIX_LENGTH, &mut bytes)?;
+    let rest = &bytes[kdf_end..];
+    let mut salt = [0u8; SALT_LENGTH];
+    salt.copy_from_slice(&rest[..SALT_LENGTH]);
+    let mut nonce_prefix = [0u8; STREAM_NONCE_PREFIX_LENGTH];
+    nonce_prefix.copy_from_slice(&rest[SALT_LENGTH..]);
+
+    Ok(StreamHeader { bytes, level, kdf, salt, nonce_prefix })
+}
+
+/// Nonce for chunk `counter` of a stream
+fn stream_nonce(prefix: &[u8; STREAM_NONCE_PREFIX_LENGTH], counter: u32, last: bool) -> [u8; NONCE_LENGTH] {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    nonce[..STREAM_NONCE_PREFIX_LENGTH].copy_from_slice(prefix);
+    nonce[STREAM_NONCE_PREFIX_LENGTH..NONCE_LENGTH - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_LENGTH - 1] = last as u8;
+    nonce
+}
+
+/// Fill `buf` from `reader`, stopping early only at end of input
+fn read_chunk(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Create `path`, hand it to `write`, and remove it again if writing fails
+fn with_output_file(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut output = BufWriter::new(file);
+    let result = write(&mut output).and_then(|()| Ok(output.flush()?));
+    if result.is_err() {
+        drop(output);
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+/// Encrypt `input` chunk by chunk into `output`
+fn seal_stream(
+    input: impl Read,
+    output: &mut impl Write,
+    key: &DerivedKey,
+    nonce_prefix: &[u8; STREAM_NONCE_PREFIX_LENGTH],
+    header: &[u8],
+) -> Result<()> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key.0));
+    let mut input = BufReader::with_capacity(STREAM_CHUNK_SIZE, input);
+    let mut chunk = Zeroizing::new(vec![0u8; STREAM_CHUNK_SIZE]);
+
+    for counter in 0..=u32::MAX {
+        let len = read_chunk(&mut input, &mut chunk)?;
+        let last = input.fill_buf()?.is_empty();
+        let nonce = stream_nonce(nonce_prefix, counter, last);
+        let sealed = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &chunk[..len], aad: header })
+            .map_err(|_| TheaterError::Encrypt)?;
+        output.write_all(&sealed)?;
+        if last {
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!("File is too large to stream")
+}
+
+/// Decrypt a stream written by `seal_stream` into `output`
+fn open_stream(
+    input: impl Read,
+    output: &mut impl Write,
+    key: &DerivedKey,
+    nonce_prefix: &[u8; STREAM_NONCE_PREFIX_LENGTH],
+    header: &[u8],
+) -> Result<()> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key.0));
+    let mut input = BufReader::with_capacity(STREAM_CHUNK_SIZE + STREAM_TAG_LENGTH, input);
+    let mut chunk = vec![0u8; STREAM_CHUNK_SIZE + STREAM_TAG_LENGTH];
+
+    for counter in 0..=u32::MAX {
+        let len = read_chunk(&mut input, &mut chunk)?;
+        let last = input.fill_buf()?.is_empty();
+        let nonce = stream_nonce(nonce_prefix, counter, last);
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(Nonce::from_slice(&nonce), Payload { msg: &chunk[..len], aad: header })
+                .map_err(|_| TheaterError::Decrypt(AuthFailed))?,
+        );
+        output.write_all(&plaintext)?;
+        if last {
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!("File is too large to stream")
+}
+
+/// How big a race may get
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaceLimits {
+    /// Most racers in one race
+    pub participants: usize,
+    /// Most bytes one race is run over
+    pub data_size: usize,
+}
+
+impl Default for RaceLimits {
+    fn default() -> Self {
+        Self { participants: DEFAULT_MAX_RACE_PARTICIPANTS, data_size: DEFAULT_MAX_RACE_BYTES }
+    }
+}
+
+/// Check a race fits in `limits`: between one and `limits.participants` racers,
+/// all named differently, over no more than `limits.data_size` bytes
+pub fn check_race(participants: &[RaceParticipant], data_size: usize, limits: RaceLimits) -> Result<(), TheaterError> {
+    if participants.is_empty() {
+        return Err(TheaterError::NoParticipants);
+    }
+    if participants.len() > limits.participants {
+        return Err(TheaterError::TooManyParticipants { count: participants.len(), limit: limits.participants });
+    }
+    if data_size > limits.data_size {
+        return Err(TheaterError::RaceTooLarge { size: data_size, limit: limits.data_size });
+    }
+    let mut names = HashSet::with_capacity(participants.len());
+    for participant in participants {
+        if !names.insert(participant.name.as_str()) {
+            return Err(TheaterError::DuplicateParticipant(participant.name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Run an encryption race between uniquely named racers, within `limits`
+pub async fn encryption_race<R: RngCore + CryptoRng + ?Sized>(
+    participants: Vec<RaceParticipant>,
+    data_size: usize,
+    limits: RaceLimits,
+    rng: &mut R,
+) -> Result<RaceResults> {
+    let mut race = RaceInProgress::start(participants, data_size, limits, rng)?;
+    while race.next_finisher().await?.is_some() {}
+    Ok(race.finish(rng))
+}
+
+/// A race under way. Racers report in as they cross the line; dropping the
+/// race calls off everyone still running.
+pub struct RaceInProgress {
+    runners: tokio::task::JoinSet<RaceResult>,
+    finished: Vec<RaceResult>,
+}
+
+impl RaceInProgress {
+    /// Check the lineup and fire the starting gun
+    pub fn start<R: RngCore + CryptoRng + ?Sized>(
+        participants: Vec<RaceParticipant>,
+        data_size: usize,
+        limits: RaceLimits,
+        rng: &mut R,
+    ) -> Result<Self, TheaterError> {
+        check_race(&participants, data_size, limits)?;
+        let mut runners = tokio::task::JoinSet::new();
+
+        for participant in participants {
+            // Random performance modifier
+            let performance = participant.encryption_speed * rng.gen_range(0.8..1.2);
+            let time_ms = RACE_WARMUP_MS.saturating_add(((data_size as f64 / performance) * 1000.0) as u64);
+
+            let result = RaceResult {
+                name: participant.name,
+                time_ms,
+                bytes_per_second: data_size as f64 * 1000.0 / time_ms as f64,
+                vehicle: participant.vehicle,
+                victory_cry: generate_victory_cry(rng),
+                trash_talk: participant.trash_talk,
+                consolation_burn: None,
+            };
+            runners.spawn(async move {
+                tokio::time::sleep(std::time::Duration::from_millis(result.time_ms)).await;
+                result
+            });
+        }
+
+        Ok(Self { finished: Vec::with_capacity(runners.len()), runners })
+    }
+
+    /// Wait for the next racer over the line and its 1-based position, or
+    /// `None` once everyone is in
+    pub async fn next_finisher(&mut self) -> Result<Option<(usize, &RaceResult)>> {
+        // Racers report in the order they actually cross the line
+        match self.runners.join_next().await {
+            Some(finished) => {
+                self.finished.push(finished.context("Race participant crashed")?);
+                Ok(self.finished.last().map(|result| (self.finished.len(), result)))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Burn whoever came last and crown the winner; call once every racer is in
+    pub fn finish<R: RngCore + ?Sized>(self, rng: &mut R) -> RaceResults {
+        let mut results = self.finished;
+        if results.len() > 1 {
+            if let Some(last) = results.last_mut() {
+                last.consolation_burn = Some(generate_consolation_burn(rng));
+            }
+        }
+
+        RaceResults {
+            winner: results[0].name.clone(),
+            results,
+            prize: "A golden encryption key (decorative only)".to_string(),
+        }
+    }
+}
+
+/// Race results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaceResults {
+    pub winner: String,
+    pub results: Vec<RaceResult>,
+    pub prize: String,
+}
+
+/// Outcome of a quick race, including any points the user won
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuickRaceResults {
+    pub race: RaceResults,
+    pub points_awarded: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaceResult {
+    pub name: String,
+    pub time_ms: u64,
+    /// Bytes raced over the whole time, warm-up included
+    pub bytes_per_second: f64,
+    pub vehicle: String,
+    pub victory_cry: String,
+    pub trash_talk: String,
+    /// Only for whoever finished dead last
+    pub consolation_burn: Option<String>,
+}
+
+fn generate_consolation_burn<R: RngCore + ?Sized>(rng: &mut R) -> String {
+    let burns = [
+        "Were you encrypting with a pencil?",
+        "Your cipher called. It wants a faster owner.",
+        "Even ROT13 finished before you.",
+        "Participation trophy: one (1) plaintext.",
+        "Have you tried turning your entropy on?",
+    ];
+    burns[rng.gen_range(0..burns.len())].to_string()
+}
+
+fn generate_victory_cry<R: RngCore + ?Sized>(rng: &mut R) -> String {
+    let cries = [
+        "ENCRYPTED TO THE MOON!",
+        "EAT MY CIPHER DUST!",
+        "CHACHA20 GO BRRRRR!",
+        "WITNESS MY ENTROPY!",
+        "QUANTUM SUPREMACY ACHIEVED!",
+        "I AM THE KEY MASTER!",
+    ];
+    cries[rng.gen_range(0..cries.len())].to_string()
+}
+
+/// Id and display name of the achievement for a user's first encryption at `level`
+fn achievement_for(level: &EncryptionLevel) -> (AchievementId, String) {
+    let (id, name) = match level {
+        EncryptionLevel::Basic => (AchievementId::FirstBasic, "Baby's First Encryption!"),
+        EncryptionLevel::Premium => (AchievementId::FirstPremium, "Premium Member!"),
+        EncryptionLevel::Paranoid => (AchievementId::FirstParanoid, "They're Watching!"),
+        EncryptionLevel::Tinfoil => (AchievementId::FirstTinfoil, "Conspiracy Theorist!"),
+        EncryptionLevel::Quantum => (AchievementId::FirstQuantum, "Quantum Entangled!"),
+        EncryptionLevel::Alien => (AchievementId::FirstAlien, "Area 51 Clearance!"),
+        EncryptionLevel::Eldritch => (AchievementId::FirstEldritch, "Ṃ̷̈́ä̶̤́d̸̰̈ṅ̷̺ë̶́ͅṣ̸̈š̷̱ ̸̜̇Ë̶̤́m̸̰̈ḃ̷̦ṛ̸̈ä̶́ͅč̷̺ë̸̱̇d̷̤̈!"),
+    };
+    (id, name.to_string())
+}
+
+/// When an encryption call began and how much of it was spent on drama
+#[derive(Clone, Copy)]
+struct Timing {
+    start: tokio::time::Instant,
+    theatrical: std::time::Duration,
+}
+
+/// What an encryption drew from the theater, so that key derivation and
+/// sealing can run without holding it
+struct EncryptionPlan {
+    user_id: u64,
+    level: EncryptionLevel,
+    password: Zeroizing<String>,
+    cipher: Arc<dyn TheaterCipher>,
+    kdf: Kdf,
+    transform: Transform,
+    /// Salt and random nonce candidate of each sealed layer, innermost first
+    layers: Vec<([u8; SALT_LENGTH], [u8; NONCE_LENGTH])>,
+    theatrical_elements: Vec<String>,
+}
+
+/// What a level does to the plaintext before sealing it
+enum Transform {
+    None,
+    /// Seal, then seal the base64 of the first ciphertext again (Premium)
+    SealTwice,
+    /// Append padding or a curse after a length prefix (Paranoid, Eldritch)
+    Trailer(String),
+    /// Compress, compress again (pointlessly), encrypt (Tinfoil)
+    Compress,
+    /// Prefix the collapsed tag and flag it in the header (Quantum)
+    Collapse,
+    /// XOR with 42 (the answer to everything), then base64
+    Alien,
+    /// Seal as is under the given header flags, for a layer being rekeyed
+    Reseal { flags: u8 },
+}
+
+/// A sealed plan, before the theater has scored it
+struct Sealed {
+    ciphertext: Vec<u8>,
+    compressed_bytes: Option<usize>,
+    input_entropy_bits_per_byte: f64,
+}
+
+impl EncryptionPlan {
+    /// One key per layer, derived on tokio's blocking pool
+    async fn derive_keys(&self) -> Result<Vec<DerivedKey>, TheaterError> {
+        let mut keys = Vec::with_capacity(self.layers.len());
+        for (salt, _) in &self.layers {
+            keys.push(derive_key_offloaded(&self.password, *salt, self.kdf).await?);
+        }
+        Ok(keys)
+    }
+
+    /// Apply the level's transform to `data` and seal each layer
+    fn seal(&self, data: &[u8], keys: &[DerivedKey], nonces: &[[u8; NONCE_LENGTH]]) -> Result<Sealed> {
+        let mut compressed_bytes = None;
+        let mut flags = 0;
+        let mut input = match &self.transform {
+            Transform::None | Transform::SealTwice => data.to_vec(),
+            Transform::Trailer(trailer) => wrap_with_trailer(data, trailer.as_bytes())?,
+            Transform::Compress => {
+                let compressed = theatrical_compress(&theatrical_compress(data));
+                compressed_bytes = Some(compressed.len());
+                compressed
+            },
+            Transform::Collapse => {
+                flags = FLAG_QUANTUM_PREFIXED;
+                [QUANTUM_COLLAPSED_TAG, data].concat()
+            },
+            Transform::Alien => {
+                let alien_data = data.iter().map(|b| b ^ 42).collect::<Vec<u8>>();
+                base64_text::encode(&alien_data).into_bytes()
+            },
+            Transform::Reseal { flags: reseal_flags } => {
+                flags = *reseal_flags;
+                data.to_vec()
+            },
+        };
+
+        // Bind the ciphertext to its owner so it can't be swapped between users
+        let aad = self.user_id.to_le_bytes();
+        let mut ciphertext = Vec::new();
+        for (index, ((salt, _), (key, nonce))) in self.layers.iter().zip(keys.iter().zip(nonces)).enumerate() {
+            if index > 0 {
+                input = base64_text::encode(&ciphertext).into_bytes();
+            }
+            let prefix = ciphertext_prefix(self.level.to_byte() | flags, self.cipher.as_ref(), &self.kdf, salt);
+            ciphertext = seal(self.cipher.as_ref(), key, prefix, nonce, &aad, &input)?;
+        }
+
+        Ok(Sealed { ciphertext, compressed_bytes, input_entropy_bits_per_byte: estimate_entropy(data) })
+    }
+}
+
+/// Everything decrypting a ciphertext needs from the theater, so the key
+/// derivations can run without it
+struct DecryptionPlan {
+    level: EncryptionLevel,
+    flags: u8,
+    password: Zeroizing<String>,
+    /// Associated data every layer was sealed with
+    aad: [u8; 8],
+    /// KDF of layers whose header is from before the KDF was recorded
+    legacy_kdf: Kdf,
+    /// Most bytes a Tinfoil layer may inflate to
+    inflate_limit: usize,
+}
+
+impl DecryptionPlan {
+    /// Open each layer of `ciphertext` and undo the level's transform
+    fn open(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let open = |blob: &[u8]| open_layer(blob, &self.password, &self.aad, self.legacy_kdf);
+        let data = match self.level {
+            EncryptionLevel::Basic => open(ciphertext)?,
+            EncryptionLevel::Premium => {
+                let inner = base64_text::decode(open(ciphertext)?)
+                    .context("Premium inner layer is not valid base64")?;
+                open(&inner)?
+            },
+            EncryptionLevel::Paranoid => unwrap_trailer(&open(ciphertext)?)?,
+            EncryptionLevel::Tinfoil => {
+                let once = theatrical_decompress(&open(ciphertext)?, self.inflate_limit)?;
+                theatrical_decompress(&once, self.inflate_limit)?
+            },
+            EncryptionLevel::Quantum => {
+                let observed = open(ciphertext)?;
+                if self.flags & FLAG_QUANTUM_PREFIXED == 0 {
+                    observed
+                } else {
+                    observed
+                        .strip_prefix(QUANTUM_COLLAPSED_TAG)
+                        .ok_or_else(|| anyhow::anyhow!("Collapsed quantum data lost its prefix"))?
+                        .to_vec()
+                }
+            },
+            EncryptionLevel::Alien => {
+                let alien_data = base64_text::decode(open(ciphertext)?)
+                    .context("Alien layer is not valid base64")?;
+                alien_data.iter().map(|b| b ^ 42).collect()
+            },
+            EncryptionLevel::Eldritch => unwrap_trailer(&open(ciphertext)?)?,
+        };
+
+        Ok(data)
+    }
+}
+
+/// Sleep for a dramatic pause of `ms`, returning how long it took and telling
+/// `progress` how far along it is every `PROGRESS_INTERVAL`, then 1.0 at the end
+async fn pause_for(ms: u64, progress: Option<&(dyn Fn(f32) + Send + Sync)>) -> std::time::Duration {
+    let started = tokio::time::Instant::now();
+    let total = tokio::time::Duration::from_millis(ms);
+    let Some(progress) = progress else {
+        tokio::time::sleep(total).await;
+        return started.elapsed();
+    };
+
+    let mut tick = PROGRESS_INTERVAL;
+    while tick < total {
+        tokio::time::sleep_until(started + tick).await;
+        progress(tick.as_secs_f32() / total.as_secs_f32());
+        tick += PROGRESS_INTERVAL;
+    }
+    tokio::time::sleep_until(started + total).await;
+    progress(1.0);
+    started.elapsed()
+}
+
+/// How `plan_encryption` picks the salt for a ciphertext
+enum Salting {
+    /// A fresh random salt and key derivation per ciphertext
+    Fresh,
+    /// One salt, and so one cached key, shared by a whole batch
+    Batch { user_id: u64, salt: [u8; SALT_LENGTH] },
+}
+
+/// A derived encryption key, wiped from memory when dropped
+#[derive(Clone)]
+struct DerivedKey([u8; 32]);
+
+impl Drop for DerivedKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// How encryption keys are derived from passwords
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Kdf {
+    Pbkdf2 { rounds: u32 },
+    /// Memory-hard, so GPUs help an attacker far less than against PBKDF2
+    Argon2id { mem_kib: u32, iters: u32, parallelism: u32 },
+}
+
+impl Kdf {
+    /// Reject parameters that can't derive a key or would take absurdly long
+    fn validate(&self) -> Result<(), TheaterError> {
+        match *self {
+            Kdf::Pbkdf2 { rounds } if !(1..=MAX_PBKDF2_ROUNDS).contains(&rounds) => Err(TheaterError::InvalidKdf(
+                format!("PBKDF2 rounds must be between 1 and {}, got {}", MAX_PBKDF2_ROUNDS, rounds),
+            )),
+            Kdf::Pbkdf2 { .. } => Ok(()),
+            Kdf::Argon2id { mem_kib, iters, parallelism } => {
+                if mem_kib > MAX_ARGON2_MEM_KIB || iters > MAX_ARGON2_ITERS || parallelism > MAX_ARGON2_PARALLELISM {
+                    return Err(TheaterError::InvalidKdf(format!(
+                        "Argon2id allows at most {} KiB, {} iterations and {} lanes",
+                        MAX_ARGON2_MEM_KIB, MAX_ARGON2_ITERS, MAX_ARGON2_PARALLELISM
+                    )));
+                }
+                argon2::Params::new(mem_kib, iters, parallelism, Some(32))
+                    .map(|_| ())
+                    .map_err(|e| TheaterError::InvalidKdf(format!("Argon2id: {}", e)))
+            },
+        }
+    }
+
+    /// Append the KDF id and its parameters to a ciphertext header
+    fn write(&self, out: &mut Vec<u8>) {
+        match *self {
+            Kdf::Pbkdf2 { rounds } => {
+                out.push(KDF_PBKDF2);
+                out.extend_from_slice(&rounds.to_le_bytes());
+            },
+            Kdf::Argon2id { mem_kib, iters, parallelism } => {
+                out.push(KDF_ARGON2ID);
+                for param in [mem_kib, iters, parallelism] {
+                    out.extend_from_slice(&param.to_le_bytes());
+                }
+            },
+        }
+    }
+
+    /// How many bytes of parameters `write` puts after KDF id `id`
+    fn params_length(id: u8) -> Result<usize, TheaterError> {
+        match id {
+            KDF_PBKDF2 => Ok(4),
+            KDF_ARGON2ID => Ok(3 * 4),
+            _ => Err(TheaterError::InvalidKdf(format!("unknown KDF id {}", id))),
+        }
+    }
+
+    /// Read what `write` wrote, returning the KDF and the bytes after it
+    fn read(bytes: &[u8]) -> Result<(Kdf, &[u8]), TheaterError> {
+        let (&id, rest) = bytes.split_first().ok_or(TheaterError::BadMagic)?;
+        let length = Kdf::params_length(id)?;
+        if rest.len() < length {
+            return Err(TheaterError::BadMagic);
+        }
+        let (params, rest) = rest.split_at(length);
+        let param = |i: usize| u32::from_le_bytes(params[i * 4..i * 4 + 4].try_into().unwrap());
+
+        let kdf = match id {
+            KDF_PBKDF2 => Kdf::Pbkdf2 { rounds: param(0) },
+            _ => Kdf::Argon2id { mem_kib: param(0), iters: param(1), parallelism: param(2) },
+        };
+        kdf.validate()?;
+        Ok((kdf, rest))
+    }
+
+    fn derive(&self, password: &str, salt: &[u8]) -> Result<DerivedKey, TheaterError> {
+        match *self {
+            Kdf::Pbkdf2 { rounds } => derive_key(password, salt, rounds),
+            Kdf::Argon2id { mem_kib, iters, parallelism } => {
+                let params = argon2::Params::new(mem_kib, iters, parallelism, Some(32))
+                    .map_err(|_| TheaterError::KeyDerivation)?;
+                let mut key = DerivedKey([0u8; 32]);
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(password.as_bytes(), salt, &mut key.0)
+                    .map_err(|_| TheaterError::KeyDerivation)?;
+                Ok(key)
+            },
+        }
+    }
+}
+
+/// `Kdf::derive` on tokio's blocking pool
+async fn derive_key_offloaded(
+    password: &str,
+    salt: [u8; SALT_LENGTH],
+    kdf: Kdf,
+) -> Result<DerivedKey, TheaterError> {
+    let password = Zeroizing::new(password.to_string());
+    tokio::task::spawn_blocking(move || kdf.derive(&password, &salt))
+        .await
+        .map_err(|_| TheaterError::KeyDerivation)?
+}
+
+/// Derive key from password (reusing from the main crypto module)
+fn derive_key(password: &str, salt: &[u8], rounds: u32) -> Result<DerivedKey, TheaterError> {
+    let salt_string = SaltString::encode_b64(salt).map_err(|_| TheaterError::KeyDerivation)?;
+    
+    let hash = Pbkdf2
+        .hash_password_customized(
+            password.as_bytes(),
+            None,
+            None,
+            pbkdf2::Params {
+                rounds,
+                output_length: 32,
+            },
+            &salt_string,
+        )
+        .map_err(|_| TheaterError::KeyDerivation)?;
+    
+    let output = hash.hash.ok_or(TheaterError::KeyDerivation)?;
+    let key: [u8; 32] = output
+        .as_bytes()
+        .try_into()
+        .map_err(|_| TheaterError::KeyDerivation)?;
+
+    Ok(DerivedKey(key))
+}
+
+/// Internals `benches/crypto.rs` times directly; not a stable API
+#[doc(hidden)]
+pub mod bench {
+    use super::*;
+
+    /// Run one PBKDF2-SHA256 derivation at `rounds`, discarding the key
+    pub fn derive_key(rounds: u32, password: &str, salt: &[u8]) -> Result<(), TheaterError> {
+        Kdf::Pbkdf2 { rounds }.derive(password, salt).map(drop)
+    }
+
+    /// Seal `data` at Basic level with a fresh salt and nonce, key derivation included
+    pub fn basic_encrypt(theater: &mut DataTheater, data: &[u8], password: &str) -> Result<Vec<u8>, TheaterError> {
+        theater.basic_encrypt(data, password, &[], &EncryptionLevel::Basic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn over_budget_encryption_is_audited_as_failed() {
+        let mut theater = DataTheater::new();
+        theater.credit_points(7, 50);
+
+        let result = theater
+            .purchase_encryption(7, b"secrets", EncryptionLevel::Eldritch)
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(theater.balance(7), 50);
+        let entry = theater.audit_log().last().unwrap();
+        assert_eq!(entry.user_id, 7);
+        assert_eq!(entry.points_delta, 0);
+        assert_eq!(
+            entry.outcome,
+            AuditOutcome::Failed("Insufficient points: need 66666, have 50".to_string())
+        );
+    }
+
+    #[test]
+    fn every_level_has_its_cost_and_points() {
+        for level in EncryptionLevel::ALL {
+            // Exhaustive so a new level can't slip in without a price
+            let expected = match level {
+                EncryptionLevel::Basic => (100, 100),
+                EncryptionLevel::Premium => (500, 500),
+                EncryptionLevel::Paranoid => (1000, 1000),
+                EncryptionLevel::Tinfoil => (5000, 2500),
+                EncryptionLevel::Quantum => (10000, 5000),
+                EncryptionLevel::Alien => (25000, 7500),
+                EncryptionLevel::Eldritch => (66666, 66666),
+            };
+            let LevelConfig { cost, points, .. } = *TheaterConfig::default().level(&level);
+            assert_eq!((cost, points), expected, "{}", level);
+        }
+    }
+
+    #[test]
+    fn certificate_scores_stay_in_range() {
+        let mut theater = seeded_theater(9);
+        for _ in 0..500 {
+            let certificate = theater.generate_certificate(4, "ada@example.com", 12);
+            assert!((900..=999).contains(&certificate.security_score));
+            assert!((3..=13).contains(&certificate.layers));
+            assert!(certificate.certificate_id.starts_with("CERT-4-"));
+            assert!(certificate.quantum_signature.ends_with(" (QUANTUM VERIFIED)"));
+        }
+
+        let (a, b) = (seeded_theater(3), seeded_theater(3));
+        let [a, b] = [a, b].map(|mut t| t.generate_certificate(1, "x", 0).quantum_signature);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn certificates_verify_until_tampered_with() {
+        let mut theater = seeded_theater(5);
+        let public_key = theater.public_key();
+        let certificate = theater.generate_certificate(4, "ada@example.com", 12);
+        assert!(verify_certificate(&certificate, &public_key));
+
+        let mut tampered = certificate.clone();
+        tampered.security_score = 1000;
+        assert!(!verify_certificate(&tampered, &public_key));
+
+        let mut forged = certificate.clone();
+        forged.signature = "not base64!".to_string();
+        assert!(!verify_certificate(&forged, &public_key));
+
+        assert!(!verify_certificate(&certificate, &seeded_theater(6).public_key()));
+    }
+
+    #[test]
+    fn threat_level_follows_the_unencrypted_share() {
+        let theater = fast_theater();
+
+        let calm = theater.threat_level(1, 50, 0);
+        assert_eq!((calm.severity, calm.level.as_str()), (1, "RAINBOW UNICORN"));
+        assert_eq!(calm.recommended_action, "No action needed. Pet a unicorn.");
+
+        let doomed = theater.threat_level(1, 0, 50);
+        assert_eq!((doomed.severity, doomed.level.as_str()), (10, "BEIGE NIGHTMARE"));
+        assert_eq!(doomed.recommended_action, "PANIC! Then have some beige tea.");
+
+        assert_eq!(theater.threat_level(1, 0, 0).severity, 1);
+        assert!(theater.threat_level(1, 50, 50).severity > calm.severity);
+        assert!(theater.threat_level(1, u64::MAX, u64::MAX).severity < doomed.severity);
+    }
+
+    #[test]
+    fn estimate_reports_the_pause_without_encrypting() {
+        let theater = DataTheater::new().with_drama_factor(0.5).unwrap();
+
+        let estimate = theater.estimate(&EncryptionLevel::Eldritch);
+        assert_eq!(estimate.base_delay_ms, 6666);
+        assert_eq!(estimate.expected_delay_ms, 3333);
+        assert_eq!((estimate.cost, estimate.points_earned), (66666, 66666));
+        assert_eq!(estimate.theatrical_elements.len(), 3);
+        assert_eq!(theater.balance(1), 0);
+
+        let sober = theater.without_theatrics().estimate(&EncryptionLevel::Eldritch);
+        assert_eq!(sober.base_delay_ms, 6666);
+        assert_eq!(sober.expected_delay_ms, 0);
+        assert!(sober.theatrical_elements.is_empty());
+    }
+
+    #[tokio::test]
+    async fn dry_run_previews_without_unlocking_anything() {
+        let mut theater = fast_theater();
+        theater.encrypt_with_drama(2, b"x", EncryptionLevel::Basic, None).await.unwrap();
+        let before = theater.achievements.clone();
+
+        let preview = theater.dry_run_encryption(2, EncryptionLevel::Tinfoil);
+        assert!(preview.ciphertext.is_empty());
+        assert_eq!(preview.achievement_id, Some(AchievementId::FirstTinfoil));
+        assert_eq!((preview.cost, preview.points_earned), (5000, 2500));
+        assert!(preview.data_id.starts_with("GONGLE-2-"));
+        assert_eq!(theater.achievements, before);
+
+        assert_eq!(theater.dry_run_encryption(2, EncryptionLevel::Basic).achievement_id, None);
+    }
+
+    #[test]
+    fn loot_boxes_cost_points() {
+        let mut theater = seeded_theater(4);
+        theater.credit_points(3, LOOT_BOX_COST + 10);
+
+        let loot = theater.open_loot_box(3).unwrap();
+        assert_eq!(theater.balance(3), 10 + loot.bonus);
+        assert_eq!(theater.audit_log().last().unwrap().points_delta, loot.bonus as i64 - LOOT_BOX_COST as i64);
+
+        theater.credit_points(4, LOOT_BOX_COST - 1);
+        assert!(matches!(
+            theater.open_loot_box(4),
+            Err(TheaterError::InsufficientPoints { need: LOOT_BOX_COST, have }) if have == LOOT_BOX_COST - 1
+        ));
+        assert_eq!(theater.balance(4), LOOT_BOX_COST - 1);
+        assert!(theater.collection(4).is_empty());
+    }
+
+    #[test]
+    fn opened_loot_boxes_join_the_collection() {
+        let mut theater = seeded_theater(9);
+        theater.credit_points(5, 3 * LOOT_BOX_COST);
+        let opened: Vec<String> = (0..3).map(|_| theater.open_loot_box(5).unwrap().algorithm).collect();
+
+        let collection = theater.collection(5);
+        let collected: Vec<String> = collection.iter().map(|loot| loot.algorithm.clone()).collect();
+        assert_eq!(collected, opened);
+        assert!(theater.collection(6).is_empty());
+
+        let groups = group_collection(&collection);
+        assert_eq!(groups.iter().map(|group| group.count).sum::<usize>(), 3);
+        assert!(groups.windows(2).all(|pair| {
+            let rank = |rarity| Rarity::ALL.iter().position(|r| *r == rarity);
+            rank(pair[0].rarity) < rank(pair[1].rarity)
+        }));
+    }
+
+    #[tokio::test]
+    async fn leaderboard_ranks_by_points_then_user_id() {
+        let mut theater = fast_theater();
+        for (user_id, points) in [(4, 500), (2, 900), (9, 500), (7, 100)] {
+            theater.credit_points(user_id, points);
+        }
+        theater.encrypt_with_drama(2, b"x", EncryptionLevel::Basic, None).await.unwrap();
+        theater.encrypt_with_drama(2, b"x", EncryptionLevel::Alien, None).await.unwrap();
+        theater.encrypt_with_drama(2, b"x", EncryptionLevel::Alien, None).await.unwrap();
+        theater.encrypt_with_drama(4, b"x", EncryptionLevel::Premium, None).await.unwrap();
+        theater.encrypt_with_drama(4, b"x", EncryptionLevel::Basic, None).await.unwrap();
+
+        let board = theater.leaderboard(3);
+        let ranks: Vec<(u64, u32)> = board.iter().map(|entry| (entry.user_id, entry.points)).collect();
+        assert_eq!(ranks, [(2, 900), (4, 500), (9, 500)]);
+        assert_eq!(board[0].favorite_level, Some(EncryptionLevel::Alien));
+        assert_eq!(board[1].favorite_level, Some(EncryptionLevel::Premium));
+        assert_eq!(board[2].favorite_level, None);
+
+        for user_id in 100..250 {
+            theater.credit_points(user_id, 1);
+        }
+        assert_eq!(theater.leaderboard(usize::MAX).len(), MAX_LEADERBOARD_SIZE);
+    }
+
+    #[test]
+    fn points_saturate_instead_of_wrapping() {
+        let mut theater = fast_theater();
+        theater.credit_points(7, u32::MAX);
+        theater.credit_points(7, u32::MAX);
+        theater.credit_points(7, 1);
+        assert_eq!(theater.balance(7), u32::MAX);
+
+        theater.debit_points(7, u32::MAX - 10).unwrap();
+        assert_eq!(theater.balance(7), 10);
+        assert!(matches!(
+            theater.debit_points(7, 11),
+            Err(TheaterError::InsufficientPoints { need: 11, have: 10 })
+        ));
+        assert_eq!(theater.balance(7), 10);
+    }
+
+    /// Randomness that never changes, so every salt and nonce collides
+    struct StuckRng;
+
+    impl RngCore for StuckRng {
+        fn next_u32(&mut self) -> u32 {
+            0
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(0);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for StuckRng {}
+
+    #[test]
+    fn nonce_store_rejects_a_repeated_nonce() {
+        let mut theater = fast_theater()
+            .with_rng(StuckRng)
+            .with_nonce_store(crate::nonce::MemoryNonceStore::new());
+        let level = EncryptionLevel::Basic;
+
+        theater.basic_encrypt(b"first", "pw", b"", &level).unwrap();
+        let err = theater.basic_encrypt(b"second", "pw", b"", &level).unwrap_err();
+        assert!(matches!(err, TheaterError::NonceReuse));
+        theater.basic_encrypt(b"other key", "other pw", b"", &level).unwrap();
+
+        let mut counted = fast_theater()
+            .with_rng(StuckRng)
+            .with_nonce_strategy(NonceStrategy::Counter)
+            .with_nonce_store(crate::nonce::MemoryNonceStore::new());
+        let first = counted.basic_encrypt(b"first", "pw", b"", &level).unwrap();
+        let second = counted.basic_encrypt(b"second", "pw", b"", &level).unwrap();
+        assert_eq!(counted.basic_decrypt(&second, "pw", b"").unwrap(), b"second");
+        assert_ne!(read_header(&first).unwrap().body, read_header(&second).unwrap().body);
+    }
+
+    #[tokio::test]
+    async fn configured_cost_is_charged() {
+        let mut text = toml::to_string(&TheaterConfig::default()).unwrap();
+        text = text.replace("cost = 100\n", "cost = 40\n");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("theater.toml");
+        fs::write(&path, text).unwrap();
+
+        let mut theater = DataTheater::from_config(&path)
+            .unwrap()
+            .with_pbkdf2_rounds(1000)
+            .unwrap()
+            .without_theatrics();
+        assert_eq!(theater.estimate(&EncryptionLevel::Basic).cost, 40);
+        assert_eq!(economy_table(theater.config()).levels[0].cost, 40);
+
+        theater.credit_points(7, 50);
+        let result = theater.purchase_encryption(7, b"secrets", EncryptionLevel::Basic).await.unwrap();
+        assert_eq!(result.cost, 40);
+        assert_eq!(theater.balance(7), 50 - 40 + 100);
+    }
+
+    #[tokio::test]
+    async fn eldritch_round_trips_at_any_zalgo_intensity() {
+        for intensity in [0, DEFAULT_ZALGO_INTENSITY, u8::MAX] {
+            let mut theater = fast_theater().with_zalgo_intensity(intensity);
+            let curse = theater.add_zalgo_text(ELDRITCH_INCANTATION);
+            assert_eq!(strip_zalgo(&curse), ELDRITCH_INCANTATION);
+            assert!(curse.chars().count() <= ELDRITCH_INCANTATION.chars().count() * (1 + intensity as usize));
+
+            // The curse lives beside the data, so marks the user wrote survive
+            for text in ["the stars are right", "noe\u{308}l"] {
+                let result = theater
+                    .encrypt_with_drama(1, text.as_bytes(), EncryptionLevel::Eldritch, None)
+                    .await
+                    .unwrap();
+                assert_eq!(theater.decrypt_auto(1, &result.ciphertext, None).unwrap(), text.as_bytes());
+            }
+        }
+        assert_eq!(fast_theater().with_zalgo_intensity(0).add_zalgo_text("plain"), "plain");
+    }
+
+    #[tokio::test]
+    async fn rotated_blobs_open_only_with_the_new_password() {
+        let mut theater = fast_theater();
+        for level in EncryptionLevel::ALL {
+            let items = vec!["rotate me".to_string()];
+            let original = theater.batch_encrypt(1, items, level.clone(), "old").await.unwrap().remove(0).ciphertext;
+
+            let rotated = theater.rotate_key(1, &original, "old", "new", level.clone()).await.unwrap();
+            assert_ne!(rotated, original, "{}", level);
+            let (before, after) = (read_header(&original).unwrap(), read_header(&rotated).unwrap());
+            assert_eq!((before.level, before.flags), (after.level, after.flags));
+            assert_eq!(theater.decrypt_auto(1, &rotated, Some("new")).unwrap(), b"rotate me", "{}", level);
+            assert!(theater.decrypt_auto(1, &rotated, Some("old")).is_err(), "{}", level);
+
+            let wrong = theater.rotate_key(1, &original, "wrong", "new", level.clone()).await.unwrap_err();
+            assert!(matches!(wrong.downcast_ref(), Some(TheaterError::Decrypt(_))), "{}", level);
+        }
+
+        let basic = theater.batch_encrypt(1, vec!["x".to_string()], EncryptionLevel::Basic, "old").await.unwrap();
+        let mismatch = theater
+            .rotate_key(1, &basic[0].ciphertext, "old", "new", EncryptionLevel::Alien)
+            .await
+            .unwrap_err();
+        assert!(matches!(mismatch.downcast_ref(), Some(TheaterError::LevelMismatch { .. })));
+    }
+
+    #[tokio::test]
+    async fn try_all_finds_the_level_on_its_own() {
+        let mut theater = fast_theater();
+        let alien = theater.encrypt_with_drama(1, b"take me to your leader", EncryptionLevel::Alien, None).await.unwrap();
+        let (level, data) = theater.decrypt_try_all(1, &alien.ciphertext, None).unwrap();
+        assert_eq!((level, data.as_slice()), (EncryptionLevel::Alien, b"take me to your leader".as_slice()));
+
+        // A level byte nobody recognizes is tried as every level in turn
+        let mut damaged = alien.ciphertext.clone();
+        damaged[5] = 0x7f;
+        assert!(theater.decrypt_auto(1, &damaged, None).is_err());
+        assert_eq!(theater.decrypt_try_all(1, &damaged, None).unwrap().0, EncryptionLevel::Alien);
+
+        // Bare salt || nonce || ciphertext from before the header existed
+        let sealed = legacy_layer(1, EncryptionLevel::Basic.to_byte(), "pw", &1u64.to_le_bytes(), b"legacy");
+        let bare = read_header(&sealed).unwrap().body.to_vec();
+        let (level, data) = theater.decrypt_try_all(1, &bare, Some("pw")).unwrap();
+        assert_eq!((level, data.as_slice()), (EncryptionLevel::Basic, b"legacy".as_slice()));
+
+        let nothing = theater.decrypt_try_all(1, &bare, Some("not pw")).unwrap_err();
+        assert!(matches!(nothing.downcast_ref(), Some(TheaterError::Undecryptable)));
+    }
+
+    #[tokio::test]
+    async fn entropy_flags_input_that_is_already_random() {
+        assert_eq!(estimate_entropy(&[0u8; 4096]), 0.0);
+        assert_eq!(estimate_entropy(b""), 0.0);
+        assert_eq!(estimate_entropy(&(0..=255).collect::<Vec<u8>>()), 8.0);
+
+        let mut noise = vec![0u8; 64 * 1024];
+        OsRng.fill_bytes(&mut noise);
+        let entropy = estimate_entropy(&noise);
+        assert!(entropy > 7.99 && entropy <= 8.0, "{}", entropy);
+
+        let mut theater = DataTheater::new().with_pbkdf2_rounds(1000).unwrap();
+        theater.drama_factor = 0.0;
+        let warning = "This already looks encrypted, you fool.".to_string();
+        let random = theater.encrypt_with_drama(1, &noise, EncryptionLevel::Basic, None).await.unwrap();
+        assert!(random.input_entropy_bits_per_byte > ALREADY_ENCRYPTED_ENTROPY);
+        assert!(random.theatrical_elements.contains(&warning));
+        let zeros = theater.encrypt_with_drama(1, &[0u8; 4096], EncryptionLevel::Basic, None).await.unwrap();
+        assert_eq!(zeros.input_entropy_bits_per_byte, 0.0);
+        assert!(!zeros.theatrical_elements.contains(&warning));
+    }
+
+    #[tokio::test]
+    async fn tinfoil_reports_a_real_compression_ratio() {
+        let mut theater = fast_theater();
+        let data = "they are listening ".repeat(1000);
+        let result = theater.encrypt_with_drama(1, data.as_bytes(), EncryptionLevel::Tinfoil, None).await.unwrap();
+        let ratio = result.compression_ratio.unwrap();
+        assert!(ratio < 1.0, "{}", ratio);
+        assert!(result.compressed_bytes.unwrap() < data.len());
+        assert!(result.ciphertext.len() < data.len());
+        assert_eq!(theater.decrypt_auto(1, &result.ciphertext, None).unwrap(), data.as_bytes());
+
+        let basic = theater.encrypt_with_drama(1, data.as_bytes(), EncryptionLevel::Basic, None).await.unwrap();
+        assert_eq!((basic.compression_ratio, basic.compressed_bytes), (None, None));
+        let empty = theater.encrypt_with_drama(1, b"", EncryptionLevel::Tinfoil, None).await.unwrap();
+        assert_eq!(empty.compression_ratio, None);
+
+        // Wrapped the old way, before compression was real
+        let wrapped = [COMPRESSED_PREFIX, COMPRESSED_PREFIX, b"old news", COMPRESSED_SUFFIX, COMPRESSED_SUFFIX].concat();
+        let password = theater.generate_theatrical_password(1, &EncryptionLevel::Tinfoil);
+        let old = theater.basic_encrypt(&wrapped, &password, &1u64.to_le_bytes(), &EncryptionLevel::Tinfoil).unwrap();
+        assert_eq!(theater.decrypt_auto(1, &old, None).unwrap(), b"old news");
+    }
+
+    #[tokio::test]
+    async fn empty_data_round_trips_at_every_level() {
+        let mut theater = fast_theater();
+        for level in EncryptionLevel::ALL {
+            let result = theater.encrypt_with_drama(1, b"", level.clone(), None).await.unwrap();
+            assert!(!result.ciphertext.is_empty(), "{}", level);
+            assert_eq!(theater.decrypt_auto(1, &result.ciphertext, None).unwrap(), b"", "{}", level);
+        }
+
+        let sealed = theater.basic_encrypt(b"", "pw", b"", &EncryptionLevel::Basic).unwrap();
+        assert_eq!(theater.basic_decrypt(&sealed, "pw", b"").unwrap(), b"");
+
+        let dir = tempfile::tempdir().unwrap();
+        let (empty, sealed, opened) = (dir.path().join("empty"), dir.path().join("sealed"), dir.path().join("opened"));
+        fs::write(&empty, b"").unwrap();
+        theater.encrypt_file(&empty, &sealed, EncryptionLevel::Alien, "pw").await.unwrap();
+        theater.decrypt_file(&sealed, &opened, "pw").await.unwrap();
+        assert_eq!(fs::read(&opened).unwrap(), b"");
+
+        let space = FuneralType::Space { trajectory: "sun".to_string(), escape_velocity: 11.2 };
+        let err = theater.schedule_funeral(1, Vec::new(), space, None).await.unwrap_err();
+        assert_eq!(error_code(&err), "INVALID_FUNERAL_PARAM");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn oversized_input_fails_before_the_pause() {
+        let mut theater = DataTheater::new().with_max_input_bytes(8);
+        let started = tokio::time::Instant::now();
+
+        let err = theater.encrypt_with_drama(1, b"ten bytes!", EncryptionLevel::Eldritch, None).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<TheaterError>(),
+            Some(TheaterError::InputTooLarge { size: 10, limit: 8 })
+        ));
+        let err = theater
+            .encrypt_batch(1, &["ok".to_string(), "far too long".to_string()], EncryptionLevel::Eldritch)
+            .await
+            .unwrap_err();
+        assert_eq!(error_code(&err), "INPUT_TOO_LARGE");
+        assert_eq!(started.elapsed(), std::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn signing_key_survives_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("signing.key");
+
+        let mut first = seeded_theater(1).with_signing_key_from(&path).unwrap();
+        let certificate = first.generate_certificate(4, "ada@example.com", 12);
+        let second = seeded_theater(2).with_signing_key_from(&path).unwrap();
+
+        assert_eq!(first.public_key(), second.public_key());
+        assert!(verify_certificate(&certificate, &second.public_key()));
+
+        fs::write(&path, b"short").unwrap();
+        assert!(seeded_theater(3).with_signing_key_from(&path).is_err());
+    }
+
+    #[tokio::test]
+    async fn purchase_applies_cost_then_points() {
+        let mut theater = fast_theater();
+        theater.credit_points(7, 6000);
+
+        let result = theater
+            .purchase_encryption(7, b"secrets", EncryptionLevel::Tinfoil)
+            .await
+            .unwrap();
+
+        assert_eq!((result.cost, result.points_earned), (5000, 2500));
+        assert_eq!(theater.balance(7), 6000 - 5000 + 2500);
+        assert_eq!(theater.audit_log().last().unwrap().points_delta, -2500);
+    }
+
+    #[tokio::test]
+    async fn failed_encryption_keeps_the_points() {
+        let mut theater = fast_theater();
+        theater.credit_points(7, 6000);
+        theater.fail_encryption = Some(0);
+
+        let result = theater
+            .purchase_encryption(7, b"secrets", EncryptionLevel::Tinfoil)
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(theater.balance(7), 6000);
+        let entry = theater.audit_log().last().unwrap();
+        assert_eq!(entry.points_delta, 0);
+        assert_eq!(entry.outcome, AuditOutcome::Failed("Encryption failed".to_string()));
+    }
+
+    #[tokio::test]
+    async fn batch_failing_part_way_charges_nothing() {
+        let mut theater = fast_theater().with_billing();
+        theater.credit_points(7, 10_000);
+        let items: Vec<String> = (0..6).map(|i| format!("row {}", i)).collect();
+        theater.fail_encryption = Some(3);
+
+        assert!(theater.encrypt_batch(7, &items, EncryptionLevel::Premium).await.is_err());
+        assert_eq!(theater.balance(7), 10_000);
+        assert!(theater.audit_log().iter().all(|entry| entry.outcome != AuditOutcome::Success));
+        assert!(theater.export_user(7).encrypted_items.is_empty());
+
+        theater.fail_encryption = None;
+        let results = theater.encrypt_batch(7, &items, EncryptionLevel::Premium).await.unwrap();
+        let LevelConfig { cost, points, .. } = *theater.config.level(&EncryptionLevel::Premium);
+        assert!(results.iter().all(|result| result.charged));
+        assert_eq!(theater.balance(7), 10_000 - 6 * cost + 6 * points);
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn failed_encryption_emits_an_error_event() {
+        let mut theater = fast_theater();
+        theater.fail_encryption = Some(0);
+
+        assert!(theater.encrypt_with_drama(7, b"secrets", EncryptionLevel::Basic, None).await.is_err());
+
+        assert!(logs_contain("encrypt_with_drama{user_id=7 level=Basic bytes=7}"));
+        assert!(logs_contain("error_code=\"ENCRYPTION_FAILED\""));
+        assert!(!logs_contain("succeeded"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn progress_ticks_through_the_dramatic_pause() {
+        let mut theater = fast_theater();
+        theater.drama_factor = 10.0;
+        let heard = std::sync::Mutex::new(Vec::new());
+        let progress = |fraction: f32| heard.lock().unwrap().push(fraction);
+
+        theater.encrypt_with_drama(1, b"x", EncryptionLevel::Basic, Some(&progress)).await.unwrap();
+
+        // A 1s pause reports at 200ms, 400ms, 600ms and 800ms, then once it's over
+        let heard = heard.into_inner().unwrap();
+        assert_eq!(heard.len(), 5, "{:?}", heard);
+        assert!(heard.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", heard);
+        assert!((heard[0] - 0.2).abs() < 1e-6, "{:?}", heard);
+        assert_eq!(heard.last(), Some(&1.0));
+
+        // Without theatrics there is no pause to report on
+        let mut sober = fast_theater().without_theatrics();
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let count = |_: f32| {
+            calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        };
+        sober.encrypt_with_drama(1, b"x", EncryptionLevel::Basic, Some(&count)).await.unwrap();
+        assert_eq!(calls.into_inner(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shared_encryptions_overlap_instead_of_queueing() {
+        // Cheap keys, so the pauses are what would pile up behind a lock
+        let mut theater = fast_theater().with_pbkdf2_rounds(1).unwrap();
+        theater.drama_factor = 5.0;
+        let delay = std::time::Duration::from_millis(theater.expected_delay_ms(&EncryptionLevel::Basic));
+        let theater = Arc::new(tokio::sync::Mutex::new(theater));
+
+        // On the paused clock only the pauses move time, so overlapping ones take one pause in all
+        let started = tokio::time::Instant::now();
+        let mut tasks = tokio::task::JoinSet::new();
+        for user_id in 0..100 {
+            let theater = theater.clone();
+            tasks.spawn(async move {
+                let result = DataTheater::encrypt_shared(&theater, user_id, b"secret", EncryptionLevel::Basic).await;
+                (user_id, result.unwrap())
+            });
+        }
+        let results = tasks.join_all().await;
+        let elapsed = started.elapsed();
+        // Queued behind one lock this would take 100 pauses
+        assert!(elapsed < delay * 2, "100 encryptions of {:?} each took {:?}", delay, elapsed);
+
+        // Every encryption was still scored and sealed for its own user
+        let mut theater = theater.lock().await;
+        for (user_id, result) in results {
+            assert!(result.achievement_unlocked.is_some());
+            assert!(result.theatrical_time_ms >= delay.as_millis() as u64);
+            assert_eq!(theater.decrypt_auto(user_id, &result.ciphertext, None).unwrap(), b"secret");
+            assert_eq!(theater.export_user(user_id).encrypted_items.len(), 1);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shared_batch_leaves_the_theater_unlocked_through_the_pause() {
+        let mut theater = fast_theater().with_pbkdf2_rounds(1).unwrap();
+        theater.drama_factor = 5.0;
+        let delay = std::time::Duration::from_millis(theater.expected_delay_ms(&EncryptionLevel::Basic));
+        let theater = Arc::new(tokio::sync::Mutex::new(theater));
+        let items: Vec<String> = (0..3).map(|i| format!("row {}", i)).collect();
+
+        let batch = tokio::spawn({
+            let theater = theater.clone();
+            let items = items.clone();
+            async move { DataTheater::encrypt_batch_shared(&theater, 1, &items, EncryptionLevel::Basic, None).await }
+        });
+        tokio::time::sleep(delay / 2).await;
+        assert!(!batch.is_finished());
+        assert!(theater.try_lock().is_ok(), "the batch held the theater lock through its pause");
+
+        let results = batch.await.unwrap().unwrap();
+        let mut theater = theater.lock().await;
+        for (item, result) in items.iter().zip(&results) {
+            assert_eq!(theater.decrypt_auto(1, &result.ciphertext, None).unwrap(), item.as_bytes());
+        }
+        assert_eq!(theater.export_user(1).encrypted_items.len(), items.len());
+    }
+
+    #[tokio::test]
+    async fn batch_encryption_does_not_starve_other_tasks() {
+        let mut theater = fast_theater();
+        let items: Vec<String> = (0..64).map(|i| format!("row {}", i)).collect();
+
+        // With the batch key already derived, the only awaits left are the cooperative yields
+        let salt = [5u8; SALT_LENGTH];
+        theater.key_cache.insert((1, salt), DerivedKey([9u8; 32]));
+        let timing = Timing { start: tokio::time::Instant::now(), theatrical: std::time::Duration::ZERO };
+        let salting = Salting::Batch { user_id: 1, salt };
+
+        // Count the turns another task on the same thread gets while the batch runs
+        let done = std::sync::atomic::AtomicBool::new(false);
+        let batch = async {
+            let results = theater
+                .encrypt_items(1, &items, &EncryptionLevel::Basic, timing, &salting, None)
+                .await;
+            done.store(true, std::sync::atomic::Ordering::Relaxed);
+            results
+        };
+        let probe = async {
+            let mut turns = 0;
+            while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                turns += 1;
+                tokio::task::yield_now().await;
+            }
+            turns
+        };
+        let (results, turns) = tokio::join!(batch, probe);
+
+        assert_eq!(results.unwrap().len(), 64);
+        assert!(
+            turns >= items.len() / BATCH_YIELD_INTERVAL,
+            "probe only ran {} times during a batch of {}",
+            turns,
+            items.len()
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_shares_one_salt_and_still_decrypts() {
+        let mut theater = fast_theater();
+        let items: Vec<String> = (0..5).map(|i| format!("row {}", i)).collect();
+        let results = theater
+            .encrypt_batch(4, &items, EncryptionLevel::Premium)
+            .await
+            .unwrap();
+
+        let salt = |blob: &[u8]| read_header(blob).unwrap().body[..SALT_LENGTH].to_vec();
+        let batch_salt = salt(&results[0].ciphertext);
+        for (item, result) in items.iter().zip(&results) {
+            assert_eq!(salt(&result.ciphertext), batch_salt);
+            assert_eq!(theater.decrypt_auto(4, &result.ciphertext, None).unwrap(), item.as_bytes());
+        }
+        assert!(theater.key_cache.is_empty());
+
+        let single = theater
+            .encrypt_with_drama(4, b"row 0", EncryptionLevel::Premium, None)
+            .await
+            .unwrap();
+        assert_ne!(salt(&single.ciphertext), batch_salt);
+    }
+
+    #[tokio::test]
+    async fn replay_rejects_inconsistent_funeral() {
+        let mut theater = DataTheater::new();
+        let mut schedule = theater
+            .schedule_funeral(
+                3,
+                vec!["a".to_string()],
+                FuneralType::Viking {
+                    longboat_size: 50,
+                    burning_arrows: 100,
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        let json = serde_json::to_string(&schedule).unwrap();
+        assert!(replay_funeral(&json).is_ok());
+
+        schedule.shred_passes = 0;
+        let json = serde_json::to_string(&schedule).unwrap();
+        let err = replay_funeral(&json).unwrap_err();
+        assert_eq!(err.to_string(), "shred_passes 0 is not valid for a viking funeral");
+    }
+
+    #[test]
+    fn levels_parse_from_their_names() {
+        let names = ["basic", "premium", "paranoid", "tinfoil", "quantum", "alien", "eldritch"];
+        for (name, level) in names.iter().zip(EncryptionLevel::ALL) {
+            assert_eq!(name.parse::<EncryptionLevel>().unwrap(), level);
+            assert_eq!(level.to_string(), *name);
+        }
+    }
+
+    #[test]
+    fn unknown_level_is_rejected() {
+        let err = "quantam".parse::<EncryptionLevel>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown encryption level 'quantam', expected one of: \
+             basic, premium, paranoid, tinfoil, quantum, alien, eldritch"
+        );
+    }
+
+    #[test]
+    fn eldritch_achievement_has_a_stable_id() {
+        let mut theater = DataTheater::new();
+        let (id, name) = theater.check_achievements(1, &EncryptionLevel::Eldritch).unwrap();
+        assert_eq!(id, AchievementId::FirstEldritch);
+        assert!(!name.is_empty());
+        assert_eq!(serde_json::to_value(id).unwrap(), "first_eldritch");
+        assert!(theater.check_achievements(1, &EncryptionLevel::Eldritch).is_none());
+    }
+
+    #[tokio::test]
+    async fn first_time_achievements_are_per_user() {
+        let mut theater = fast_theater();
+        for user_id in [1, 2] {
+            let result = theater
+                .encrypt_with_drama(user_id, b"hello", EncryptionLevel::Basic, None)
+                .await
+                .unwrap();
+            assert_eq!(result.achievement_id, Some(AchievementId::FirstBasic));
+        }
+
+        let again = theater
+            .encrypt_with_drama(1, b"hello", EncryptionLevel::Basic, None)
+            .await
+            .unwrap();
+        assert_eq!(again.achievement_id, None);
+    }
+
+    #[tokio::test]
+    async fn saved_achievements_do_not_retrigger_after_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("achievements.json");
+        assert!(load_achievements(&path).unwrap().is_empty());
+
+        let mut theater = fast_theater();
+        theater.encrypt_with_drama(4, b"x", EncryptionLevel::Alien, None).await.unwrap();
+        theater.save_achievements(&path).unwrap();
+
+        let mut restarted = fast_theater().with_achievements_from(&path).unwrap();
+        let result = restarted.encrypt_with_drama(4, b"x", EncryptionLevel::Alien, None).await.unwrap();
+        assert_eq!(result.achievement_id, None);
+        let result = restarted.encrypt_with_drama(5, b"x", EncryptionLevel::Alien, None).await.unwrap();
+        assert_eq!(result.achievement_id, Some(AchievementId::FirstAlien));
+    }
+
+    #[test]
+    fn funeral_types_round_trip_with_type_tag() {
+        let variants = [
+            FuneralType::Viking { longboat_size: 200, burning_arrows: 7 },
+            FuneralType::Space { trajectory: "Jupiter".to_string(), escape_velocity: 59.5 },
+            FuneralType::Quantum { superposition: false, observer_count: 3 },
+            FuneralType::Eldritch { tentacles: 8, dimensions_breached: 2, sanity_cost: -1 },
+        ];
+        for funeral_type in variants {
+            let json = serde_json::to_value(&funeral_type).unwrap();
+            assert_eq!(json["type"], funeral_type.kind());
+            let back: FuneralType = serde_json::from_value(json).unwrap();
+            assert_eq!(back, funeral_type);
+        }
+    }
+
+    #[test]
+    fn scheduled_time_is_unix_millis() {
+        let json = serde_json::json!({
+            "ceremony_id": "FUNERAL-3-1",
+            "user_id": 3,
+            "data_ids": ["a"],
+            "funeral_type": { "type": "viking", "longboat_size": 1, "burning_arrows": 1 },
+            "scheduled_time": 1_704_153_600_123u64,
+            "epitaph": "",
+            "shred_passes": 35,
+            "special_effects": [],
+            "livestream_url": "",
+            "guest_list": [],
+        });
+        let schedule: FuneralSchedule = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            schedule.scheduled_time,
+            UNIX_EPOCH + std::time::Duration::from_millis(1_704_153_600_123)
+        );
+        assert_eq!(serde_json::to_value(&schedule).unwrap()["scheduled_time"], 1_704_153_600_123u64);
+    }
+
+    #[test]
+    fn tuned_rounds_hit_the_target_time() {
+        // A pretend machine that takes 10µs a round, plus a fixed 1ms of overhead
+        let timer = |rounds: u32| Ok(std::time::Duration::from_micros(1_000 + 10 * u64::from(rounds)));
+        let rounds = tune_rounds(std::time::Duration::from_millis(40), timer).unwrap();
+        assert!((3_700..=4_100).contains(&rounds), "tuned to {} rounds, wanted about 3900", rounds);
+
+        let instant = |_| Ok(std::time::Duration::ZERO);
+        let rounds = tune_rounds(std::time::Duration::from_millis(40), instant).unwrap();
+        assert!(rounds >= MAX_TUNED_ROUNDS - MAX_TUNED_ROUNDS / 20, "tuned to {} rounds", rounds);
+        let glacial = |_| Ok(std::time::Duration::from_secs(60));
+        assert_eq!(tune_rounds(std::time::Duration::from_millis(40), glacial).unwrap(), MIN_TUNED_ROUNDS);
+
+        let mut theater = DataTheater::new();
+        let rounds = theater.tune_kdf_rounds(std::time::Duration::from_millis(1), true).unwrap();
+        assert_eq!(theater.kdf, Kdf::Pbkdf2 { rounds });
+    }
+
+    #[test]
+    fn loot_box_rarities_follow_the_weights() {
+        let mut theater = DataTheater::new();
+        let mut counts: HashMap<Rarity, u32> = HashMap::new();
+        let rolls = 100_000;
+        theater.credit_points(1, rolls * LOOT_BOX_COST);
+        for _ in 0..rolls {
+            *counts.entry(theater.open_loot_box(1).unwrap().rarity).or_default() += 1;
+        }
+
+        let expected = [
+            (Rarity::Common, 0.40),
+            (Rarity::Uncommon, 0.30),
+            (Rarity::Rare, 0.15),
+            (Rarity::Epic, 0.10),
+            (Rarity::Legendary, 0.04),
+            (Rarity::Mythic, 0.01),
+        ];
+        for (rarity, share) in expected {
+            let observed = counts.get(&rarity).copied().unwrap_or_default() as f64 / rolls as f64;
+            assert!(
+                (observed - share).abs() < 0.01,
+                "{:?} came up {:.4}, expected {:.2}",
+                rarity,
+                observed,
+                share
+            );
+        }
+    }
+
+    #[test]
+    fn levels_order_from_basic_to_eldritch() {
+        assert!(EncryptionLevel::ALL.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(EncryptionLevel::ALL.iter().max(), Some(&EncryptionLevel::Eldritch));
+        assert!(EncryptionLevel::Paranoid.at_least(EncryptionLevel::Paranoid));
+        assert!(EncryptionLevel::Alien.at_least(EncryptionLevel::Paranoid));
+        assert!(!EncryptionLevel::Premium.at_least(EncryptionLevel::Paranoid));
+    }
+
+    #[test]
+    fn every_level_has_a_distinct_badge() {
+        let badges: std::collections::HashSet<char> = EncryptionLevel::ALL
+            .iter()
+            .map(|level| describe_level(level, &TheaterConfig::default()).emoji)
+            .collect();
+        assert_eq!(badges.len(), EncryptionLevel::ALL.len());
+        assert_eq!(describe_level(&EncryptionLevel::Basic, &TheaterConfig::default()).emoji, '🔓');
+        assert_eq!(describe_level(&EncryptionLevel::Eldritch, &TheaterConfig::default()).emoji, '🐙');
+    }
+
+    #[test]
+    fn described_prices_follow_the_config() {
+        let mut config = TheaterConfig::default();
+        config.levels.get_mut("eldritch").unwrap().cost = 99999;
+
+        let info = describe_level(&EncryptionLevel::Eldritch, &config);
+        assert_eq!((info.cost, info.points), (99999, 66666));
+    }
+
+    fn fast_theater() -> DataTheater {
+        let mut theater = DataTheater::new()
+            .with_pbkdf2_rounds(1000)
+            .unwrap();
+        theater.drama_factor = 0.0;
+        theater
+    }
+
+    /// One ChaCha20-Poly1305 layer in a format from before the header was
+    /// authenticated: version 1, or version 2 with its cipher byte, keyed the
+    /// way `fast_theater` would
+    fn legacy_layer(version: u8, level_byte: u8, password: &str, aad: &[u8], data: &[u8]) -> Vec<u8> {
+        let (salt, nonce) = ([7u8; SALT_LENGTH], [9u8; NONCE_LENGTH]);
+        let key = Kdf::Pbkdf2 { rounds: 1000 }.derive(password, &salt).unwrap();
+        let encrypted = ChaCha20Cipher.encrypt(&key.0, &nonce, aad, data).unwrap();
+        let cipher: &[u8] = if version == 1 { &[] } else { &[CHACHA20_POLY1305_ID] };
+        [FORMAT_MAGIC.as_slice(), &[version, level_byte], cipher, &salt, &nonce, &encrypted].concat()
+    }
+
+    #[tokio::test]
+    async fn encryption_returns_the_ciphertext() {
+        let mut theater = fast_theater();
+        let result = theater
+            .encrypt_with_drama(1, b"attack at dawn", EncryptionLevel::Basic, None)
+            .await
+            .unwrap();
+        assert!(!result.ciphertext.is_empty());
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert!(json["ciphertext"].as_str().is_some_and(|s| !s.is_empty()));
+    }
+
+    // Canary for the "ciphertext computed then discarded" bug: every level must
+    // hand back a non-empty ciphertext that decrypts to the original input
+    #[tokio::test]
+    async fn returned_ciphertext_round_trips() {
+        let mut theater = fast_theater();
+        for level in EncryptionLevel::ALL {
+            let result = theater
+                .encrypt_with_drama(1, b"attack at dawn", level.clone(), None)
+                .await
+                .unwrap();
+            assert!(!result.ciphertext.is_empty(), "{} dropped its ciphertext", level);
+
+            let decrypted = theater.decrypt_auto(1, &result.ciphertext, None).unwrap();
+            assert_eq!(decrypted, b"attack at dawn", "{} did not round trip", level);
+        }
+    }
+
+    #[tokio::test]
+    async fn ciphertext_is_bound_to_its_user() {
+        let mut theater = fast_theater();
+        let result = theater
+            .encrypt_with_drama(1, b"mine", EncryptionLevel::Basic, None)
+            .await
+            .unwrap();
+        let password = theater.generate_theatrical_password(1, &EncryptionLevel::Basic);
+
+        let err = theater
+            .basic_decrypt(&result.ciphertext, &password, &2u64.to_le_bytes())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Decryption failed");
+
+        let plaintext = theater
+            .basic_decrypt(&result.ciphertext, &password, &1u64.to_le_bytes())
+            .unwrap();
+        assert_eq!(plaintext, b"mine");
+    }
+
+    fn rigged_racer(encryption_speed: f64) -> RaceParticipant {
+        RaceParticipant {
+            name: "User 7".to_string(),
+            encryption_speed,
+            vehicle: "🏎️".to_string(),
+            trash_talk: "Rigged.".to_string(),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn quick_race_win_credits_prize() {
+        let mut theater = fast_theater();
+        let outcome = theater.quick_race(7, rigged_racer(1_000_000.0), 4096).await.unwrap();
+        assert_eq!(outcome.race.winner, "User 7");
+        assert_eq!(outcome.race.results.len(), 1 + QUICK_RACE_OPPONENTS);
+        assert_eq!(outcome.points_awarded, QUICK_RACE_PRIZE);
+        assert_eq!(theater.balance(7), 1000);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bigger_races_score_higher() {
+        use rand::SeedableRng;
+
+        let race = |data_size| async move {
+            let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(5);
+            encryption_race(vec![rigged_racer(1000.0)], data_size, RaceLimits::default(), &mut rng).await.unwrap()
+        };
+        let (small, large) = (race(1024).await, race(1 << 20).await);
+        assert!(large.results[0].bytes_per_second > small.results[0].bytes_per_second);
+        assert_eq!(race(0).await.results[0].bytes_per_second, 0.0);
+
+        let mut theater = fast_theater().with_max_race_bytes(4 << 20);
+        let outcome = theater.quick_race(7, rigged_racer(1e12), 4 << 20).await.unwrap();
+        assert_eq!(outcome.points_awarded, 4096);
+        assert_eq!(theater.balance(7), 4096);
+        assert_eq!(race_prize(10), QUICK_RACE_PRIZE);
+        assert_eq!(race_prize(usize::MAX), MAX_QUICK_RACE_PRIZE);
+
+        // Racing anything beyond the limit is refused before anyone starts
+        let err = theater.quick_race(7, rigged_racer(1.0), (4 << 20) + 1).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(TheaterError::RaceTooLarge { .. })));
+        assert_eq!(theater.balance(7), 4096);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shared_quick_race_runs_without_the_lock() {
+        let theater = Arc::new(tokio::sync::Mutex::new(fast_theater()));
+        let race = tokio::spawn({
+            let theater = theater.clone();
+            async move { DataTheater::quick_race_shared(&theater, 7, rigged_racer(1_000_000.0), 4096).await }
+        });
+
+        // The opponents need over an hour for 4 KiB; the theater stays free meanwhile
+        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+        assert!(!race.is_finished());
+        assert!(theater.try_lock().is_ok());
+
+        let outcome = race.await.unwrap().unwrap();
+        assert_eq!(outcome.race.winner, "User 7");
+        assert_eq!(theater.lock().await.balance(7), outcome.points_awarded);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn quick_race_loss_credits_nothing() {
+        let mut theater = fast_theater();
+        let outcome = theater.quick_race(7, rigged_racer(0.000_001), 4096).await.unwrap();
+        assert_ne!(outcome.race.winner, "User 7");
+        assert_eq!(outcome.points_awarded, 0);
+        assert_eq!(theater.balance(7), 0);
+    }
+
+    #[tokio::test]
+    async fn decrypt_auto_detects_level() {
+        let mut theater = fast_theater();
+        for level in [EncryptionLevel::Premium, EncryptionLevel::Tinfoil, EncryptionLevel::Alien] {
+            let result = theater
+                .encrypt_with_drama(3, b"line one\nline two", level.clone(), None)
+                .await
+                .unwrap();
+            let decrypted = theater.decrypt_auto(3, &result.ciphertext, None).unwrap();
+            assert_eq!(decrypted, b"line one\nline two", "round trip failed for {}", level);
+        }
+    }
+
+    #[test]
+    fn insufficient_points_serializes_code_and_details() {
+        let error = TheaterError::InsufficientPoints { need: 66666, have: 50 };
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["code"], "INSUFFICIENT_POINTS");
+        assert_eq!(json["message"], "Insufficient points: need 66666, have 50");
+        assert_eq!(json["details"]["need"], 66666);
+        assert_eq!(json["details"]["have"], 50);
+    }
+
+    #[tokio::test]
+    async fn concurrent_encryptions_keep_the_runtime_responsive() {
+        const ROUNDS: u32 = 2_000;
+        let started = std::time::Instant::now();
+        derive_key("calibration", &[0u8; 32], ROUNDS).unwrap();
+        let one_derivation = started.elapsed();
+
+        let encryptions: Vec<_> = (0..50u64)
+            .map(|user_id| {
+                tokio::spawn(async move {
+                    let mut theater = DataTheater::new()
+                        .with_pbkdf2_rounds(ROUNDS)
+                        .unwrap();
+                    theater.drama_factor = 0.0;
+                    theater.encrypt_with_drama(user_id, b"load", EncryptionLevel::Basic, None).await
+                })
+            })
+            .collect();
+
+        // A heartbeat on the same (single-threaded) runtime: if derivations ran
+        // inline it would stall until every queued derivation had finished
+        let heartbeat = tokio::spawn(async move {
+            let mut longest_gap = std::time::Duration::ZERO;
+            let mut last = std::time::Instant::now();
+            for _ in 0..20 {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                longest_gap = longest_gap.max(last.elapsed());
+                last = std::time::Instant::now();
+            }
+            longest_gap
+        });
+
+        let longest_gap = heartbeat.await.unwrap();
+        for encryption in encryptions {
+            assert!(!encryption.await.unwrap().unwrap().ciphertext.is_empty());
+        }
+        assert!(
+            longest_gap < one_derivation * 10,
+            "runtime stalled for {:?}, one derivation takes {:?}",
+            longest_gap,
+            one_derivation
+        );
+    }
+
+    fn viking() -> FuneralType {
+        FuneralType::Viking { longboat_size: 1, burning_arrows: 1 }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scheduled_funeral_fires_at_its_time() {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let schedule = theater.schedule_funeral(1, vec!["a".to_string()], viking(), None).await.unwrap();
+
+        let mut scheduler = FuneralScheduler::new().with_clock(clock);
+        scheduler.store(1, "a", b"secret".to_vec());
+        scheduler.enqueue(schedule.clone());
+        let scheduler = Arc::new(tokio::sync::Mutex::new(scheduler));
+        let worker = FuneralScheduler::spawn(scheduler.clone());
+
+        tokio::time::sleep(std::time::Duration::from_secs(86_399)).await;
+        assert_eq!(scheduler.lock().await.pending_count(), 1);
+        assert_eq!(scheduler.lock().await.get(1, "a"), Some(&StoredData::Alive(b"secret".to_vec())));
+
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        let scheduler = scheduler.lock().await;
+        assert_eq!(scheduler.pending_count(), 0);
+        assert!(matches!(
+            scheduler.get(1, "a"),
+            Some(StoredData::Tombstone { ceremony_id, .. }) if *ceremony_id == schedule.ceremony_id
+        ));
+        worker.abort();
+    }
+
+    /// A scheduler on `clock` that has just held a funeral shredding "a" for user 1
+    async fn shredded_for_user_1(clock: Arc<MockClock>) -> FuneralScheduler {
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let schedule = theater.schedule_funeral(1, vec!["a".to_string()], viking(), None).await.unwrap();
+        clock.advance(std::time::Duration::from_secs(86_400));
+
+        let mut scheduler = FuneralScheduler::new().with_clock(clock);
+        scheduler.store(1, "a", b"oops".to_vec());
+        scheduler.enqueue(schedule);
+        scheduler.hold_due_funerals(tokio::time::Instant::now());
+        assert!(matches!(scheduler.get(1, "a"), Some(StoredData::Tombstone { .. })));
+        scheduler
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shredded_data_restores_within_the_window() {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut scheduler = shredded_for_user_1(clock.clone()).await;
+
+        clock.advance(DEFAULT_RESTORE_WINDOW - std::time::Duration::from_secs(1));
+        assert!(matches!(scheduler.restore(2, "a"), Err(TheaterError::NothingToRestore(_))));
+        scheduler.restore(1, "a").unwrap();
+        assert_eq!(scheduler.get(1, "a"), Some(&StoredData::Alive(b"oops".to_vec())));
+        assert!(matches!(scheduler.restore(1, "a"), Err(TheaterError::NothingToRestore(_))));
+    }
+
+    #[tokio::test]
+    async fn encryptions_restore_within_the_window() {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let first = theater.encrypt_with_drama(1, b"oops", EncryptionLevel::Basic, None).await.unwrap();
+        let second = theater.encrypt_with_drama(1, b"late", EncryptionLevel::Basic, None).await.unwrap();
+
+        clock.advance(DEFAULT_RESTORE_WINDOW - std::time::Duration::from_secs(1));
+        assert!(matches!(theater.restore(2, &first.data_id), Err(TheaterError::NothingToRestore(_))));
+        assert_eq!(theater.restore(1, &first.data_id).unwrap(), b"oops");
+        let exported = theater.export_user(1).encrypted_items;
+        assert_eq!(exported.iter().map(|item| &item.data_id).collect::<Vec<_>>(), [&second.data_id]);
+        assert!(matches!(theater.restore(1, &first.data_id), Err(TheaterError::NothingToRestore(_))));
+
+        clock.advance(std::time::Duration::from_secs(1));
+        assert!(matches!(theater.restore(1, &second.data_id), Err(TheaterError::RestoreWindowExpired(_))));
+        assert!(theater.restorable.is_empty());
+
+        let mut forgetful = fast_theater().with_restore_window(std::time::Duration::ZERO);
+        let result = forgetful.encrypt_with_drama(1, b"gone", EncryptionLevel::Basic, None).await.unwrap();
+        assert!(matches!(forgetful.restore(1, &result.data_id), Err(TheaterError::RestoreWindowExpired(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shredded_data_stays_gone_after_the_window() {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut scheduler = shredded_for_user_1(clock.clone()).await;
+
+        clock.advance(DEFAULT_RESTORE_WINDOW);
+        assert!(matches!(scheduler.restore(1, "a"), Err(TheaterError::RestoreWindowExpired(_))));
+        assert!(matches!(scheduler.get(1, "a"), Some(StoredData::Tombstone { .. })));
+
+        // Once the worker has dropped the bytes, the tombstone still explains why
+        let mut scheduler = shredded_for_user_1(clock.clone()).await;
+        clock.advance(DEFAULT_RESTORE_WINDOW);
+        scheduler.hold_due_funerals(tokio::time::Instant::now());
+        assert!(scheduler.shredded.is_empty());
+        assert!(matches!(scheduler.restore(1, "a"), Err(TheaterError::RestoreWindowExpired(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_worker_wipes_shredded_bytes_when_the_window_closes() {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let schedule = theater.schedule_funeral(1, vec!["a".to_string()], viking(), None).await.unwrap();
+        clock.advance(std::time::Duration::from_secs(86_400));
+
+        let mut scheduler = FuneralScheduler::new().with_clock(clock.clone());
+        scheduler.store(1, "a", b"oops".to_vec());
+        scheduler.enqueue(schedule);
+        let scheduler = Arc::new(tokio::sync::Mutex::new(scheduler));
+        let worker = FuneralScheduler::spawn(scheduler.clone());
+
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        assert_eq!(scheduler.lock().await.shredded.len(), 1);
+
+        // Nothing else is pending, so only the window closing wakes the worker
+        clock.advance(DEFAULT_RESTORE_WINDOW);
+        tokio::time::sleep(DEFAULT_RESTORE_WINDOW).await;
+        assert!(scheduler.lock().await.shredded.is_empty());
+        worker.abort();
+    }
+
+    #[tokio::test]
+    async fn funerals_only_shred_their_own_users_data() {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let schedule = theater.schedule_funeral(2, vec!["a".to_string()], viking(), None).await.unwrap();
+        clock.advance(std::time::Duration::from_secs(86_400));
+
+        let mut scheduler = FuneralScheduler::new().with_clock(clock);
+        scheduler.store(1, "a", b"not yours".to_vec());
+        scheduler.enqueue(schedule);
+        scheduler.hold_due_funerals(tokio::time::Instant::now());
+
+        assert_eq!(scheduler.get(1, "a"), Some(&StoredData::Alive(b"not yours".to_vec())));
+        assert_eq!(scheduler.get(2, "a"), None);
+        assert!(matches!(scheduler.restore(2, "a"), Err(TheaterError::NothingToRestore(_))));
+        assert!(scheduler.shredded.is_empty());
+    }
+
+    #[tokio::test]
+    async fn livestreams_hang_off_the_base_url() {
+        let mut theater = fast_theater();
+        let schedule = theater.schedule_funeral(1, vec!["a".to_string()], viking(), None).await.unwrap();
+        assert!(schedule.livestream_url.starts_with("https://gongle.com/funerals/live/"));
+
+        let mut theater = fast_theater().with_base_url("http://localhost:8080/theater/").unwrap();
+        let schedule = theater.schedule_funeral(1, vec!["a".to_string()], viking(), None).await.unwrap();
+        assert!(
+            schedule.livestream_url.starts_with("http://localhost:8080/theater/funerals/live/"),
+            "{}",
+            schedule.livestream_url
+        );
+
+        for bad in ["gongle.example", "ftp://gongle.example", "https://", ""] {
+            assert!(fast_theater().with_base_url(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn funeral_falls_due_when_the_clock_passes_it() {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let schedule = theater.schedule_funeral(1, vec!["a".to_string()], viking(), None).await.unwrap();
+        let mut scheduler = FuneralScheduler::new().with_clock(clock.clone());
+        scheduler.store(1, "a", b"secret".to_vec());
+        scheduler.enqueue(schedule.clone());
+
+        clock.advance(schedule.scheduled_time.duration_since(clock.now()).unwrap() - std::time::Duration::from_secs(1));
+        scheduler.hold_due_funerals(tokio::time::Instant::now());
+        assert_eq!(scheduler.pending_count(), 1);
+        assert_eq!(scheduler.get(1, "a"), Some(&StoredData::Alive(b"secret".to_vec())));
+
+        clock.advance(std::time::Duration::from_secs(1));
+        scheduler.hold_due_funerals(tokio::time::Instant::now());
+        assert_eq!(scheduler.pending_count(), 0);
+        assert!(matches!(scheduler.get(1, "a"), Some(StoredData::Tombstone { .. })));
+
+        // Past MAX_FUNERAL_AGE the same schedule is too stale to replay
+        assert!(schedule.validate_at(clock.now()).is_ok());
+        clock.advance(MAX_FUNERAL_AGE + std::time::Duration::from_secs(1));
+        assert!(schedule.validate_at(clock.now()).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_funeral_leaves_data_alive() {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let cancelled = theater.schedule_funeral(1, vec!["a".to_string()], viking(), None).await.unwrap();
+        let held = theater.schedule_funeral(1, vec!["b".to_string()], viking(), None).await.unwrap();
+
+        let mut scheduler = FuneralScheduler::new().with_clock(clock);
+        scheduler.store(1, "a", b"keep me".to_vec());
+        scheduler.enqueue(cancelled.clone());
+        scheduler.enqueue(held.clone());
+        let scheduler = Arc::new(tokio::sync::Mutex::new(scheduler));
+        let worker = FuneralScheduler::spawn(scheduler.clone());
+
+        scheduler.lock().await.cancel_funeral(&cancelled.ceremony_id).unwrap();
+        tokio::time::sleep(std::time::Duration::from_secs(86_401)).await;
+
+        let mut scheduler = scheduler.lock().await;
+        assert_eq!(scheduler.get(1, "a"), Some(&StoredData::Alive(b"keep me".to_vec())));
+        assert!(matches!(
+            scheduler.cancel_funeral(&cancelled.ceremony_id),
+            Err(TheaterError::NotFound(_))
+        ));
+        assert!(matches!(
+            scheduler.cancel_funeral(&held.ceremony_id),
+            Err(TheaterError::AlreadyExecuted(_))
+        ));
+        worker.abort();
+    }
+
+    /// Hold `schedule` straight away and wait for its webhook to settle
+    async fn hold_with_webhook(schedule: &FuneralSchedule, clock: Arc<MockClock>) -> WebhookStatus {
+        clock.advance(std::time::Duration::from_secs(86_400));
+        let mut scheduler = FuneralScheduler::new().with_clock(clock).with_private_webhooks();
+        scheduler.enqueue(schedule.clone());
+        let scheduler = Arc::new(tokio::sync::Mutex::new(scheduler));
+        let worker = FuneralScheduler::spawn(scheduler.clone());
+
+        let status = loop {
+            match scheduler.lock().await.webhook_status(&schedule.ceremony_id) {
+                None | Some(WebhookStatus::Pending) => {},
+                Some(status) => break status.clone(),
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        worker.abort();
+        status
+    }
+
+    #[tokio::test]
+    async fn held_funeral_notifies_its_webhook() {
+        use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/buried"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let url = format!("{}/buried", server.uri());
+        let schedule = theater.schedule_funeral(1, vec!["a".to_string()], viking(), Some(url)).await.unwrap();
+
+        assert_eq!(hold_with_webhook(&schedule, clock).await, WebhookStatus::Delivered { attempts: 1 });
+        let received = server.received_requests().await.unwrap();
+        let payload: serde_json::Value = serde_json::from_slice(&received[0].body).unwrap();
+        assert_eq!(payload["ceremony_id"], schedule.ceremony_id.as_str());
+        assert_eq!(payload["completed_at"], 1_704_236_400_000u64);
+    }
+
+    #[tokio::test]
+    async fn failed_webhook_is_retried_once() {
+        use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let webhook = Some(server.uri());
+        let schedule = theater.schedule_funeral(1, vec!["a".to_string()], viking(), webhook).await.unwrap();
+
+        let status = hold_with_webhook(&schedule, clock).await;
+        assert!(matches!(status, WebhookStatus::Failed { attempts: 2, .. }), "{:?}", status);
+
+        let ftp = Some("ftp://x".to_string());
+        let err = theater.schedule_funeral(1, vec!["a".to_string()], viking(), ftp).await;
+        assert!(matches!(
+            err.unwrap_err().downcast_ref(),
+            Some(TheaterError::InvalidFuneralParam { field: "webhook_url", .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn webhooks_to_internal_addresses_are_refused() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/hook",
+            "http://192.168.0.1/hook",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert!(webhook_client(url, false).await.is_err(), "{url} was allowed");
+            assert!(webhook_client(url, true).await.is_ok(), "{url} refused despite opting in");
+        }
+        assert!(webhook_client("https://93.184.216.34/hook", false).await.is_ok());
+
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let webhook = Some("http://169.254.169.254/latest/meta-data".to_string());
+        let schedule = theater.schedule_funeral(1, vec!["a".to_string()], viking(), webhook).await.unwrap();
+
+        clock.advance(std::time::Duration::from_secs(86_400));
+        let mut scheduler = FuneralScheduler::new().with_clock(clock);
+        scheduler.enqueue(schedule.clone());
+        let scheduler = Arc::new(tokio::sync::Mutex::new(scheduler));
+        let worker = FuneralScheduler::spawn(scheduler.clone());
+        let status = loop {
+            match scheduler.lock().await.webhook_status(&schedule.ceremony_id) {
+                None | Some(WebhookStatus::Pending) => {},
+                Some(status) => break status.clone(),
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        worker.abort();
+        assert!(matches!(status, WebhookStatus::Failed { attempts: 0, .. }), "{:?}", status);
+    }
+
+    #[tokio::test]
+    async fn webhook_redirects_are_not_followed() {
+        use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(matchers::path("/buried"))
+            .respond_with(ResponseTemplate::new(302).insert_header("location", "/internal"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::path("/internal"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = fast_theater().with_clock(clock.clone());
+        let webhook = Some(format!("{}/buried", server.uri()));
+        let schedule = theater.schedule_funeral(1, vec!["a".to_string()], viking(), webhook).await.unwrap();
+
+        hold_with_webhook(&schedule, clock).await;
+    }
+
+    struct Pirate;
+
+    impl FuneralCeremony for Pirate {
+        fn epitaph(&self, data_count: usize) -> String {
+            format!("{} chests of data sent to Davy Jones' locker", data_count)
+        }
+
+        fn shred_passes(&self, _rng: &mut dyn RngCore) -> u32 {
+            7
+        }
+
+        fn special_effects(&self) -> Vec<String> {
+            vec!["🏴‍☠️".to_string(), "🦜".to_string()]
+        }
+    }
+
+    #[test]
+    fn shred_passes_come_from_the_funeral_type() {
+        use rand::SeedableRng;
+
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(9);
+        for _ in 0..50 {
+            assert_eq!(viking().shred_passes(&mut rng), 35);
+            let eldritch = FuneralType::stock("eldritch").unwrap();
+            assert_eq!(eldritch.shred_passes(&mut rng), 666);
+            let space = FuneralType::stock("space").unwrap().shred_passes(&mut rng);
+            assert!((1..100).contains(&space), "{}", space);
+            let quantum = FuneralType::stock("quantum").unwrap().shred_passes(&mut rng);
+            assert!(quantum == 0 || quantum == 999, "{}", quantum);
+
+            // Whatever a stock funeral draws, validation accepts
+            for name in ["viking", "space", "quantum", "eldritch"] {
+                let funeral_type = FuneralType::stock(name).unwrap();
+                assert!(funeral_type.accepts_passes(funeral_type.shred_passes(&mut rng)), "{}", name);
+            }
+        }
+        assert!(!viking().accepts_passes(34));
+
+        let draw = |seed| {
+            let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(seed);
+            FuneralType::stock("space").unwrap().shred_passes(&mut rng)
+        };
+        assert_eq!(draw(3), draw(3));
+    }
+
+    #[tokio::test]
+    async fn registered_ceremonies_can_be_scheduled() {
+        let mut theater = fast_theater();
+        theater.register_ceremony("pirate", Box::new(Pirate));
+        let pirate = FuneralType::Custom { ceremony: "pirate".to_string() };
+
+        let schedule = theater.schedule_funeral(1, vec!["a".into(), "b".into()], pirate, None).await.unwrap();
+        assert_eq!(schedule.epitaph, "2 chests of data sent to Davy Jones' locker");
+        assert_eq!(schedule.shred_passes, 7);
+        assert_eq!(schedule.special_effects, ["🏴‍☠️", "🦜"]);
+        schedule.validate().unwrap();
+
+        // The built-ins are registered too, under their frontend names
+        let viking = FuneralType::Custom { ceremony: "viking".to_string() };
+        let schedule = theater.schedule_funeral(1, vec!["a".into()], viking, None).await.unwrap();
+        assert_eq!(schedule.shred_passes, 35);
+
+        let norse = FuneralType::Custom { ceremony: "norse".to_string() };
+        let err = theater.schedule_funeral(1, vec!["a".into()], norse, None).await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(TheaterError::UnknownCeremony(key)) if key == "norse"));
+    }
+
+    #[tokio::test]
+    async fn funeral_quota_resets_at_utc_midnight() {
+        // 2024-01-01 23:00:00 UTC
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH + std::time::Duration::from_secs(1_704_150_000)));
+        let mut theater = DataTheater::new()
+            .with_clock(clock.clone())
+            .with_daily_funeral_quota(3);
+
+        for _ in 0..3 {
+            theater.schedule_funeral(5, vec!["x".to_string()], viking(), None).await.unwrap();
+        }
+
+        let err = theater.schedule_funeral(5, vec!["x".to_string()], viking(), None).await.unwrap_err();
+        match err.downcast_ref() {
+            Some(TheaterError::QuotaExceeded { limit, resets_at }) => {
+                assert_eq!(*limit, 3);
+                assert_eq!(*resets_at, 1_704_153_600);
+            },
+            other => panic!("expected QuotaExceeded, got {:?}", other),
+        }
+
+        clock.advance(std::time::Duration::from_secs(2 * 3600));
+        theater.schedule_funeral(5, vec!["x".to_string()], viking(), None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn encrypt_rate_limit_slides_with_the_clock() {
+        let clock = Arc::new(MockClock::new(UNIX_EPOCH));
+        let mut theater = fast_theater().with_clock(clock.clone()).with_encrypt_rate_limit(2);
+
+        theater.encrypt_with_drama(1, b"a", EncryptionLevel::Basic, None).await.unwrap();
+        clock.advance(std::time::Duration::from_secs(30));
+        theater.encrypt_with_drama(1, b"b", EncryptionLevel::Basic, None).await.unwrap();
+
+        let err = theater.encrypt_with_drama(1, b"c", EncryptionLevel::Basic, None).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(TheaterError::RateLimited { limit: 2, retry_after_secs: 30 })
+        ));
+
+        // The first encryption ages out of the window; the second is still in it
+        clock.advance(std::time::Duration::from_secs(30));
+        theater.encrypt_with_drama(1, b"c", EncryptionLevel::Basic, None).await.unwrap();
+        assert!(theater.encrypt_with_drama(1, b"d", EncryptionLevel::Basic, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn random_binary_round_trips_at_every_level() {
+        let mut theater = fast_theater();
+        let mut data = vec![0u8; 1024];
+        OsRng.fill_bytes(&mut data);
+
+        for level in EncryptionLevel::ALL {
+            let result = theater.encrypt_with_drama(8, &data, level.clone(), None).await.unwrap();
+            let decrypted = theater.decrypt_auto(8, &result.ciphertext, None).unwrap();
+            assert_eq!(decrypted, data, "{} mangled binary input", level);
+        }
+    }
+
+    #[tokio::test]
+    async fn ten_megabyte_file_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("dump.sql");
+        let sealed = dir.path().join("dump.sql.gngs");
+        let opened = dir.path().join("dump.sql.out");
+
+        let mut data = vec![0u8; 10 * 1024 * 1024 + 123];
+        OsRng.fill_bytes(&mut data);
+        fs::write(&plain, &data).unwrap();
+
+        let mut theater = fast_theater();
+        theater
+            .encrypt_file(&plain, &sealed, EncryptionLevel::Tinfoil, "hunter2")
+            .await
+            .unwrap();
+        let level = theater.decrypt_file(&sealed, &opened, "hunter2").await.unwrap();
+
+        assert_eq!(level, EncryptionLevel::Tinfoil);
+        assert!(fs::read(&opened).unwrap() == data);
+
+        // Dropping the final chunk must be detected, and leave no output behind
+        let truncated = fs::read(&sealed).unwrap();
+        fs::write(&sealed, &truncated[..truncated.len() - 1000]).unwrap();
+        fs::remove_file(&opened).unwrap();
+        assert!(theater.decrypt_file(&sealed, &opened, "hunter2").await.is_err());
+        assert!(!opened.exists());
+    }
+
+    #[tokio::test]
+    async fn files_decrypt_with_the_kdf_they_were_written_with() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("notes.txt");
+        let sealed = dir.path().join("notes.txt.gngs");
+        let opened = dir.path().join("notes.txt.out");
+        fs::write(&plain, b"meet me at the old mill").unwrap();
+
+        let argon2 = Kdf::Argon2id { mem_kib: 64, iters: 1, parallelism: 1 };
+        let mut writer = fast_theater().with_kdf(argon2).unwrap();
+        writer.encrypt_file(&plain, &sealed, EncryptionLevel::Basic, "pw").await.unwrap();
+
+        let reader = fast_theater().with_pbkdf2_rounds(2_000).unwrap();
+        reader.decrypt_file(&sealed, &opened, "pw").await.unwrap();
+        assert_eq!(fs::read(&opened).unwrap(), b"meet me at the old mill");
+
+        // Version 1 files still decrypt with the configured rounds
+        let mut header = STREAM_MAGIC.to_vec();
+        header.extend_from_slice(&[1, EncryptionLevel::Basic.to_byte()]);
+        header.extend_from_slice(&[7u8; SALT_LENGTH]);
+        header.extend_from_slice(&[9u8; STREAM_NONCE_PREFIX_LENGTH]);
+        let key = Kdf::Pbkdf2 { rounds: 2_000 }.derive("pw", &[7u8; SALT_LENGTH]).unwrap();
+        let mut v1 = header.clone();
+        seal_stream(&b"old news"[..], &mut v1, &key, &[9u8; STREAM_NONCE_PREFIX_LENGTH], &header).unwrap();
+        fs::write(&sealed, &v1).unwrap();
+        fs::remove_file(&opened).unwrap();
+
+        reader.decrypt_file(&sealed, &opened, "pw").await.unwrap();
+        assert_eq!(fs::read(&opened).unwrap(), b"old news");
+    }
+
+    fn seeded_theater(seed: u64) -> DataTheater {
+        use rand::SeedableRng;
+        fast_theater().with_rng(rand_chacha::ChaCha20Rng::seed_from_u64(seed))
+    }
+
+    #[tokio::test]
+    async fn sober_mode_skips_drama_but_not_crypto() {
+        let mut dramatic = seeded_theater(5);
+        let mut sober = seeded_theater(5).without_theatrics();
+        let with = dramatic.encrypt_with_drama(1, b"plain", EncryptionLevel::Eldritch, None).await.unwrap();
+        let without = sober.encrypt_with_drama(1, b"plain", EncryptionLevel::Eldritch, None).await.unwrap();
+
+        assert_eq!(with.ciphertext, without.ciphertext);
+        assert!(!with.theatrical_elements.is_empty());
+        assert!(without.theatrical_elements.is_empty());
+        for (theater, result) in [(&mut dramatic, &with), (&mut sober, &without)] {
+            assert_eq!(theater.decrypt_auto(1, &result.ciphertext, None).unwrap(), b"plain");
+        }
+
+        // Full drama factor, but the 6.6s Eldritch pause must not happen
+        let mut sober = DataTheater::new()
+            .with_pbkdf2_rounds(1000)
+            .unwrap()
+            .without_theatrics();
+        let started = std::time::Instant::now();
+        sober.encrypt_with_drama(1, b"plain", EncryptionLevel::Eldritch, None).await.unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(100));
+    }
+
+    #[test]
+    fn drama_factor_rejects_nonsense() {
+        let mut theater = fast_theater();
+        for factor in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(theater.set_drama_factor(factor).is_err(), "accepted {}", factor);
+        }
+        assert_eq!(theater.drama_factor, 0.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drama_factor_scales_the_pause() {
+        let mut theater = fast_theater();
+        theater.set_drama_factor(1.0).unwrap();
+        let normal = theater.encrypt_with_drama(1, b"x", EncryptionLevel::Basic, None).await.unwrap();
+        theater.set_drama_factor(2.0).unwrap();
+        let doubled = theater.encrypt_with_drama(1, b"x", EncryptionLevel::Basic, None).await.unwrap();
+
+        assert_eq!(normal.theatrical_time_ms, 100);
+        assert_eq!(doubled.theatrical_time_ms, 200);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn eldritch_timing_separates_drama_from_crypto() {
+        let mut theater = fast_theater().with_drama_factor(1.0).unwrap();
+        let result = theater
+            .encrypt_with_drama(1, b"ph'nglui", EncryptionLevel::Eldritch, None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.theatrical_time_ms, 6666);
+        assert!(result.real_crypto_time_ms < result.theatrical_time_ms);
+        assert!(result.encryption_time_ms >= result.theatrical_time_ms);
+    }
+
+    #[test]
+    fn base64_helper_round_trips() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let encoded = base64_text::encode(&bytes);
+        assert_eq!(&encoded[..8], "AAECAwQF");
+        assert_eq!(base64_text::decode(&encoded).unwrap(), bytes);
+        assert!(base64_text::decode("not base64!").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fastest_racer_finishes_first() {
+        use rand::SeedableRng;
+
+        let racer = |name: &str, encryption_speed| RaceParticipant {
+            name: name.to_string(),
+            encryption_speed,
+            vehicle: "🚗".to_string(),
+            trash_talk: String::new(),
+        };
+        let racers = vec![racer("Slow", 1.0), racer("Fast", 5.0), racer("Middling", 2.0)];
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(3);
+
+        let started = tokio::time::Instant::now();
+        let race = encryption_race(racers, 1024, RaceLimits::default(), &mut rng).await.unwrap();
+
+        assert_eq!(race.winner, "Fast");
+        let names: Vec<&str> = race.results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["Fast", "Middling", "Slow"]);
+        assert!(race.results.windows(2).all(|w| w[0].time_ms <= w[1].time_ms));
+        let slowest = race.results.last().unwrap().time_ms;
+        assert_eq!(started.elapsed().as_millis() as u64, slowest);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trash_talk_carries_through_and_last_place_gets_burned() {
+        let racers = vec![
+            RaceParticipant {
+                name: "Hare".to_string(),
+                encryption_speed: 10.0,
+                vehicle: "🐇".to_string(),
+                trash_talk: "Catch me if you can".to_string(),
+            },
+            RaceParticipant {
+                name: "Tortoise".to_string(),
+                encryption_speed: 1.0,
+                vehicle: "🐢".to_string(),
+                trash_talk: "Slow and steady".to_string(),
+            },
+        ];
+
+        let race = encryption_race(racers, 64, RaceLimits::default(), &mut OsRng).await.unwrap();
+
+        let hare = race.results.iter().find(|r| r.name == "Hare").unwrap();
+        let tortoise = race.results.iter().find(|r| r.name == "Tortoise").unwrap();
+        assert_eq!(hare.trash_talk, "Catch me if you can");
+        assert_eq!(tortoise.trash_talk, "Slow and steady");
+        assert!(hare.consolation_burn.is_none());
+        assert!(tortoise.consolation_burn.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bad_lineups_are_rejected() {
+        let limits = RaceLimits { participants: 3, data_size: 1024 };
+        let race = |racers| async move { encryption_race(racers, 64, limits, &mut OsRng).await };
+        let lineup = |names: &[&str]| -> Vec<RaceParticipant> {
+            names
+                .iter()
+                .map(|name| RaceParticipant { name: name.to_string(), ..rigged_racer(1.0) })
+                .collect()
+        };
+        let error = |result: Result<RaceResults>| result.unwrap_err().downcast::<TheaterError>().unwrap();
+
+        assert!(matches!(
+            error(race(lineup(&["User_42", "Hare", "User_42"])).await),
+            TheaterError::DuplicateParticipant(name) if name == "User_42"
+        ));
+        assert!(matches!(error(race(Vec::new()).await), TheaterError::NoParticipants));
+        assert!(matches!(
+            error(race(lineup(&["a", "b", "c", "d"])).await),
+            TheaterError::TooManyParticipants { count: 4, limit: 3 }
+        ));
+        assert!(race(lineup(&["a", "b", "c"])).await.is_ok());
+
+        let huge = encryption_race(lineup(&["a"]), usize::MAX, limits, &mut OsRng).await;
+        assert!(matches!(error(huge), TheaterError::RaceTooLarge { size: usize::MAX, limit: 1024 }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn seeded_races_are_reproducible() {
+        use rand::SeedableRng;
+
+        let racers = seeded_theater(1).generate_opponents(6);
+        let run = |seed| {
+            let racers = racers.clone();
+            async move {
+                let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(seed);
+                encryption_race(racers, 4096, RaceLimits::default(), &mut rng).await.unwrap()
+            }
+        };
+
+        let (first, second) = (run(42).await, run(42).await);
+        assert_eq!(first.winner, second.winner);
+        let times = |r: &RaceResults| r.results.iter().map(|r| r.time_ms).collect::<Vec<_>>();
+        assert_eq!(times(&first), times(&second));
+
+        let (mut a, mut b) = (seeded_theater(7), seeded_theater(7));
+        let race_a = a.quick_race(1, rigged_racer(1.0), 4096).await.unwrap();
+        let race_b = b.quick_race(1, rigged_racer(1.0), 4096).await.unwrap();
+        assert_eq!(race_a.race.winner, race_b.race.winner);
+        assert_eq!(race_a.points_awarded, race_b.points_awarded);
+    }
+
+    #[tokio::test]
+    async fn seeded_funerals_invite_the_same_guests() {
+        let schedule = |seed| async move {
+            seeded_theater(seed)
+                .schedule_funeral(2, vec!["a".to_string()], viking(), None)
+                .await
+                .unwrap()
+        };
+
+        let (first, second) = (schedule(99).await, schedule(99).await);
+        assert_eq!(first.guest_list, second.guest_list);
+        assert_eq!(first.ceremony_id, second.ceremony_id);
+    }
+
+    #[tokio::test]
+    async fn quantum_round_trips_down_both_branches() {
+        let mut theater = seeded_theater(3);
+        // Looks like the collapsed prefix, so a guessing decrypt would strip it
+        let data = b"QUANTUM:not actually a prefix";
+        let mut branches = std::collections::HashSet::new();
+
+        for _ in 0..32 {
+            let result = theater.encrypt_with_drama(6, data, EncryptionLevel::Quantum, None).await.unwrap();
+            branches.insert(result.ciphertext[5] & FLAG_QUANTUM_PREFIXED);
+            assert_eq!(theater.decrypt_auto(6, &result.ciphertext, None).unwrap(), data);
+        }
+        assert_eq!(branches.len(), 2, "only one quantum branch was exercised");
+    }
+
+    async fn round_trip(mut theater: DataTheater) -> Vec<u8> {
+        let result = theater.encrypt_with_drama(3, b"cipher agnostic", EncryptionLevel::Premium, None).await.unwrap();
+        assert_eq!(theater.decrypt_auto(3, &result.ciphertext, None).unwrap(), b"cipher agnostic");
+        result.ciphertext
+    }
+
+    #[tokio::test]
+    async fn chacha_round_trips_and_reads_version_1() {
+        let blob = round_trip(fast_theater()).await;
+        assert_eq!(blob[6], crate::cipher::CHACHA20_POLY1305_ID);
+
+        // Version 1 has no cipher byte and authenticates only the user id
+        let (password, aad) = ("user_3_premiumpassword!", 3u64.to_le_bytes());
+        let premium = EncryptionLevel::Premium.to_byte();
+        let inner = legacy_layer(1, premium, password, &aad, b"cipher agnostic");
+        let v1 = legacy_layer(1, premium, password, &aad, base64_text::encode(&inner).as_bytes());
+        assert_eq!(fast_theater().decrypt_auto(3, &v1, None).unwrap(), b"cipher agnostic");
+
+        // From version 3 the header is authenticated too
+        let mut promoted = blob.clone();
+        promoted[5] = EncryptionLevel::Basic.to_byte();
+        assert!(fast_theater().decrypt_auto(3, &promoted, Some(password)).is_err());
+        let mut weakened = blob.clone();
+        let salt_start = blob.len() - read_header(&blob).unwrap().body.len();
+        weakened[salt_start] ^= 1;
+        assert!(fast_theater().decrypt_auto(3, &weakened, None).is_err());
+
+        let mut unknown = blob.clone();
+        unknown[6] = 0x7f;
+        let err = fast_theater().decrypt_auto(3, &unknown, None).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(TheaterError::UnsupportedCipher(0x7f))));
+    }
+
+    #[tokio::test]
+    async fn argon2id_round_trips_from_its_header() {
+        let argon2 = Kdf::Argon2id { mem_kib: 64, iters: 1, parallelism: 1 };
+        let blob = round_trip(fast_theater().with_kdf(argon2).unwrap()).await;
+        assert_eq!(read_header(&blob).unwrap().kdf, Some(argon2));
+
+        // A PBKDF2 theater reads the Argon2id parameters from the blob
+        assert_eq!(fast_theater().decrypt_auto(3, &blob, None).unwrap(), b"cipher agnostic");
+
+        let pbkdf2 = round_trip(fast_theater()).await;
+        assert_eq!(read_header(&pbkdf2).unwrap().kdf, Some(Kdf::Pbkdf2 { rounds: 1000 }));
+    }
+
+    #[test]
+    fn kdf_parameters_are_bounded() {
+        assert!(fast_theater().with_kdf(Kdf::Pbkdf2 { rounds: 0 }).is_err());
+        assert!(fast_theater().with_kdf(Kdf::Argon2id { mem_kib: 1, iters: 1, parallelism: 1 }).is_err());
+
+        // A crafted header can't demand 4 GiB of memory
+        let mut header = Vec::new();
+        Kdf::Argon2id { mem_kib: u32::MAX, iters: 1, parallelism: 1 }.write(&mut header);
+        assert!(matches!(Kdf::read(&header), Err(TheaterError::InvalidKdf(_))));
+        assert!(matches!(Kdf::read(&header[..5]), Err(TheaterError::BadMagic)));
+    }
+
+    #[cfg(feature = "aes")]
+    #[tokio::test]
+    async fn aes_gcm_round_trips() {
+        use crate::cipher::{Aes256GcmCipher, AES_256_GCM_ID};
+
+        let blob = round_trip(fast_theater().with_cipher(Aes256GcmCipher)).await;
+        assert_eq!(blob[6], AES_256_GCM_ID);
+
+        // The header picks the cipher, whatever the decrypting theater seals with
+        assert_eq!(fast_theater().decrypt_auto(3, &blob, None).unwrap(), b"cipher agnostic");
+        let mut mislabelled = blob.clone();
+        mislabelled[6] = crate::cipher::CHACHA20_POLY1305_ID;
+        assert!(fast_theater().decrypt_auto(3, &mislabelled, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn wrong_magic_is_rejected() {
+        let mut theater = fast_theater();
+        let mut blob = theater
+            .encrypt_with_drama(1, b"hello", EncryptionLevel::Basic, None)
+            .await
+            .unwrap()
+            .ciphertext;
+        assert_eq!(&blob[..8], b"GNGL\x03\x00\x00\x00");
+        let password = theater.generate_theatrical_password(1, &EncryptionLevel::Basic);
+
+        blob[0] = b'X';
+        let err = theater.basic_decrypt(&blob, &password, &1u64.to_le_bytes()).unwrap_err();
+        assert!(matches!(err, TheaterError::BadMagic));
+
+        blob[0] = b'G';
+        blob[4] = 9;
+        let err = theater.basic_decrypt(&blob, &password, &1u64.to_le_bytes()).unwrap_err();
+        assert!(matches!(err, TheaterError::UnsupportedVersion(9)));
+    }
+
+    #[test]
+    fn appended_records_decrypt_independently() {
+        let mut theater = fast_theater();
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("audit.log");
+
+        let entries: [&[u8]; 3] = [b"first", b"second entry", b"\x00\xffthird"];
+        for entry in entries {
+            theater.append_encrypted(&log, entry, "log-password").unwrap();
+        }
+
+        let records = theater.decrypt_concatenated(&log, "log-password").unwrap();
+        assert_eq!(records, entries.iter().map(|e| e.to_vec()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn generated_opponents_are_distinct_and_moving() {
+        let mut theater = DataTheater::new();
+        let opponents = theater.generate_opponents(3);
+        assert_eq!(opponents.len(), 3);
+
+        let names: std::collections::HashSet<&str> =
+            opponents.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names.len(), 3);
+        assert!(opponents.iter().all(|o| o.encryption_speed > 0.0));
+    }
+
+    #[test]
+    fn derive_key_is_stable() {
+        let key = derive_key("user_1_password123", &[7u8; 32], 1000).unwrap();
+        assert_eq!(
+            hex::encode(&key.0),
+            "34e56f6cb3abcdb000a050673040c305bfc2c840816c647bd34992caa2dd71ed"
+        );
+    }
+
+    #[test]
+    fn derived_key_is_wiped_on_drop() {
+        let mut key = std::mem::ManuallyDrop::new(derive_key("hunter2", &[1u8; 32], 1000).unwrap());
+        assert_ne!(key.0, [0u8; 32]);
+
+        // Run the destructor but keep the storage around so it can be inspected
+        unsafe { std::ptr::drop_in_place(&mut *key) };
+        assert_eq!(key.0, [0u8; 32]);
+    }
+}
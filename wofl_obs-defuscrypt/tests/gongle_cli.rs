@@ -0,0 +1,49 @@
+// End-to-end runs of the gongle binary
+#![cfg(feature = "web-api")]
+
+use assert_cmd::Command;
+
+fn gongle() -> Command {
+    let mut cmd = Command::cargo_bin("gongle").unwrap();
+    cmd.args(["--no-drama", "--pbkdf2-rounds", "1000"]).env_remove("GONGLE_PASSWORD");
+    cmd
+}
+
+#[test]
+fn encrypt_then_decrypt_a_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let plain = dir.path().join("file.txt");
+    let sealed = dir.path().join("file.gngl");
+    let opened = dir.path().join("file.out");
+    std::fs::write(&plain, b"the cake is a lie\n").unwrap();
+
+    gongle()
+        .args(["encrypt", "--level", "eldritch", "--password-stdin"])
+        .arg("--in")
+        .arg(&plain)
+        .arg("--out")
+        .arg(&sealed)
+        .write_stdin("hunter2\n")
+        .assert()
+        .success();
+    assert_ne!(std::fs::read(&sealed).unwrap(), std::fs::read(&plain).unwrap());
+
+    gongle()
+        .args(["decrypt", "--in"])
+        .arg(&sealed)
+        .arg("--out")
+        .arg(&opened)
+        .env("GONGLE_PASSWORD", "hunter2")
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&opened).unwrap(), b"the cake is a lie\n");
+
+    // No password anywhere is an error, not an empty password
+    gongle()
+        .args(["decrypt", "--in"])
+        .arg(&sealed)
+        .arg("--out")
+        .arg(dir.path().join("nope.out"))
+        .assert()
+        .failure();
+}